@@ -0,0 +1,372 @@
+/* ----- CDDL HEADER -----
+ *
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.1 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * https://spdx.org/licenses/CDDL-1.1.html
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ *
+ * ----- CDDL HEADER END -----
+ */
+
+// DOS stored everything above 0x7F in the code page the machine was set up
+// with, so the same byte is a different character in Germany and in Norway.
+// WordStar didn't keep track of that, so the user has to tell us (or we use
+// the one most machines had). WordStar for Windows used Windows-1252. If
+// they don't know either, we can guess: the right code page turns the
+// extended characters into letters which are common in European languages,
+// the wrong ones into Greek, math and line drawings in the middle of words.
+// Printers could have their own character sets though, so the user can map
+// single bytes to whatever they printed on top of that. Bytes which are in
+// neither (Windows-1252 has a few holes) are up to the user as well.
+
+use std::{collections::HashMap, fmt};
+
+/// The code pages of WordStar files.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum CodePage {
+    /// US and the default of most machines.
+    #[default]
+    Cp437,
+    /// Western Europe.
+    Cp850,
+    /// Nordic.
+    Cp865,
+    /// WordStar for Windows.
+    Windows1252,
+}
+
+impl CodePage {
+    /// Returns the code page called `name`, if we know it.
+    pub fn from_name(name: &str) -> Option<CodePage> {
+        match name.to_lowercase().as_str() {
+            "cp437" | "437" => Some(CodePage::Cp437),
+            "cp850" | "850" => Some(CodePage::Cp850),
+            "cp865" | "865" => Some(CodePage::Cp865),
+            "windows-1252" | "cp1252" | "1252" => Some(CodePage::Windows1252),
+            _ => None,
+        }
+    }
+
+    /// Returns the byte which stands for `character`, if there is one.
+    pub fn encode(self, character: char) -> Option<u8> {
+        match character.is_ascii() {
+            true => Some(character as u8),
+            false => (0x80..=0xff).find(|&byte| self.decode(byte) == Some(character)),
+        }
+    }
+
+    /// Returns the character `byte` stands for, if there is one.
+    pub fn decode(self, byte: u8) -> Option<char> {
+        let table = match self {
+            CodePage::Cp437 => &CP437,
+            CodePage::Cp850 => &CP850,
+            CodePage::Cp865 => &CP865,
+            CodePage::Windows1252 => &WINDOWS_1252,
+        };
+        match byte {
+            // The holes are control codes in the tables.
+            0x80..=0xff => Some(table[usize::from(byte - 0x80)]).filter(|c| !c.is_control()),
+            _ => Some(char::from(byte)),
+        }
+    }
+}
+
+/// What to do with bytes which aren't characters.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Undecodable {
+    /// Write U+FFFD instead.
+    #[default]
+    Replace,
+    /// Drop them.
+    Drop,
+    /// Write them as \xNN.
+    Escape,
+    /// Stop with an error (before decoding anything).
+    Error,
+}
+
+/// What the user maps single bytes to instead of their code page characters.
+pub type CharacterMap = HashMap<u8, String>;
+
+/// Decodes bytes from a code page and the user's character map.
+pub struct Decoder<'a> {
+    pub code_page: CodePage,
+    pub charmap: &'a CharacterMap,
+    pub undecodable: Undecodable,
+}
+
+impl Decoder<'_> {
+    /// Whether `byte` is a character.
+    pub fn knows(&self, byte: u8) -> bool {
+        self.charmap.contains_key(&byte) || self.code_page.decode(byte).is_some()
+    }
+
+    /// Returns the text `byte` stands for.
+    pub fn decode(&self, byte: u8) -> String {
+        if let Some(text) = self.charmap.get(&byte) {
+            return text.clone();
+        }
+        match (self.code_page.decode(byte), self.undecodable) {
+            (Some(character), _) => character.to_string(),
+            (None, Undecodable::Drop) => String::new(),
+            (None, Undecodable::Escape) => format!("\\x{:02X}", byte),
+            (None, Undecodable::Replace | Undecodable::Error) => '\u{fffd}'.to_string(),
+        }
+    }
+
+    /// Returns the text `bytes` stand for.
+    pub fn decode_all(&self, bytes: &[u8]) -> String {
+        bytes.iter().map(|&byte| self.decode(byte)).collect()
+    }
+}
+
+impl fmt::Display for CodePage {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(match self {
+            CodePage::Cp437 => "cp437",
+            CodePage::Cp850 => "cp850",
+            CodePage::Cp865 => "cp865",
+            CodePage::Windows1252 => "windows-1252",
+        })
+    }
+}
+
+/// How often characters above 0x7F appear in (Western and Northern) European
+/// text, roughly in per mille of the letters. Everything else counts as a
+/// wrong guess.
+const CHARACTER_FREQUENCIES: &[(char, f64)] = &[
+    ('é', 20.0),
+    ('ä', 6.0),
+    ('ü', 6.0),
+    ('ö', 5.0),
+    ('å', 5.0),
+    ('à', 5.0),
+    ('è', 5.0),
+    ('ø', 4.0),
+    ('á', 4.0),
+    ('í', 4.0),
+    ('ó', 4.0),
+    ('ß', 3.0),
+    ('ñ', 3.0),
+    ('æ', 2.0),
+    ('ç', 2.0),
+    ('ê', 2.0),
+    ('ú', 2.0),
+    ('â', 1.0),
+    ('ô', 1.0),
+    ('î', 1.0),
+    ('û', 1.0),
+    ('ë', 1.0),
+    ('ï', 1.0),
+    ('ì', 1.0),
+    ('ò', 1.0),
+    ('ù', 1.0),
+    ('ã', 1.0),
+    ('õ', 1.0),
+    ('É', 1.0),
+    ('Ä', 1.0),
+    ('Ö', 1.0),
+    ('Ü', 1.0),
+    ('Å', 1.0),
+    ('Ø', 1.0),
+    ('Æ', 0.5),
+    ('Ç', 0.5),
+    ('Ñ', 0.5),
+    ('À', 0.5),
+    ('ÿ', 0.1),
+    ('\u{201c}', 3.0),
+    ('\u{201d}', 3.0),
+    ('\u{2018}', 3.0),
+    ('\u{2019}', 3.0),
+    ('\u{2013}', 2.0),
+    ('\u{2014}', 2.0),
+    ('\u{2026}', 1.0),
+    ('«', 1.0),
+    ('»', 1.0),
+    ('¿', 1.0),
+    ('¡', 1.0),
+    ('§', 0.5),
+    ('°', 0.5),
+    ('£', 0.5),
+    ('€', 0.5),
+    ('½', 0.5),
+];
+
+/// How much a character nobody would type counts against a code page.
+const WRONG_CHARACTER_SCORE: f64 = -2.0;
+
+/// How much a line drawing counts. Tables and frames were common, but only
+/// in DOS.
+const DRAWING_SCORE: f64 = 0.5;
+
+/// How likely `character` is in a document.
+fn character_score(character: char) -> f64 {
+    match CHARACTER_FREQUENCIES
+        .iter()
+        .find(|(frequent_character, _)| *frequent_character == character)
+    {
+        Some((_, frequency)) => *frequency,
+        None if ('\u{2500}'..='\u{259f}').contains(&character) => DRAWING_SCORE,
+        None => WRONG_CHARACTER_SCORE,
+    }
+}
+
+/// Returns the one of `candidates` which makes the most sense of the
+/// extended characters in `samples`, the first one if there is a tie (or no
+/// samples).
+pub fn detect(samples: &[u8], candidates: &[CodePage]) -> CodePage {
+    let mut best = (candidates[0], f64::MIN);
+    for &code_page in candidates {
+        let score: f64 = samples
+            .iter()
+            .map(|&byte| {
+                code_page
+                    .decode(byte)
+                    .map_or(WRONG_CHARACTER_SCORE, character_score)
+            })
+            .sum();
+        if score > best.1 {
+            best = (code_page, score);
+        }
+    }
+    best.0
+}
+
+/// Code page 437, 0x80 to 0xFF.
+const CP437: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// Code page 850, 0x80 to 0xFF.
+const CP850: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', 'ø', '£', 'Ø', '×', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '®', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', 'Á', 'Â', 'À', '©', '╣', '║', '╗', '╝', '¢', '¥', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', 'ã', 'Ã', '╚', '╔', '╩', '╦', '╠', '═', '╬', '¤', //
+    'ð', 'Ð', 'Ê', 'Ë', 'È', 'ı', 'Í', 'Î', 'Ï', '┘', '┌', '█', '▄', '¦', 'Ì', '▀', //
+    'Ó', 'ß', 'Ô', 'Ò', 'õ', 'Õ', 'µ', 'þ', 'Þ', 'Ú', 'Û', 'Ù', 'ý', 'Ý', '¯', '´', //
+    '\u{ad}', '±', '‗', '¾', '¶', '§', '÷', '¸', '°', '¨', '·', '¹', '³', '²', '■', '\u{a0}',
+];
+
+/// Code page 865, 0x80 to 0xFF.
+const CP865: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', 'ø', '£', 'Ø', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '¤', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// Windows-1252, 0x80 to 0xFF. The bytes it doesn't define stay control
+/// codes.
+const WINDOWS_1252: [char; 128] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž',
+    '\u{8f}', //
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}', 'ž',
+    'Ÿ', //
+    '\u{a0}', '¡', '¢', '£', '¤', '¥', '¦', '§', '¨', '©', 'ª', '«', '¬', '\u{ad}', '®',
+    '¯', //
+    '°', '±', '²', '³', '´', 'µ', '¶', '·', '¸', '¹', 'º', '»', '¼', '½', '¾', '¿', //
+    'À', 'Á', 'Â', 'Ã', 'Ä', 'Å', 'Æ', 'Ç', 'È', 'É', 'Ê', 'Ë', 'Ì', 'Í', 'Î', 'Ï', //
+    'Ð', 'Ñ', 'Ò', 'Ó', 'Ô', 'Õ', 'Ö', '×', 'Ø', 'Ù', 'Ú', 'Û', 'Ü', 'Ý', 'Þ', 'ß', //
+    'à', 'á', 'â', 'ã', 'ä', 'å', 'æ', 'ç', 'è', 'é', 'ê', 'ë', 'ì', 'í', 'î', 'ï', //
+    'ð', 'ñ', 'ò', 'ó', 'ô', 'õ', 'ö', '÷', 'ø', 'ù', 'ú', 'û', 'ü', 'ý', 'þ', 'ÿ',
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dos_code_pages() {
+        assert_eq!(CodePage::Cp437.decode(0x81), Some('ü'));
+        // They differ in some characters.
+        assert_eq!(CodePage::Cp437.decode(0x9b), Some('¢'));
+        assert_eq!(CodePage::Cp850.decode(0x9b), Some('ø'));
+        assert_eq!(CodePage::Cp865.decode(0xaf), Some('¤'));
+        assert_eq!(CodePage::Cp437.decode(b'a'), Some('a'));
+    }
+
+    #[test]
+    fn windows_1252() {
+        assert_eq!(CodePage::Windows1252.decode(0xe9), Some('é'));
+        assert_eq!(CodePage::Windows1252.decode(0x80), Some('€'));
+        assert_eq!(CodePage::Windows1252.decode(0x93), Some('“'));
+    }
+
+    #[test]
+    fn detection() {
+        let candidates = [CodePage::Cp437, CodePage::Windows1252];
+        // äöü in CP437
+        assert_eq!(
+            detect(&[0x84, 0x94, 0x81], &candidates).to_string(),
+            "cp437"
+        );
+        // éä in Windows-1252
+        assert_eq!(
+            detect(&[0xe9, 0xe4], &candidates).to_string(),
+            "windows-1252"
+        );
+        assert_eq!(detect(&[], &candidates).to_string(), "cp437");
+    }
+
+    #[test]
+    fn character_map_comes_first() {
+        let charmap = CharacterMap::from([(0x81, String::from("ue"))]);
+        let decoder = Decoder {
+            code_page: CodePage::Cp437,
+            charmap: &charmap,
+            undecodable: Undecodable::default(),
+        };
+        assert_eq!(decoder.decode_all(b"M\x81ller \x84"), "Mueller ä");
+    }
+
+    #[test]
+    fn undecodable_bytes() {
+        let charmap = CharacterMap::new();
+        for (undecodable, text) in [
+            (Undecodable::Replace, "a\u{fffd}b"),
+            (Undecodable::Drop, "ab"),
+            (Undecodable::Escape, "a\\x81b"),
+        ] {
+            let decoder = Decoder {
+                code_page: CodePage::Windows1252,
+                charmap: &charmap,
+                undecodable,
+            };
+            assert!(!decoder.knows(0x81));
+            assert_eq!(decoder.decode_all(b"a\x81b"), text);
+        }
+    }
+
+    #[test]
+    fn encoding() {
+        assert_eq!(
+            CodePage::from_name("CP850").map(|code_page| code_page.to_string()),
+            Some(String::from("cp850"))
+        );
+        assert!(CodePage::from_name("utf-8").is_none());
+        assert_eq!(CodePage::Cp850.encode('ø'), Some(0x9b));
+        assert_eq!(CodePage::Cp437.encode('ø'), None);
+        assert_eq!(CodePage::Windows1252.encode('a'), Some(b'a'));
+    }
+}
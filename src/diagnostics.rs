@@ -0,0 +1,120 @@
+/* ----- CDDL HEADER -----
+ *
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.1 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * https://spdx.org/licenses/CDDL-1.1.html
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ *
+ * ----- CDDL HEADER END -----
+ */
+
+// Warnings and errors in a form other programs can read: one JSON object per
+// line, so that logs of many conversions can simply be appended to each other.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    /// Nothing's wrong, but the user asked.
+    Note,
+    Warning,
+    Error,
+}
+
+/// Something to tell the user about a file.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// What kind of problem this is, e.g. "unclosed-modifier".
+    pub code: &'static str,
+    pub path: PathBuf,
+    pub message: String,
+    /// Where it is in the file, if that's known.
+    pub offset: Option<usize>,
+    pub line: Option<usize>,
+}
+
+impl Diagnostic {
+    pub fn warning(path: &Path, code: &'static str, message: String) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            code,
+            path: path.to_path_buf(),
+            message,
+            offset: None,
+            line: None,
+        }
+    }
+
+    pub fn at_line(mut self, line: usize) -> Diagnostic {
+        self.line = Some(line);
+        self
+    }
+
+    pub fn at_offset(mut self, offset: usize) -> Diagnostic {
+        self.offset = Some(offset);
+        self
+    }
+
+    /// The diagnostic as a JSON object on a single line.
+    pub fn to_json(&self) -> String {
+        let number =
+            |value: Option<usize>| value.map_or("null".to_string(), |value| value.to_string());
+        format!(
+            "{{\"code\":{},\"severity\":{},\"file\":{},\"message\":{},\"offset\":{},\"line\":{}}}",
+            json_string(self.code),
+            json_string(match self.severity {
+                Severity::Note => "note",
+                Severity::Warning => "warning",
+                Severity::Error => "error",
+            }),
+            json_string(&self.path.display().to_string()),
+            json_string(&self.message),
+            number(self.offset),
+            number(self.line)
+        )
+    }
+}
+
+// The way people read it.
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: ", self.path.display())?;
+        if let Some(line) = self.line {
+            write!(f, "line {}: ", line)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+/// `text` as a JSON string. There's no serde_json here, and what we write
+/// as JSON is simple enough.
+pub fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for character in text.chars() {
+        match character {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            character if character.is_control() => {
+                json.push_str(&format!("\\u{:04x}", character as u32))
+            }
+            character => json.push(character),
+        }
+    }
+    json.push('"');
+    json
+}
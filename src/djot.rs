@@ -0,0 +1,284 @@
+/* ----- CDDL HEADER -----
+ *
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.1 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * https://spdx.org/licenses/CDDL-1.1.html
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ *
+ * ----- CDDL HEADER END -----
+ */
+
+// Djot output. Djot has underline (as insertion), superscript, subscript
+// and spans of its own, so less of the WordStar formatting ends up as HTML.
+// It has no front matter, which is kept in a comment.
+
+use crate::document::{attribute, unquote, Block, Document, Inline};
+
+/// Writes `document` in Djot's syntax.
+pub fn render(document: &Document) -> String {
+    let mut djot = String::new();
+    if !document.metadata.is_empty() {
+        djot.push_str("{%\n");
+        for (key, value) in &document.metadata {
+            djot.push_str(&format!(
+                "{}: {}\n",
+                key,
+                unquote(value).replace('%', "\\%")
+            ));
+        }
+        djot.push_str("%}\n\n");
+    }
+    djot.push_str(&blocks(&document.blocks));
+    djot.push('\n');
+    djot
+}
+
+// Blank lines are all the same in Djot.
+fn blocks(blocks: &[Block]) -> String {
+    let rendered: Vec<String> = blocks
+        .iter()
+        .filter(|block| !matches!(block, Block::Space(_)))
+        .map(block)
+        .collect();
+    rendered.join("\n\n")
+}
+
+// Indents all but the first line of `text`.
+fn indent(text: &str, width: usize) -> String {
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| match index {
+            _ if index == 0 || line.is_empty() => line.to_string(),
+            _ => format!("{}{}", " ".repeat(width), line),
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+// Our attributes, HTML (align="center") or Pandoc's ({.center}), the way
+// Djot writes them.
+fn attributes(attributes: &str) -> String {
+    if attributes.starts_with('{') {
+        return attributes.to_string();
+    }
+    if !attributes.contains('=') {
+        // ::: center
+        return format!("{{.{}}}", attributes);
+    }
+    if let Some(alignment) = attribute(attributes, "align") {
+        return format!("{{.{}}}", alignment);
+    }
+    format!("{{{}}}", attributes)
+}
+
+// Text which starts like a block would be one.
+fn escape_block_start(text: String) -> String {
+    let digits = text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    if text.starts_with(['#', '>', '-', '+', ':', '|']) {
+        format!("\\{}", text)
+    } else if digits > 0 && text[digits..].starts_with(['.', ')']) {
+        format!("{}\\{}", &text[..digits], &text[digits..])
+    } else {
+        text
+    }
+}
+
+fn raw_html(html: &str) -> String {
+    format!("``` =html\n{}\n```", html)
+}
+
+fn block(block: &Block) -> String {
+    match block {
+        Block::Heading { level, content } => {
+            format!("{} {}", "#".repeat(*level), inlines(content))
+        }
+        Block::Paragraph(content) => escape_block_start(inlines(content)),
+        Block::Code { language, text } => {
+            format!("``` {}\n{}\n```", language, text).replacen("``` \n", "```\n", 1)
+        }
+        Block::Quote(content) => blocks(content)
+            .lines()
+            .map(|line| format!("> {}", line).trim_end().to_string())
+            .collect::<Vec<String>>()
+            .join("\n"),
+        Block::List {
+            ordered,
+            start,
+            items,
+        } => {
+            // Items with more than a paragraph need space between them.
+            let loose = items.iter().any(|item| item.len() > 1);
+            items
+                .iter()
+                .enumerate()
+                .map(|(index, item)| {
+                    let marker = if *ordered {
+                        format!("{}. ", start + index)
+                    } else {
+                        "- ".to_string()
+                    };
+                    format!("{}{}", marker, indent(&blocks(item), marker.len()))
+                })
+                .collect::<Vec<String>>()
+                .join(if loose { "\n\n" } else { "\n" })
+        }
+        Block::Table { header, rows } => {
+            let row = |cells: &Vec<Vec<Inline>>| {
+                let cells: Vec<String> = cells
+                    .iter()
+                    .map(|cell| inlines(cell).replace('|', "\\|"))
+                    .collect();
+                format!("| {} |", cells.join(" | "))
+            };
+            let separator = format!("|{}", "---|".repeat(header.len()));
+            let mut table = vec![row(header), separator];
+            table.extend(rows.iter().map(row));
+            table.join("\n")
+        }
+        Block::Rule => "* * *".to_string(),
+        Block::Div {
+            attributes: div_attributes,
+            content,
+        } => format!(
+            "{}\n:::\n{}\n:::",
+            attributes(div_attributes),
+            blocks(content)
+        ),
+        Block::Comment(comment) => raw_html(&format!("<!-- {} -->", comment)),
+        Block::Template(template) => template.clone(),
+        Block::Space(_) => String::new(),
+    }
+}
+
+fn inlines(inlines: &[Inline]) -> String {
+    inlines.iter().map(inline).collect()
+}
+
+// Djot lets every punctuation character be escaped, these have to be.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        if "\\*_`[]{}^~<$!".contains(character) {
+            escaped.push('\\');
+        }
+        escaped.push(character);
+    }
+    escaped
+}
+
+fn inline(inline: &Inline) -> String {
+    match inline {
+        Inline::Text(text) => escape(text),
+        Inline::Bold(content) => format!("*{}*", inlines(content)),
+        Inline::Italic(content) => format!("_{}_", inlines(content)),
+        Inline::Underline(content) => format!("{{+{}+}}", inlines(content)),
+        Inline::Strikeout(content) => format!("{{-{}-}}", inlines(content)),
+        Inline::Superscript(content) => format!("{{^{}^}}", inlines(content)),
+        Inline::Subscript(content) => format!("{{~{}~}}", inlines(content)),
+        Inline::Code(code) if code.contains('`') => format!("`` {} ``", code),
+        Inline::Code(code) => format!("`{}`", code),
+        Inline::Link { content, target } => format!("[{}]({})", inlines(content), target),
+        Inline::Image {
+            description,
+            source,
+        } => format!("![{}]({})", escape(description), source),
+        Inline::Anchor(name) => format!("[]{{#{}}}", name),
+        Inline::Span {
+            attributes: span_attributes,
+            content,
+        } => format!("[{}]{}", inlines(content), attributes(span_attributes)),
+        Inline::LineBreak => "\\\n".to_string(),
+        Inline::SoftBreak => "\n".to_string(),
+        Inline::Note(content) => format!("[{}]{{.note}}", inlines(content)),
+        Inline::Template(template) => template.clone(),
+        Inline::Html(html) => format!("`{}`{{=html}}", html),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Inline {
+        Inline::Text(text.to_string())
+    }
+
+    fn paragraph(content: Vec<Inline>) -> String {
+        render(&Document {
+            metadata: Vec::new(),
+            blocks: vec![Block::Paragraph(content)],
+            right_margin: None,
+        })
+    }
+
+    #[test]
+    fn formatting() {
+        assert_eq!(
+            paragraph(vec![
+                Inline::Underline(vec![text("under")]),
+                text(" x"),
+                Inline::Superscript(vec![text("2")]),
+                text(" H"),
+                Inline::Subscript(vec![text("2")]),
+                text("O "),
+                Inline::Span {
+                    attributes: String::from("style=\"color:red\""),
+                    content: vec![text("red")],
+                },
+            ]),
+            "{+under+} x{^2^} H{~2~}O [red]{style=\"color:red\"}\n"
+        );
+    }
+
+    #[test]
+    fn raw_html() {
+        assert_eq!(
+            paragraph(vec![
+                Inline::Html(String::from("<big>")),
+                text("big"),
+                Inline::Html(String::from("</big>")),
+            ]),
+            "`<big>`{=html}big`</big>`{=html}\n"
+        );
+        assert_eq!(
+            render(&Document {
+                metadata: Vec::new(),
+                blocks: vec![Block::Comment(String::from("page 2"))],
+                right_margin: None,
+            }),
+            "``` =html\n<!-- page 2 -->\n```\n"
+        );
+    }
+
+    #[test]
+    fn text_is_escaped() {
+        assert_eq!(
+            paragraph(vec![text("# *not* a_heading {x} [y]")]),
+            "\\# \\*not\\* a\\_heading \\{x\\} \\[y\\]\n"
+        );
+        assert_eq!(paragraph(vec![text("1. one")]), "1\\. one\n");
+    }
+
+    #[test]
+    fn paragraphs_may_start_with_formatting() {
+        let options = crate::Options::default();
+        let document = crate::convert(
+            "\u{13}Chapter one\u{13} begins here.\r\nSecond para & more.\r\n",
+            &options,
+            &[std::path::PathBuf::from("test.ws")],
+        )
+        .unwrap();
+        assert_eq!(
+            render(&document),
+            "{+Chapter+} {+one+} begins here.\\\nSecond para & more.\n"
+        );
+    }
+}
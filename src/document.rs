@@ -0,0 +1,649 @@
+/* ----- CDDL HEADER -----
+ *
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.1 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * https://spdx.org/licenses/CDDL-1.1.html
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ *
+ * ----- CDDL HEADER END -----
+ */
+
+// The converted document as blocks and inlines. convert() builds it from the
+// parse tree, line by line (see Builder), and every output format, Markdown
+// included, is written from it.
+
+/// A piece of text in a block.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Inline {
+    Text(String),
+    Bold(Vec<Inline>),
+    Italic(Vec<Inline>),
+    Underline(Vec<Inline>),
+    Strikeout(Vec<Inline>),
+    Superscript(Vec<Inline>),
+    Subscript(Vec<Inline>),
+    Code(String),
+    Link {
+        content: Vec<Inline>,
+        target: String,
+    },
+    Image {
+        description: String,
+        source: String,
+    },
+    /// The target of a link within the document
+    Anchor(String),
+    /// Text with HTML attributes (fonts, colors)
+    Span {
+        attributes: String,
+        content: Vec<Inline>,
+    },
+    /// A line break within the paragraph, and one which is only in the
+    /// source.
+    LineBreak,
+    SoftBreak,
+    /// An endnote, where its number goes.
+    Note(Vec<Inline>),
+    /// A placeholder of the template engine, written as it is.
+    Template(String),
+    /// HTML we don't know any better.
+    Html(String),
+}
+
+/// A paragraph or something else which stands on its own.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Block {
+    Heading {
+        level: usize,
+        content: Vec<Inline>,
+    },
+    Paragraph(Vec<Inline>),
+    Code {
+        language: String,
+        text: String,
+    },
+    Quote(Vec<Block>),
+    List {
+        ordered: bool,
+        /// The number of the first item, if it's ordered.
+        start: usize,
+        items: Vec<Vec<Block>>,
+    },
+    Table {
+        header: Vec<Vec<Inline>>,
+        rows: Vec<Vec<Vec<Inline>>>,
+    },
+    /// Horizontal rules, which are also where the pages break.
+    Rule,
+    /// Aligned or indented text, with its HTML attributes or Pandoc classes.
+    Div {
+        attributes: String,
+        content: Vec<Block>,
+    },
+    Comment(String),
+    /// A tag of the template engine (.if, .el, .ei, .ma), written as it is.
+    Template(String),
+    /// Blank lines, where WordStar had them. Only Markdown keeps them.
+    Space(usize),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Document {
+    /// The front matter, values as they are in YAML.
+    pub metadata: Vec<(String, String)>,
+    pub blocks: Vec<Block>,
+    /// The right margin set first (.rm or a ruler), for wrapping plain text.
+    pub right_margin: Option<usize>,
+}
+
+impl Document {
+    /// A value from the front matter, without its YAML quotes.
+    pub fn metadata(&self, key: &str) -> Option<String> {
+        self.metadata
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| unquote(value))
+    }
+
+    /// A setting of the page layout in the front matter ("wordstar:
+    /// {top-margin: 3, ...}") or a value of its own ("right-margin: 65").
+    pub fn layout(&self, key: &str) -> Option<usize> {
+        if let Some(value) = self.metadata(key) {
+            return value.parse().ok();
+        }
+        let layout = self.metadata("wordstar")?;
+        let start = layout.find(&format!("{}: ", key))? + key.len() + 2;
+        let digits: String = layout[start..]
+            .chars()
+            .take_while(char::is_ascii_digit)
+            .collect();
+        digits.parse().ok()
+    }
+}
+
+/// Reads a YAML scalar as we write it (see yaml_quote()).
+pub fn unquote(value: &str) -> String {
+    match value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    {
+        Some(value) => value.replace("\\\"", "\"").replace("\\\\", "\\"),
+        None => value.to_string(),
+    }
+}
+
+/// The text of `inlines`, without any formatting.
+pub fn plain_text(inlines: &[Inline]) -> String {
+    let mut text = String::new();
+    for inline in inlines {
+        match inline {
+            Inline::Text(content) | Inline::Code(content) => text.push_str(content),
+            Inline::Bold(content)
+            | Inline::Italic(content)
+            | Inline::Underline(content)
+            | Inline::Strikeout(content)
+            | Inline::Superscript(content)
+            | Inline::Subscript(content)
+            | Inline::Link { content, .. }
+            | Inline::Span { content, .. } => text.push_str(&plain_text(content)),
+            Inline::Image { description, .. } => text.push_str(description),
+            Inline::Template(template) => text.push_str(template),
+            Inline::LineBreak => text.push('\n'),
+            Inline::SoftBreak => text.push(' '),
+            Inline::Anchor(_) | Inline::Note(_) | Inline::Html(_) => {}
+        }
+    }
+    text
+}
+
+/// The value of the HTML attribute `name` in `attributes`.
+pub fn attribute(attributes: &str, name: &str) -> Option<String> {
+    let start = attributes.find(&format!("{}=\"", name))? + name.len() + 2;
+    let end = attributes[start..].find('"')? + start;
+    Some(attributes[start..end].to_string())
+}
+
+/// Calls `visit` for every inline in `blocks`, and for the ones in it.
+pub fn visit_inlines(blocks: &mut [Block], visit: &mut dyn FnMut(&mut Inline)) {
+    fn visit_all(inlines: &mut [Inline], visit: &mut dyn FnMut(&mut Inline)) {
+        for inline in inlines {
+            visit(inline);
+            match inline {
+                Inline::Bold(content)
+                | Inline::Italic(content)
+                | Inline::Underline(content)
+                | Inline::Strikeout(content)
+                | Inline::Superscript(content)
+                | Inline::Subscript(content)
+                | Inline::Link { content, .. }
+                | Inline::Span { content, .. }
+                | Inline::Note(content) => visit_all(content, visit),
+                _ => {}
+            }
+        }
+    }
+    for block in blocks {
+        match block {
+            Block::Heading { content, .. } | Block::Paragraph(content) => visit_all(content, visit),
+            Block::Quote(content) | Block::Div { content, .. } => visit_inlines(content, visit),
+            Block::List { items, .. } => {
+                for item in items {
+                    visit_inlines(item, visit);
+                }
+            }
+            Block::Table { header, rows } => {
+                for cell in header.iter_mut().chain(rows.iter_mut().flatten()) {
+                    visit_all(cell, visit);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Replaces the notes in `blocks` by numbered links to them and appends
+/// them as a list of their own, for the formats which have no notes.
+pub fn endnotes(blocks: &mut Vec<Block>) {
+    let mut notes = Vec::new();
+    visit_inlines(blocks, &mut |inline| {
+        if let Inline::Note(content) = inline {
+            notes.push(std::mem::take(content));
+            *inline = Inline::Superscript(vec![
+                Inline::Anchor(format!("endnote-ref-{}", notes.len())),
+                Inline::Link {
+                    content: vec![Inline::Text(notes.len().to_string())],
+                    target: format!("#endnote-{}", notes.len()),
+                },
+            ]);
+        }
+    });
+    if notes.is_empty() {
+        return;
+    }
+
+    // Each note links back to where it was.
+    let items = notes
+        .into_iter()
+        .enumerate()
+        .map(|(index, mut content)| {
+            content.insert(0, Inline::Anchor(format!("endnote-{}", index + 1)));
+            content.push(Inline::Text(String::from(" ")));
+            content.push(Inline::Link {
+                content: vec![Inline::Text(String::from("↩"))],
+                target: format!("#endnote-ref-{}", index + 1),
+            });
+            vec![Block::Paragraph(content)]
+        })
+        .collect();
+    blocks.extend([
+        Block::Space(1),
+        Block::Heading {
+            level: 2,
+            content: vec![Inline::Text(String::from("Notes"))],
+        },
+        Block::Space(1),
+        Block::List {
+            ordered: true,
+            start: 1,
+            items,
+        },
+    ]);
+}
+
+/// Blocks which contain others: aligned or indented text.
+#[derive(Clone, PartialEq)]
+pub enum Container {
+    /// A div with these attributes
+    Div(String),
+    /// Quotes nested this deep, or no quote at all for 0
+    Quote(usize),
+}
+
+/// What the next line goes on, if it doesn't start a block of its own.
+#[derive(Clone, Copy, Default, PartialEq)]
+enum Open {
+    #[default]
+    Nothing,
+    Paragraph,
+    Quote,
+    Code,
+}
+
+/// An open container or list, with what's in it so far.
+enum Frame {
+    Container(Container, Vec<Block>),
+    List {
+        ordered: bool,
+        start: usize,
+        items: Vec<Vec<Block>>,
+    },
+}
+
+/// Builds the blocks of a document line by line. Lines go on the paragraph
+/// (or quote, or code block) before them until something else comes or
+/// end() is called. Containers stay open until they're closed, lists until
+/// something else than their items comes.
+#[derive(Default)]
+pub struct Builder {
+    blocks: Vec<Block>,
+    frames: Vec<Frame>,
+    open: Open,
+    /// How often anything was added
+    changes: usize,
+}
+
+impl Builder {
+    // Where blocks go now: into the open container, or the current list item.
+    fn target(&mut self) -> &mut Vec<Block> {
+        match self.frames.last_mut() {
+            None => &mut self.blocks,
+            Some(Frame::Container(_, blocks)) => blocks,
+            Some(Frame::List { items, .. }) => {
+                if items.is_empty() {
+                    items.push(Vec::new());
+                }
+                items.last_mut().unwrap()
+            }
+        }
+    }
+
+    /// Adds a block of its own.
+    pub fn push(&mut self, block: Block) {
+        self.open = Open::Nothing;
+        self.changes += 1;
+        self.target().push(block);
+    }
+
+    /// Adds blank lines. In lists, they only end the paragraph.
+    pub fn space(&mut self, lines: usize) {
+        if lines == 0 {
+            return;
+        }
+        self.open = Open::Nothing;
+        self.changes += 1;
+        if matches!(self.frames.last(), Some(Frame::List { .. })) {
+            return;
+        }
+        let target = self.target();
+        if let Some(Block::Space(space)) = target.last_mut() {
+            *space += lines;
+            return;
+        }
+        target.push(Block::Space(lines));
+    }
+
+    /// Adds a line to the open paragraph, or starts one with it.
+    pub fn paragraph_line(&mut self, content: Vec<Inline>) {
+        self.changes += 1;
+        if self.open == Open::Paragraph {
+            if let Some(Block::Paragraph(paragraph)) = self.target().last_mut() {
+                paragraph.push(Inline::LineBreak);
+                paragraph.extend(content);
+                return;
+            }
+        }
+        self.target().push(Block::Paragraph(content));
+        self.open = Open::Paragraph;
+    }
+
+    /// Adds a line to the open quote, or starts one with it.
+    pub fn quote_line(&mut self, content: Vec<Inline>) {
+        self.changes += 1;
+        if self.open == Open::Quote {
+            if let Some(Block::Quote(quote)) = self.target().last_mut() {
+                if let Some(Block::Paragraph(paragraph)) = quote.last_mut() {
+                    paragraph.push(Inline::LineBreak);
+                    paragraph.extend(content);
+                    return;
+                }
+            }
+        }
+        self.target()
+            .push(Block::Quote(vec![Block::Paragraph(content)]));
+        self.open = Open::Quote;
+    }
+
+    /// Adds a line to the open code block, or starts one with it.
+    pub fn code_line(&mut self, line: &str) {
+        self.changes += 1;
+        if self.open == Open::Code {
+            if let Some(Block::Code { text, .. }) = self.target().last_mut() {
+                text.push('\n');
+                text.push_str(line);
+                return;
+            }
+        }
+        self.target().push(Block::Code {
+            language: String::new(),
+            text: line.to_string(),
+        });
+        self.open = Open::Code;
+    }
+
+    /// Ends the open paragraph, quote or code block.
+    pub fn end(&mut self) {
+        self.open = Open::Nothing;
+    }
+
+    /// Opens a container, which ends the lists.
+    pub fn open(&mut self, container: Container) {
+        self.end_lists();
+        self.changes += 1;
+        self.frames.push(Frame::Container(container, Vec::new()));
+    }
+
+    /// Closes the innermost container, and the lists in it. Containers
+    /// without text are left out.
+    pub fn close(&mut self) {
+        self.end_lists();
+        self.changes += 1;
+        let Some(Frame::Container(container, content)) = self.frames.pop() else {
+            return;
+        };
+        if content.iter().all(|block| matches!(block, Block::Space(_))) {
+            return;
+        }
+        let blocks = match container {
+            Container::Div(attributes) => vec![Block::Div {
+                attributes,
+                content,
+            }],
+            Container::Quote(depth) => {
+                let mut content = content;
+                for _ in 0..depth {
+                    content = vec![Block::Quote(content)];
+                }
+                content
+            }
+        };
+        self.target().extend(blocks);
+    }
+
+    // Closes the innermost list.
+    fn close_list(&mut self) {
+        if let Some(Frame::List {
+            ordered,
+            start,
+            items,
+        }) = self.frames.pop()
+        {
+            self.target().push(Block::List {
+                ordered,
+                start,
+                items,
+            });
+        }
+    }
+
+    /// Starts an item of the list at `depth` (1 for the outermost list),
+    /// opening and closing lists as needed.
+    pub fn list_item(&mut self, depth: usize, ordered: bool, start: usize) {
+        self.open = Open::Nothing;
+        self.changes += 1;
+        loop {
+            let lists = self
+                .frames
+                .iter()
+                .rev()
+                .take_while(|frame| matches!(frame, Frame::List { .. }))
+                .count();
+            match self.frames.last() {
+                _ if lists > depth => self.close_list(),
+                // Bullets and numbers don't go into the same list.
+                Some(Frame::List {
+                    ordered: list_ordered,
+                    ..
+                }) if lists == depth && *list_ordered != ordered => self.close_list(),
+                _ if lists < depth => {
+                    // The list goes into the current item of the one around it.
+                    self.target();
+                    self.frames.push(Frame::List {
+                        ordered,
+                        start,
+                        items: Vec::new(),
+                    });
+                }
+                _ => break,
+            }
+        }
+        if let Some(Frame::List { items, .. }) = self.frames.last_mut() {
+            items.push(Vec::new());
+        }
+    }
+
+    /// Ends the lists, which were open until now.
+    pub fn end_lists(&mut self) {
+        while matches!(self.frames.last(), Some(Frame::List { .. })) {
+            self.open = Open::Nothing;
+            self.close_list();
+        }
+    }
+
+    /// Where the next block goes, counted in the top level blocks.
+    pub fn position(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// How often anything was added so far, to tell whether something was.
+    pub fn changes(&self) -> usize {
+        self.changes
+    }
+
+    /// Closes everything and returns the blocks.
+    pub fn finish(mut self) -> Vec<Block> {
+        while !self.frames.is_empty() {
+            self.end_lists();
+            self.close();
+        }
+        self.blocks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Inline {
+        Inline::Text(text.to_string())
+    }
+
+    fn paragraph(content: &str) -> Block {
+        Block::Paragraph(vec![text(content)])
+    }
+
+    #[test]
+    fn lines_go_on_the_open_paragraph() {
+        let mut builder = Builder::default();
+        builder.paragraph_line(vec![text("one")]);
+        builder.paragraph_line(vec![text("two")]);
+        builder.space(1);
+        builder.space(1);
+        builder.paragraph_line(vec![text("three")]);
+        assert_eq!(
+            builder.finish(),
+            [
+                Block::Paragraph(vec![text("one"), Inline::LineBreak, text("two")]),
+                Block::Space(2),
+                paragraph("three"),
+            ]
+        );
+    }
+
+    #[test]
+    fn containers_nest_and_empty_ones_are_left_out() {
+        let mut builder = Builder::default();
+        builder.open(Container::Div(String::from("align=\"center\"")));
+        builder.open(Container::Quote(2));
+        builder.paragraph_line(vec![text("quoted")]);
+        builder.close();
+        builder.open(Container::Quote(1));
+        builder.close();
+        assert_eq!(
+            builder.finish(),
+            [Block::Div {
+                attributes: String::from("align=\"center\""),
+                content: vec![Block::Quote(vec![Block::Quote(vec![paragraph("quoted")])])],
+            }]
+        );
+    }
+
+    #[test]
+    fn list_items_nest_by_depth() {
+        let mut builder = Builder::default();
+        builder.list_item(1, false, 1);
+        builder.paragraph_line(vec![text("one")]);
+        builder.list_item(2, true, 3);
+        builder.paragraph_line(vec![text("nested")]);
+        builder.space(1);
+        builder.paragraph_line(vec![text("more")]);
+        builder.list_item(1, false, 1);
+        builder.paragraph_line(vec![text("two")]);
+        builder.end_lists();
+        builder.paragraph_line(vec![text("after")]);
+        assert_eq!(
+            builder.finish(),
+            [
+                Block::List {
+                    ordered: false,
+                    start: 1,
+                    items: vec![
+                        vec![
+                            paragraph("one"),
+                            Block::List {
+                                ordered: true,
+                                start: 3,
+                                items: vec![vec![paragraph("nested"), paragraph("more")]],
+                            }
+                        ],
+                        vec![paragraph("two")],
+                    ],
+                },
+                paragraph("after"),
+            ]
+        );
+    }
+
+    #[test]
+    fn endnotes_are_numbered_and_appended() {
+        let mut blocks = vec![Block::Paragraph(vec![
+            text("Text"),
+            Inline::Note(vec![text("A note")]),
+        ])];
+        endnotes(&mut blocks);
+        assert_eq!(
+            blocks[0],
+            Block::Paragraph(vec![
+                text("Text"),
+                Inline::Superscript(vec![
+                    Inline::Anchor(String::from("endnote-ref-1")),
+                    Inline::Link {
+                        content: vec![text("1")],
+                        target: String::from("#endnote-1"),
+                    },
+                ]),
+            ])
+        );
+        assert_eq!(
+            blocks[4],
+            Block::List {
+                ordered: true,
+                start: 1,
+                items: vec![vec![Block::Paragraph(vec![
+                    Inline::Anchor(String::from("endnote-1")),
+                    text("A note"),
+                    text(" "),
+                    Inline::Link {
+                        content: vec![text("↩")],
+                        target: String::from("#endnote-ref-1"),
+                    },
+                ])]],
+            }
+        );
+    }
+
+    #[test]
+    fn layout() {
+        let document = Document {
+            metadata: vec![
+                (String::from("right-margin"), String::from("65")),
+                (
+                    String::from("wordstar"),
+                    String::from("{top-margin: 3, page-length: 66}"),
+                ),
+            ],
+            blocks: Vec::new(),
+            right_margin: None,
+        };
+        assert_eq!(document.layout("right-margin"), Some(65));
+        assert_eq!(document.layout("page-length"), Some(66));
+        assert_eq!(document.layout("bottom-margin"), None);
+    }
+}
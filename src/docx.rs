@@ -0,0 +1,714 @@
+/* ----- CDDL HEADER -----
+ *
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.1 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * https://spdx.org/licenses/CDDL-1.1.html
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ *
+ * ----- CDDL HEADER END -----
+ */
+
+// Word (DOCX) output: headings become Word's heading styles, emphasis run
+// properties, lists numbered paragraphs, rules page breaks. Margins written
+// as non-breaking spaces or <div> padding become paragraph indentation, the
+// page layout from the front matter (--layout-metadata) the page margins.
+
+use crate::document::{attribute, Block, Document, Inline};
+use crate::html::escape;
+use crate::zip;
+
+// WordStar works in columns (1/10 inch) and lines (1/6 inch), Word in twips.
+const COLUMN: usize = 144;
+const LINE: usize = 240;
+// US Letter, with Word's default margins
+const PAGE_WIDTH: usize = 12240;
+const PAGE_HEIGHT: usize = 15840;
+const PAGE_MARGIN: usize = 1440;
+// How far quotes and list levels are indented
+const INDENTATION: usize = 720;
+
+const CONTENT_TYPES: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">
+<Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/>
+<Default Extension="xml" ContentType="application/xml"/>
+<Override PartName="/word/document.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.document.main+xml"/>
+<Override PartName="/word/styles.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.styles+xml"/>
+<Override PartName="/word/numbering.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.numbering+xml"/>
+<Override PartName="/docProps/core.xml" ContentType="application/vnd.openxmlformats-package.core-properties+xml"/>
+</Types>
+"#;
+
+const PACKAGE_RELATIONSHIPS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+<Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="word/document.xml"/>
+<Relationship Id="rId2" Type="http://schemas.openxmlformats.org/package/2006/relationships/metadata/core-properties" Target="docProps/core.xml"/>
+</Relationships>
+"#;
+
+const NAMESPACES: &str = r#"xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships""#;
+
+fn styles() -> String {
+    let mut styles = format!(
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<w:styles {}>
+<w:docDefaults><w:rPrDefault><w:rPr><w:sz w:val="24"/></w:rPr></w:rPrDefault><w:pPrDefault><w:pPr><w:spacing w:after="160"/></w:pPr></w:pPrDefault></w:docDefaults>
+<w:style w:type="paragraph" w:default="1" w:styleId="Normal"><w:name w:val="Normal"/></w:style>
+<w:style w:type="character" w:styleId="Hyperlink"><w:name w:val="Hyperlink"/><w:rPr><w:color w:val="0563C1"/><w:u w:val="single"/></w:rPr></w:style>
+"#,
+        NAMESPACES
+    );
+    for level in 1..=6 {
+        // 20 to 12 points
+        let size = 40 - (level - 1) * 3;
+        styles.push_str(&format!(
+            r#"<w:style w:type="paragraph" w:styleId="Heading{0}"><w:name w:val="heading {0}"/><w:basedOn w:val="Normal"/><w:next w:val="Normal"/><w:pPr><w:keepNext/><w:spacing w:before="240"/><w:outlineLvl w:val="{1}"/></w:pPr><w:rPr><w:b/><w:sz w:val="{2}"/></w:rPr></w:style>
+"#,
+            level,
+            level - 1,
+            size
+        ));
+    }
+    styles.push_str("</w:styles>\n");
+    styles
+}
+
+// One numbering definition for bullets, one for numbers, and an instance
+// for each list, so that numbered lists start at their first number again.
+fn numbering(lists: &[(bool, usize)]) -> String {
+    let mut numbering = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<w:numbering {}>\n",
+        NAMESPACES
+    );
+    for (id, ordered) in [false, true].into_iter().enumerate() {
+        numbering.push_str(&format!("<w:abstractNum w:abstractNumId=\"{}\">", id));
+        for level in 0..9 {
+            let (format, text) = if ordered {
+                ("decimal", format!("%{}.", level + 1))
+            } else {
+                ("bullet", "•".to_string())
+            };
+            numbering.push_str(&format!(
+                "<w:lvl w:ilvl=\"{}\"><w:start w:val=\"1\"/><w:numFmt w:val=\"{}\"/><w:lvlText w:val=\"{}\"/><w:lvlJc w:val=\"left\"/><w:pPr><w:ind w:left=\"{}\" w:hanging=\"360\"/></w:pPr></w:lvl>",
+                level,
+                format,
+                text,
+                INDENTATION * (level + 1)
+            ));
+        }
+        numbering.push_str("</w:abstractNum>\n");
+    }
+    for (index, &(ordered, start)) in lists.iter().enumerate() {
+        numbering.push_str(&format!(
+            "<w:num w:numId=\"{}\"><w:abstractNumId w:val=\"{}\"/>",
+            index + 1,
+            ordered as usize
+        ));
+        if ordered {
+            for level in 0..9 {
+                numbering.push_str(&format!(
+                    "<w:lvlOverride w:ilvl=\"{}\"><w:startOverride w:val=\"{}\"/></w:lvlOverride>",
+                    level,
+                    if level == 0 { start } else { 1 }
+                ));
+            }
+        }
+        numbering.push_str("</w:num>\n");
+    }
+    numbering.push_str("</w:numbering>\n");
+    numbering
+}
+
+fn core_properties(document: &Document) -> String {
+    let title = document
+        .metadata("title")
+        .map(|title| format!("<dc:title>{}</dc:title>", escape(&title)))
+        .unwrap_or_default();
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<cp:coreProperties xmlns:cp="http://schemas.openxmlformats.org/package/2006/metadata/core-properties" xmlns:dc="http://purl.org/dc/elements/1.1/">{}<dc:creator>ws2markdown</dc:creator></cp:coreProperties>
+"#,
+        title
+    )
+}
+
+fn section_properties(document: &Document) -> String {
+    let height = document
+        .layout("page-length")
+        .map_or(PAGE_HEIGHT, |lines| lines * LINE);
+    let top = document
+        .layout("top-margin")
+        .map_or(PAGE_MARGIN, |lines| lines * LINE);
+    let bottom = document
+        .layout("bottom-margin")
+        .map_or(PAGE_MARGIN, |lines| lines * LINE);
+    let left = document
+        .layout("page-offset")
+        .map_or(PAGE_MARGIN, |columns| columns * COLUMN);
+    // The right margin is where the text ends.
+    let right = document
+        .layout("right-margin")
+        .and_then(|columns| PAGE_WIDTH.checked_sub(left + columns * COLUMN))
+        .unwrap_or(PAGE_MARGIN);
+    format!(
+        "<w:sectPr><w:pgSz w:w=\"{}\" w:h=\"{}\"/><w:pgMar w:top=\"{}\" w:right=\"{}\" w:bottom=\"{}\" w:left=\"{}\" w:header=\"720\" w:footer=\"720\" w:gutter=\"0\"/></w:sectPr>",
+        PAGE_WIDTH, height, top, right, bottom, left
+    )
+}
+
+/// How a paragraph is set.
+#[derive(Clone, Default)]
+struct Paragraph {
+    style: Option<String>,
+    indentation: usize,
+    alignment: Option<&'static str>,
+    /// The list it's an item of (numbering instance, level).
+    numbering: Option<(usize, usize)>,
+    list_level: usize,
+    keep_lines: bool,
+}
+
+/// How a run of text is set.
+#[derive(Clone, Default)]
+struct Format {
+    bold: bool,
+    italic: bool,
+    underline: bool,
+    strikeout: bool,
+    vertical: Option<&'static str>,
+    code: bool,
+    link: bool,
+}
+
+#[derive(Default)]
+struct Writer {
+    body: String,
+    /// The targets of external links, as relationships rId3, rId4, ...
+    links: Vec<String>,
+    /// The lists, whether they're numbered and their first numbers.
+    lists: Vec<(bool, usize)>,
+    bookmarks: usize,
+    /// HTML which Word has no place for, to tell the user about.
+    left_out: Vec<String>,
+}
+
+// Word's bookmark names can't have dashes.
+fn bookmark_name(anchor: &str) -> String {
+    anchor.replace('-', "_")
+}
+
+impl Writer {
+    fn blocks(&mut self, blocks: &[Block], paragraph: &Paragraph) {
+        for block in blocks {
+            self.block(block, paragraph);
+        }
+    }
+
+    fn block(&mut self, block: &Block, paragraph: &Paragraph) {
+        // Only the first paragraph of a list item gets its number, not all
+        // of the ones in a quote or a list there.
+        let contained = Paragraph {
+            numbering: None,
+            ..paragraph.clone()
+        };
+        match block {
+            Block::Heading { level, content } => {
+                let heading = Paragraph {
+                    style: Some(format!("Heading{}", level)),
+                    ..paragraph.clone()
+                };
+                self.paragraph(content, &heading);
+            }
+            Block::Paragraph(content) => self.paragraph(content, paragraph),
+            Block::Code { text, .. } => {
+                let mut content = Vec::new();
+                for (index, line) in text.lines().enumerate() {
+                    if index > 0 {
+                        content.push(Inline::LineBreak);
+                    }
+                    content.push(Inline::Code(line.to_string()));
+                }
+                let code = Paragraph {
+                    keep_lines: true,
+                    ..paragraph.clone()
+                };
+                self.paragraph(&content, &code);
+            }
+            Block::Quote(content) => {
+                let quote = Paragraph {
+                    indentation: paragraph.indentation + INDENTATION,
+                    ..contained
+                };
+                self.blocks(content, &quote);
+            }
+            Block::List {
+                ordered,
+                start,
+                items,
+            } => {
+                self.lists.push((*ordered, *start));
+                let id = self.lists.len();
+                let level = paragraph.list_level;
+                let paragraph = &contained;
+                for item in items {
+                    for (index, block) in item.iter().enumerate() {
+                        let item_paragraph = if index == 0 {
+                            Paragraph {
+                                numbering: Some((id, level)),
+                                list_level: level + 1,
+                                ..paragraph.clone()
+                            }
+                        } else {
+                            // The item goes on.
+                            Paragraph {
+                                indentation: INDENTATION * (level + 1),
+                                list_level: level + 1,
+                                ..paragraph.clone()
+                            }
+                        };
+                        self.block(block, &item_paragraph);
+                    }
+                }
+            }
+            Block::Table { header, rows } => self.table(header, rows),
+            Block::Rule => self
+                .body
+                .push_str("<w:p><w:r><w:br w:type=\"page\"/></w:r></w:p>\n"),
+            Block::Div {
+                attributes,
+                content,
+            } => {
+                let mut div = contained;
+                if attributes.contains("center") {
+                    div.alignment = Some("center");
+                } else if attributes.contains("justify") {
+                    div.alignment = Some("both");
+                }
+                // <div style="padding-left:5ch">
+                if let Some(padding) = attribute(attributes, "style").and_then(|style| {
+                    style
+                        .split_once("padding-left:")
+                        .map(|(_, p)| p.to_string())
+                }) {
+                    let columns: String =
+                        padding.chars().take_while(char::is_ascii_digit).collect();
+                    div.indentation += columns.parse().unwrap_or(0) * COLUMN;
+                }
+                self.blocks(content, &div);
+            }
+            Block::Template(template) => {
+                self.paragraph(&[Inline::Text(template.clone())], paragraph)
+            }
+            // Neither has a place in Word.
+            Block::Comment(_) | Block::Space(_) => {}
+        }
+    }
+
+    fn paragraph(&mut self, content: &[Inline], paragraph: &Paragraph) {
+        // Leading non-breaking spaces are the left margin.
+        let mut content = content.to_vec();
+        let mut indentation = paragraph.indentation;
+        if let Some(Inline::Text(text)) = content.first_mut() {
+            let margin = text.chars().take_while(|&c| c == '\u{a0}').count();
+            if margin > 0 {
+                indentation += margin * COLUMN;
+                *text = text.trim_start_matches('\u{a0}').to_string();
+            }
+        }
+
+        self.body.push_str("<w:p><w:pPr>");
+        if let Some(style) = &paragraph.style {
+            self.body
+                .push_str(&format!("<w:pStyle w:val=\"{}\"/>", style));
+        }
+        if paragraph.keep_lines {
+            self.body.push_str("<w:keepLines/>");
+        }
+        if let Some((id, level)) = paragraph.numbering {
+            self.body.push_str(&format!(
+                "<w:numPr><w:ilvl w:val=\"{}\"/><w:numId w:val=\"{}\"/></w:numPr>",
+                level, id
+            ));
+        }
+        if indentation > 0 {
+            self.body
+                .push_str(&format!("<w:ind w:left=\"{}\"/>", indentation));
+        }
+        if let Some(alignment) = paragraph.alignment {
+            self.body
+                .push_str(&format!("<w:jc w:val=\"{}\"/>", alignment));
+        }
+        self.body.push_str("</w:pPr>");
+        self.runs(&content, &Format::default());
+        self.body.push_str("</w:p>\n");
+    }
+
+    fn run(&mut self, text: &str, format: &Format) {
+        // Form feeds left over from page breaks are page breaks.
+        if let Some((before, after)) = text.split_once('\x0c') {
+            self.run(before, format);
+            self.body.push_str("<w:r><w:br w:type=\"page\"/></w:r>");
+            self.run(after, format);
+            return;
+        }
+        if text.is_empty() {
+            return;
+        }
+        let mut properties = String::new();
+        if format.link {
+            properties.push_str("<w:rStyle w:val=\"Hyperlink\"/>");
+        }
+        if format.code {
+            properties.push_str(
+                "<w:rFonts w:ascii=\"Courier New\" w:hAnsi=\"Courier New\" w:cs=\"Courier New\"/>",
+            );
+        }
+        for (on, property) in [
+            (format.bold, "<w:b/>"),
+            (format.italic, "<w:i/>"),
+            (format.strikeout, "<w:strike/>"),
+            (format.underline, "<w:u w:val=\"single\"/>"),
+        ] {
+            if on {
+                properties.push_str(property);
+            }
+        }
+        if let Some(vertical) = format.vertical {
+            properties.push_str(&format!("<w:vertAlign w:val=\"{}\"/>", vertical));
+        }
+        self.body.push_str("<w:r>");
+        if !properties.is_empty() {
+            self.body
+                .push_str(&format!("<w:rPr>{}</w:rPr>", properties));
+        }
+        self.body.push_str(&format!(
+            "<w:t xml:space=\"preserve\">{}</w:t></w:r>",
+            escape(text)
+        ));
+    }
+
+    fn runs(&mut self, content: &[Inline], format: &Format) {
+        for inline in content {
+            match inline {
+                Inline::Text(text) => self.run(text, format),
+                Inline::SoftBreak => self.run(" ", format),
+                Inline::LineBreak => self.body.push_str("<w:r><w:br/></w:r>"),
+                Inline::Bold(content) => self.runs(
+                    content,
+                    &Format {
+                        bold: true,
+                        ..format.clone()
+                    },
+                ),
+                Inline::Italic(content) => self.runs(
+                    content,
+                    &Format {
+                        italic: true,
+                        ..format.clone()
+                    },
+                ),
+                Inline::Underline(content) => self.runs(
+                    content,
+                    &Format {
+                        underline: true,
+                        ..format.clone()
+                    },
+                ),
+                Inline::Strikeout(content) => self.runs(
+                    content,
+                    &Format {
+                        strikeout: true,
+                        ..format.clone()
+                    },
+                ),
+                Inline::Superscript(content) => self.runs(
+                    content,
+                    &Format {
+                        vertical: Some("superscript"),
+                        ..format.clone()
+                    },
+                ),
+                Inline::Subscript(content) => self.runs(
+                    content,
+                    &Format {
+                        vertical: Some("subscript"),
+                        ..format.clone()
+                    },
+                ),
+                Inline::Code(code) => self.run(
+                    code,
+                    &Format {
+                        code: true,
+                        ..format.clone()
+                    },
+                ),
+                Inline::Link { content, target } => {
+                    match target.strip_prefix('#') {
+                        Some(anchor) => self.body.push_str(&format!(
+                            "<w:hyperlink w:anchor=\"{}\">",
+                            escape(&bookmark_name(anchor))
+                        )),
+                        None => {
+                            self.links.push(target.clone());
+                            self.body.push_str(&format!(
+                                "<w:hyperlink r:id=\"rId{}\">",
+                                self.links.len() + 2
+                            ));
+                        }
+                    }
+                    self.runs(
+                        content,
+                        &Format {
+                            link: true,
+                            ..format.clone()
+                        },
+                    );
+                    self.body.push_str("</w:hyperlink>");
+                }
+                // There's no image file to embed, only its description.
+                Inline::Image { description, .. } => self.run(
+                    &format!("[{}]", description),
+                    &Format {
+                        italic: true,
+                        ..format.clone()
+                    },
+                ),
+                Inline::Anchor(name) => {
+                    self.bookmarks += 1;
+                    self.body.push_str(&format!(
+                        "<w:bookmarkStart w:id=\"{0}\" w:name=\"{1}\"/><w:bookmarkEnd w:id=\"{0}\"/>",
+                        self.bookmarks,
+                        escape(&bookmark_name(name))
+                    ));
+                }
+                Inline::Span { content, .. } => self.runs(content, format),
+                // Notes are numbered links by now, see document::endnotes().
+                Inline::Note(content) => self.runs(content, format),
+                Inline::Template(template) => self.run(template, format),
+                // Comments have no place in Word, just like Block::Comment.
+                Inline::Html(html) if html.starts_with("<!--") => {}
+                Inline::Html(html) if html.starts_with("<br") => {
+                    self.body.push_str("<w:r><w:br/></w:r>")
+                }
+                Inline::Html(html) => self.left_out.push(html.clone()),
+            }
+        }
+    }
+
+    fn table(&mut self, header: &[Vec<Inline>], rows: &[Vec<Vec<Inline>>]) {
+        let columns = rows
+            .iter()
+            .map(Vec::len)
+            .chain([header.len()])
+            .max()
+            .unwrap_or(0);
+        self.body
+            .push_str("<w:tbl><w:tblPr><w:tblW w:w=\"0\" w:type=\"auto\"/><w:tblBorders>");
+        for border in ["top", "left", "bottom", "right", "insideH", "insideV"] {
+            self.body.push_str(&format!(
+                "<w:{} w:val=\"single\" w:sz=\"4\" w:space=\"0\" w:color=\"auto\"/>",
+                border
+            ));
+        }
+        self.body.push_str("</w:tblBorders></w:tblPr><w:tblGrid>");
+        for _ in 0..columns {
+            self.body.push_str("<w:gridCol w:w=\"2000\"/>");
+        }
+        self.body.push_str("</w:tblGrid>\n");
+        for (index, row) in std::iter::once(header)
+            .chain(rows.iter().map(Vec::as_slice))
+            .enumerate()
+        {
+            self.body.push_str("<w:tr>");
+            for column in 0..columns {
+                self.body.push_str("<w:tc><w:p>");
+                if let Some(cell) = row.get(column) {
+                    let format = Format {
+                        bold: index == 0,
+                        ..Format::default()
+                    };
+                    self.runs(cell, &format);
+                }
+                self.body.push_str("</w:p></w:tc>");
+            }
+            self.body.push_str("</w:tr>\n");
+        }
+        self.body.push_str("</w:tbl>\n");
+    }
+}
+
+/// Writes `document` as a Word file. Also returns the HTML in it which had to
+/// be left out.
+pub fn render(document: &Document) -> (Vec<u8>, Vec<String>) {
+    let mut writer = Writer::default();
+    writer.blocks(&document.blocks, &Paragraph::default());
+
+    let main_document = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<w:document {}><w:body>\n{}{}</w:body></w:document>\n",
+        NAMESPACES,
+        writer.body,
+        section_properties(document)
+    );
+    let mut relationships = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+<Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles" Target="styles.xml"/>
+<Relationship Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/numbering" Target="numbering.xml"/>
+"#,
+    );
+    for (index, target) in writer.links.iter().enumerate() {
+        relationships.push_str(&format!(
+            "<Relationship Id=\"rId{}\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/hyperlink\" Target=\"{}\" TargetMode=\"External\"/>\n",
+            index + 3,
+            escape(target)
+        ));
+    }
+    relationships.push_str("</Relationships>\n");
+
+    let docx = zip::write(&[
+        ("[Content_Types].xml", CONTENT_TYPES.as_bytes().to_vec()),
+        ("_rels/.rels", PACKAGE_RELATIONSHIPS.as_bytes().to_vec()),
+        ("docProps/core.xml", core_properties(document).into_bytes()),
+        ("word/document.xml", main_document.into_bytes()),
+        ("word/styles.xml", styles().into_bytes()),
+        ("word/numbering.xml", numbering(&writer.lists).into_bytes()),
+        ("word/_rels/document.xml.rels", relationships.into_bytes()),
+    ]);
+    (docx, writer.left_out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Inline {
+        Inline::Text(text.to_string())
+    }
+
+    fn item(content: &str) -> Vec<Block> {
+        vec![Block::Paragraph(vec![text(content)])]
+    }
+
+    fn body(blocks: &[Block]) -> Writer {
+        let mut writer = Writer::default();
+        writer.blocks(blocks, &Paragraph::default());
+        writer
+    }
+
+    #[test]
+    fn headings_and_emphasis() {
+        let blocks = [
+            Block::Heading {
+                level: 1,
+                content: vec![text("Title")],
+            },
+            Block::Space(1),
+            Block::Paragraph(vec![
+                text("Some "),
+                Inline::Bold(vec![text("bold")]),
+                text(" text"),
+            ]),
+        ];
+        assert_eq!(
+            body(&blocks).body,
+            "<w:p><w:pPr><w:pStyle w:val=\"Heading1\"/></w:pPr><w:r><w:t xml:space=\"preserve\">Title</w:t></w:r></w:p>\n\
+             <w:p><w:pPr></w:pPr><w:r><w:t xml:space=\"preserve\">Some </w:t></w:r><w:r><w:rPr><w:b/></w:rPr><w:t xml:space=\"preserve\">bold</w:t></w:r><w:r><w:t xml:space=\"preserve\"> text</w:t></w:r></w:p>\n"
+        );
+    }
+
+    #[test]
+    fn lists_are_numbered_paragraphs() {
+        let blocks = [
+            Block::List {
+                ordered: true,
+                start: 3,
+                items: vec![item("three"), item("four")],
+            },
+            Block::List {
+                ordered: false,
+                start: 1,
+                items: vec![item("five")],
+            },
+        ];
+        let writer = body(&blocks);
+        assert_eq!(writer.lists, [(true, 3), (false, 1)]);
+        assert_eq!(writer.body.matches("<w:numId w:val=\"1\"/>").count(), 2);
+        assert_eq!(writer.body.matches("<w:numId w:val=\"2\"/>").count(), 1);
+        assert!(numbering(&writer.lists).contains(
+            "<w:lvlOverride w:ilvl=\"0\"><w:startOverride w:val=\"3\"/></w:lvlOverride>"
+        ));
+    }
+
+    #[test]
+    fn page_layout() {
+        let document = Document {
+            metadata: vec![
+                (String::from("right-margin"), String::from("65")),
+                (
+                    String::from("wordstar"),
+                    String::from("{page-length: 72, top-margin: 3, page-offset: 5}"),
+                ),
+            ],
+            blocks: Vec::new(),
+            right_margin: None,
+        };
+        assert_eq!(
+            section_properties(&document),
+            "<w:sectPr><w:pgSz w:w=\"12240\" w:h=\"17280\"/><w:pgMar w:top=\"720\" w:right=\"2160\" w:bottom=\"1440\" w:left=\"720\" w:header=\"720\" w:footer=\"720\" w:gutter=\"0\"/></w:sectPr>"
+        );
+    }
+
+    #[test]
+    fn quotes_in_items_are_not_numbered() {
+        let blocks = [Block::List {
+            ordered: true,
+            start: 1,
+            items: vec![vec![Block::Quote(vec![
+                Block::Paragraph(vec![text("one")]),
+                Block::Paragraph(vec![text("two")]),
+            ])]],
+        }];
+        assert_eq!(body(&blocks).body.matches("<w:numPr>").count(), 0);
+    }
+
+    #[test]
+    fn html_is_converted_or_left_out() {
+        let writer = body(&[Block::Paragraph(vec![
+            Inline::Html(String::from("<!-- note -->")),
+            Inline::Html(String::from("<kbd>")),
+            text("Enter"),
+            Inline::Html(String::from("</kbd>")),
+            Inline::Html(String::from("<br>")),
+        ])]);
+        assert_eq!(
+            writer.body,
+            "<w:p><w:pPr></w:pPr><w:r><w:t xml:space=\"preserve\">Enter</w:t></w:r><w:r><w:br/></w:r></w:p>\n"
+        );
+        assert_eq!(writer.left_out, ["<kbd>", "</kbd>"]);
+    }
+
+    #[test]
+    fn paragraphs_may_start_with_formatting() {
+        let options = crate::Options::default();
+        let document = crate::convert(
+            "\u{13}Chapter one\u{13} begins here.\r\nSecond para & more.\r\n",
+            &options,
+            &[std::path::PathBuf::from("test.ws")],
+        )
+        .unwrap();
+        let body = body(&document.blocks).body;
+        assert!(body.starts_with(
+            "<w:p><w:pPr></w:pPr><w:r><w:rPr><w:u w:val=\"single\"/></w:rPr><w:t xml:space=\"preserve\">Chapter</w:t></w:r>"
+        ));
+        assert!(body.ends_with(
+            "<w:r><w:br/></w:r><w:r><w:t xml:space=\"preserve\">Second para &amp; more.</w:t></w:r></w:p>\n"
+        ));
+    }
+}
@@ -0,0 +1,248 @@
+/* ----- CDDL HEADER -----
+ *
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.1 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * https://spdx.org/licenses/CDDL-1.1.html
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ *
+ * ----- CDDL HEADER END -----
+ */
+
+// Dot commands as people typed them: .Pa, .LM   5 and .lm 5 inch. The grammar
+// only knows them as WordStar wrote them, so they're tidied up first. How
+// much is tidied up depends on the conversion mode.
+
+use std::path::Path;
+
+use crate::diagnostics::Diagnostic;
+use crate::ConversionMode;
+
+// What comes after the name of a dot command
+enum Arguments {
+    None,
+    Number,
+    Numbers,
+    /// A number or +n/-n
+    Margin,
+    OnOff,
+    Text,
+}
+
+fn arguments(name: &str) -> Option<Arguments> {
+    match name {
+        "hy" | "el" | "ei" | "kr" | "cb" | "pa" | "pg" | "op" => Some(Arguments::None),
+        "lm" | "pm" | "rm" => Some(Arguments::Margin),
+        "ls" | "mt" | "mb" | "pl" | "po" | "cw" | "lh" | "cp" | "pn" => Some(Arguments::Number),
+        "co" => Some(Arguments::Numbers),
+        "oc" | "oj" | "ps" | "ul" => Some(Arguments::OnOff),
+        "he" | "h1" | "h2" | "h3" | "h4" | "h5" | "fi" | "av" | "df" | "rv" | "ma" | "sv"
+        | "ix" | "tc" | "if" | "rr" | "fo" => Some(Arguments::Text),
+        // .f1 to .f9 are footers, too.
+        _ if name.starts_with('f') && name.as_bytes()[1].is_ascii_digit() => Some(Arguments::Text),
+        _ => None,
+    }
+}
+
+/// Rewrites the dot commands in `text` so the grammar understands them. In
+/// strict mode, only the names are put in lower case. Returns the text and warnings about what
+/// was dropped (in lossy mode) and about lines which only start with a period.
+pub fn normalize(text: &str, mode: &ConversionMode, path: &Path) -> (String, Vec<Diagnostic>) {
+    let mut warnings = Vec::new();
+    let lines: Vec<String> = text
+        .split('\n')
+        .enumerate()
+        .map(|(index, line)| {
+            // The last line can end with ^Z, the others with a carriage return.
+            let content = line.trim_end_matches(['\u{1a}', '\r']);
+            if is_prose(content) {
+                warnings.push(
+                    Diagnostic::warning(
+                        path,
+                        "dot-command-prose",
+                        "starts with a period but isn't a dot command, keeping it as text"
+                            .to_string(),
+                    )
+                    .at_line(index + 1),
+                );
+                return line.to_string();
+            }
+            if *mode == ConversionMode::Strict {
+                return lowercase_name(line).unwrap_or_else(|| line.to_string());
+            }
+            match normalize_line(content) {
+                Some((command, junk)) if junk.is_empty() || *mode == ConversionMode::Lossy => {
+                    if !junk.is_empty() {
+                        warnings.push(
+                            Diagnostic::warning(
+                                path,
+                                "dot-command-junk",
+                                format!("dropping \"{}\" after {}", junk, &command[..3]),
+                            )
+                            .at_line(index + 1),
+                        );
+                    }
+                    command + &line[content.len()..]
+                }
+                _ => line.to_string(),
+            }
+        })
+        .collect();
+    (lines.join("\n"), warnings)
+}
+
+/// Whether `line` only starts with a period, like ".NET was released": a word
+/// longer than a dot command, followed by lowercase text. This is what
+/// dot_prose in the grammar matches.
+pub fn is_prose(line: &str) -> bool {
+    let Some(rest) = line.strip_prefix('.') else {
+        return false;
+    };
+    let word_end = rest
+        .find(|character: char| !character.is_alphabetic())
+        .unwrap_or(rest.len());
+    let text = rest[word_end..].trim_start_matches(' ');
+    rest[..word_end].chars().count() > 2
+        && text.len() < rest.len() - word_end
+        && text.starts_with(char::is_lowercase)
+}
+
+// `line` with the name of its dot command in lower case, if it has one.
+fn lowercase_name(line: &str) -> Option<String> {
+    let name = line.strip_prefix('.')?.get(..2)?.to_ascii_lowercase();
+    arguments(&name)?;
+    Some(format!(".{}{}", name, &line[3..]))
+}
+
+// The dot command in `line` as the grammar wants it, and what's left over.
+fn normalize_line(line: &str) -> Option<(String, String)> {
+    let name = line.strip_prefix('.')?.get(..2)?.to_ascii_lowercase();
+    let rest = &line[3..];
+    let (argument, junk) = match arguments(&name)? {
+        Arguments::Text => return Some((format!(".{}{}", name, rest), String::new())),
+        Arguments::None => (String::new(), rest),
+        Arguments::Number => split_number(rest.trim_start()),
+        Arguments::Margin => {
+            let rest = rest.trim_start();
+            let (sign, number) = match rest.strip_prefix(['+', '-']) {
+                Some(number) => (&rest[..1], number.trim_start()),
+                None => ("", rest),
+            };
+            match split_number(number) {
+                (number, junk) if !number.is_empty() => (format!("{}{}", sign, number), junk),
+                _ => (String::new(), rest),
+            }
+        }
+        Arguments::Numbers => {
+            let (first, rest) = split_number(rest.trim_start());
+            match rest.trim_start().strip_prefix(',') {
+                Some(second) if !first.is_empty() => match split_number(second.trim_start()) {
+                    (second, rest) if !second.is_empty() => (format!("{},{}", first, second), rest),
+                    _ => (first, rest),
+                },
+                _ => (first, rest),
+            }
+        }
+        Arguments::OnOff => {
+            let rest = rest.trim_start();
+            let word_end = rest.find([' ', '\t']).unwrap_or(rest.len());
+            match rest[..word_end].to_ascii_lowercase().as_str() {
+                "on" | "off" => (rest[..word_end].to_string(), &rest[word_end..]),
+                _ => (String::new(), rest),
+            }
+        }
+    };
+    let command = if argument.is_empty() {
+        format!(".{}", name)
+    } else {
+        format!(".{} {}", name, argument)
+    };
+    Some((command, junk.trim().to_string()))
+}
+
+// The digits at the start of `text`, and the rest.
+fn split_number(text: &str) -> (String, &str) {
+    let end = text
+        .find(|character: char| !character.is_ascii_digit())
+        .unwrap_or(text.len());
+    (text[..end].to_string(), &text[end..])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn normalized(text: &str, mode: ConversionMode) -> String {
+        normalize(text, &mode, Path::new("test.ws")).0
+    }
+
+    #[test]
+    fn names_are_lowercased_in_every_mode() {
+        for mode in [
+            ConversionMode::Strict,
+            ConversionMode::Default,
+            ConversionMode::Lossy,
+        ] {
+            assert_eq!(normalized(".LM 5\r\n.PA\r\n", mode), ".lm 5\r\n.pa\r\n");
+        }
+    }
+
+    #[test]
+    fn whitespace_is_only_tidied_up_outside_strict_mode() {
+        assert_eq!(normalized(".LM   5", ConversionMode::Default), ".lm 5");
+        assert_eq!(normalized(".LM   5", ConversionMode::Strict), ".lm   5");
+    }
+
+    #[test]
+    fn junk_is_only_dropped_in_lossy_mode() {
+        assert_eq!(
+            normalized(".lm 5 inch", ConversionMode::Default),
+            ".lm 5 inch"
+        );
+        assert_eq!(normalized(".lm 5 inch", ConversionMode::Lossy), ".lm 5");
+        let (_, warnings) = normalize(".lm 5 inch", &ConversionMode::Lossy, Path::new("test.ws"));
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn text_arguments_are_kept() {
+        assert_eq!(
+            normalized(".HE My Header", ConversionMode::Default),
+            ".he My Header"
+        );
+        assert_eq!(normalized(".Op", ConversionMode::Default), ".op");
+    }
+
+    #[test]
+    fn prose_is_not_a_dot_command() {
+        assert!(is_prose(".NET was released"));
+        assert!(!is_prose(".pa"));
+        assert_eq!(
+            normalized(".NET was released", ConversionMode::Default),
+            ".NET was released"
+        );
+        // Prose starting with the name of a dot command stays text, too.
+        let options = crate::Options::default();
+        let files = [PathBuf::from("test.ws")];
+        for line in [
+            ".iffy weather is coming",
+            ".avocados are green",
+            ".head of the table",
+            ".footnotes come last",
+        ] {
+            let text = format!("{}\r\n", line);
+            let document = crate::convert(&text, &options, &files).unwrap();
+            let converted = crate::markdown::render(&document, &options);
+            assert_eq!(converted, format!("{}\n", line));
+        }
+    }
+}
@@ -0,0 +1,371 @@
+/* ----- CDDL HEADER -----
+ *
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.1 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * https://spdx.org/licenses/CDDL-1.1.html
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ *
+ * ----- CDDL HEADER END -----
+ */
+
+// EPUB output. The document is split into chapters at its top headings and
+// at the page breaks, each chapter is a page of XHTML, the headings make the
+// table of contents and the front matter the book's metadata.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::document::{plain_text, visit_inlines, Block, Document, Inline};
+use crate::html::{self, escape};
+use crate::zip;
+
+const CONTAINER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+<rootfiles>
+<rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+</rootfiles>
+</container>
+"#;
+
+// The front matter which has a place of its own in the book's metadata.
+const DUBLIN_CORE: [(&str, &str); 4] = [
+    ("author", "creator"),
+    ("date", "date"),
+    ("description", "description"),
+    ("publisher", "publisher"),
+];
+
+struct Chapter {
+    title: Option<String>,
+    blocks: Vec<Block>,
+}
+
+fn file_name(index: usize) -> String {
+    format!("chapter-{}.xhtml", index + 1)
+}
+
+// A new chapter starts at each heading of the top level and after each page
+// break; the page breaks themselves are gone then.
+fn chapters(blocks: &[Block]) -> Vec<Chapter> {
+    let top_level = blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::Heading { level, .. } => Some(*level),
+            _ => None,
+        })
+        .min();
+    let mut chapters = vec![Chapter {
+        title: None,
+        blocks: Vec::new(),
+    }];
+    for block in blocks {
+        match block {
+            Block::Rule => chapters.push(Chapter {
+                title: None,
+                blocks: Vec::new(),
+            }),
+            Block::Heading { level, content } if Some(*level) == top_level => {
+                chapters.push(Chapter {
+                    title: Some(plain_text(content).trim().to_string()),
+                    blocks: vec![block.clone()],
+                })
+            }
+            _ => chapters.last_mut().unwrap().blocks.push(block.clone()),
+        }
+    }
+    // Comments and blank lines alone don't make a chapter.
+    chapters.retain(|chapter| {
+        chapter
+            .blocks
+            .iter()
+            .any(|block| !matches!(block, Block::Comment(_) | Block::Space(_)))
+    });
+    chapters
+}
+
+// Links within the document have to say which chapter they go to now. The
+// images aren't in the book, so their descriptions stand in for them.
+fn resolve_links(chapters: &mut [Chapter]) {
+    let mut anchors: Vec<(String, usize)> = Vec::new();
+    for (index, chapter) in chapters.iter_mut().enumerate() {
+        visit_inlines(&mut chapter.blocks, &mut |inline| {
+            if let Inline::Anchor(name) = inline {
+                anchors.push((name.clone(), index));
+            }
+        });
+    }
+    for chapter in chapters.iter_mut() {
+        visit_inlines(&mut chapter.blocks, &mut |inline| match inline {
+            Inline::Link { target, .. } => {
+                if let Some((_, index)) = target
+                    .strip_prefix('#')
+                    .and_then(|anchor| anchors.iter().find(|(name, _)| name == anchor))
+                {
+                    *target = format!("{}{}", file_name(*index), target);
+                }
+            }
+            Inline::Image { description, .. } => {
+                *inline = Inline::Text(format!("[{}]", description));
+            }
+            _ => {}
+        });
+    }
+}
+
+// Days since 1970-01-01 as a date (Howard Hinnant's civil_from_days).
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+// Now, as EPUB wants it: 2024-05-01T12:00:00Z
+fn modified() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64);
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    let time = seconds.rem_euclid(86400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+fn package(document: &Document, title: &str, language: &str, chapters: &[Chapter]) -> String {
+    let mut metadata = format!(
+        "<dc:identifier id=\"id\">urn:ws2markdown:{:08x}</dc:identifier>\n<dc:title>{}</dc:title>\n<dc:language>{}</dc:language>\n",
+        zip::crc32(html::blocks(&document.blocks).as_bytes()),
+        escape(title),
+        escape(language)
+    );
+    for (key, element) in DUBLIN_CORE {
+        if let Some(value) = document.metadata(key) {
+            metadata.push_str(&format!("<dc:{0}>{1}</dc:{0}>\n", element, escape(&value)));
+        }
+    }
+    metadata.push_str(&format!(
+        "<meta property=\"dcterms:modified\">{}</meta>\n",
+        modified()
+    ));
+    // The rest is kept the way EPUB 2 did it.
+    for (key, _) in &document.metadata {
+        if !matches!(key.as_str(), "title" | "lang" | "language")
+            && !DUBLIN_CORE.iter().any(|(name, _)| name == key)
+        {
+            let value = document.metadata(key).unwrap_or_default();
+            metadata.push_str(&format!(
+                "<meta name=\"{}\" content=\"{}\"/>\n",
+                escape(key),
+                escape(&value)
+            ));
+        }
+    }
+
+    let mut manifest = String::from(
+        "<item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n",
+    );
+    let mut spine = String::new();
+    for index in 0..chapters.len() {
+        manifest.push_str(&format!(
+            "<item id=\"chapter-{}\" href=\"{}\" media-type=\"application/xhtml+xml\"/>\n",
+            index + 1,
+            file_name(index)
+        ));
+        spine.push_str(&format!("<itemref idref=\"chapter-{}\"/>\n", index + 1));
+    }
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"id\">\n<metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n{}</metadata>\n<manifest>\n{}</manifest>\n<spine>\n{}</spine>\n</package>\n",
+        metadata, manifest, spine
+    )
+}
+
+// The table of contents: the chapters with a heading, or at least the first.
+fn navigation(title: &str, language: &str, chapters: &[Chapter]) -> String {
+    let mut entries: String = chapters
+        .iter()
+        .enumerate()
+        .filter_map(|(index, chapter)| {
+            let title = chapter.title.as_ref()?;
+            Some(format!(
+                "<li><a href=\"{}\">{}</a></li>\n",
+                file_name(index),
+                escape(title)
+            ))
+        })
+        .collect();
+    if entries.is_empty() {
+        entries = format!(
+            "<li><a href=\"{}\">{}</a></li>\n",
+            file_name(0),
+            escape(title)
+        );
+    }
+    let body = format!(
+        "<nav epub:type=\"toc\" id=\"toc\">\n<h1>{}</h1>\n<ol>\n{}</ol>\n</nav>\n",
+        escape(title),
+        entries
+    );
+    html::page(title, language, html::STYLE, &body)
+}
+
+/// Writes `document` as an e-book. The title is `title` unless the front
+/// matter has one.
+pub fn render(document: &Document, title: &str) -> Vec<u8> {
+    let title = document.metadata("title").unwrap_or(title.to_string());
+    let language = document
+        .metadata("lang")
+        .or(document.metadata("language"))
+        .unwrap_or("en".to_string());
+
+    let mut chapters = chapters(&document.blocks);
+    if chapters.is_empty() {
+        chapters.push(Chapter {
+            title: None,
+            blocks: Vec::new(),
+        });
+    }
+    resolve_links(&mut chapters);
+
+    let mut files = vec![
+        // The mimetype goes first, so that it's found at a fixed offset.
+        ("mimetype".to_string(), b"application/epub+zip".to_vec()),
+        (
+            "META-INF/container.xml".to_string(),
+            CONTAINER.as_bytes().to_vec(),
+        ),
+        (
+            "OEBPS/content.opf".to_string(),
+            package(document, &title, &language, &chapters).into_bytes(),
+        ),
+        (
+            "OEBPS/nav.xhtml".to_string(),
+            navigation(&title, &language, &chapters).into_bytes(),
+        ),
+    ];
+    for (index, chapter) in chapters.iter().enumerate() {
+        let chapter_title = chapter.title.as_ref().unwrap_or(&title);
+        files.push((
+            format!("OEBPS/{}", file_name(index)),
+            html::page(
+                chapter_title,
+                &language,
+                html::STYLE,
+                &html::blocks(&chapter.blocks),
+            )
+            .into_bytes(),
+        ));
+    }
+    zip::write(&files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Inline {
+        Inline::Text(text.to_string())
+    }
+
+    fn heading(level: usize, content: Vec<Inline>) -> Block {
+        Block::Heading { level, content }
+    }
+
+    #[test]
+    fn chapters_start_at_top_headings_and_page_breaks() {
+        let blocks = vec![
+            Block::Paragraph(vec![text("Preface")]),
+            Block::Space(1),
+            heading(2, vec![text("One")]),
+            Block::Paragraph(vec![text("Text")]),
+            Block::Rule,
+            Block::Paragraph(vec![text("More")]),
+            heading(2, vec![text("Two")]),
+            heading(3, vec![text("Part")]),
+            Block::Space(1),
+            Block::Rule,
+            Block::Space(1),
+        ];
+        let titles: Vec<Option<String>> = chapters(&blocks)
+            .into_iter()
+            .map(|chapter| chapter.title)
+            .collect();
+        assert_eq!(
+            titles,
+            [
+                None,
+                Some(String::from("One")),
+                None,
+                Some(String::from("Two"))
+            ]
+        );
+    }
+
+    #[test]
+    fn links_go_to_the_chapter_of_their_anchor() {
+        let blocks = vec![
+            heading(1, vec![text("One")]),
+            Block::Paragraph(vec![
+                text("See "),
+                Inline::Link {
+                    content: vec![text("two")],
+                    target: String::from("#two"),
+                },
+                text("."),
+            ]),
+            heading(1, vec![text("Two"), Inline::Anchor(String::from("two"))]),
+        ];
+        let mut chapters = chapters(&blocks);
+        resolve_links(&mut chapters);
+        let mut targets = Vec::new();
+        visit_inlines(&mut chapters[0].blocks, &mut |inline| {
+            if let Inline::Link { target, .. } = inline {
+                targets.push(target.clone());
+            }
+        });
+        assert_eq!(targets, ["chapter-2.xhtml#two"]);
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11_017), (2000, 3, 1));
+        assert_eq!(civil_from_days(19_844), (2024, 5, 1));
+    }
+
+    #[test]
+    fn paragraphs_may_start_with_formatting() {
+        let options = crate::Options::default();
+        let document = crate::convert(
+            "\u{13}Chapter one\u{13} begins here.\r\nSecond para & more.\r\n",
+            &options,
+            &[std::path::PathBuf::from("test.ws")],
+        )
+        .unwrap();
+        let chapters = chapters(&document.blocks);
+        assert_eq!(
+            html::blocks(&chapters[0].blocks),
+            "<p><u>Chapter</u> <u>one</u> begins here.<br/>\nSecond para &amp; more.</p>\n"
+        );
+    }
+}
@@ -0,0 +1,192 @@
+/* ----- CDDL HEADER -----
+ *
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.1 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * https://spdx.org/licenses/CDDL-1.1.html
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ *
+ * ----- CDDL HEADER END -----
+ */
+
+// Everything that can go wrong while converting a file, with the file it went
+// wrong in. main() hands these to anyhow, which prints them with their causes.
+// Decoding and parsing errors come with an excerpt of where it went wrong, in
+// hex and as text, and a hint what to do about it.
+
+use pest::error::LineColLocation;
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::Rule;
+
+#[derive(Debug, Error)]
+pub enum ConversionError {
+    /// A file could not be read or written.
+    #[error("could not {action} {}", path.display())]
+    Io {
+        path: PathBuf,
+        action: &'static str,
+        source: io::Error,
+    },
+    /// The bytes of a file don't make sense in the code page or as WordStar.
+    #[error("{}: {message} at offset {offset}\n{excerpt}", path.display())]
+    Decode {
+        path: PathBuf,
+        offset: usize,
+        message: String,
+        excerpt: String,
+    },
+    /// The text of a file doesn't make sense to the grammar.
+    #[error("{}:{line}:{column}: invalid WordStar file\n{excerpt}", path.display())]
+    Parse {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        excerpt: String,
+    },
+    /// Something can't be converted as it is (in strict mode).
+    #[error("{diagnostic}")]
+    Render { diagnostic: Diagnostic },
+}
+
+impl ConversionError {
+    /// The error as a diagnostic, without the excerpt.
+    pub fn diagnostic(&self) -> Diagnostic {
+        let (path, code, message) = match self {
+            ConversionError::Io {
+                path,
+                action,
+                source,
+            } => (path, "io", format!("could not {}: {}", action, source)),
+            ConversionError::Decode { path, message, .. } => (path, "decode", message.clone()),
+            ConversionError::Parse { path, .. } => {
+                (path, "parse", "invalid WordStar file".to_string())
+            }
+            ConversionError::Render { diagnostic } => {
+                return Diagnostic {
+                    severity: Severity::Error,
+                    ..diagnostic.clone()
+                }
+            }
+        };
+        let mut diagnostic = Diagnostic {
+            severity: Severity::Error,
+            ..Diagnostic::warning(path, code, message)
+        };
+        match self {
+            ConversionError::Decode { offset, .. } => diagnostic.offset = Some(*offset),
+            ConversionError::Parse { line, .. } => diagnostic.line = Some(*line),
+            _ => {}
+        }
+        diagnostic
+    }
+
+    /// The bytes at `offset` of `content` can't be decoded.
+    pub fn decode(
+        path: &Path,
+        content: &[u8],
+        offset: usize,
+        message: String,
+        hint: &str,
+    ) -> ConversionError {
+        let start = offset.saturating_sub(8);
+        let end = (offset + 8).min(content.len());
+        let mut excerpt = String::from("   |\n");
+        excerpt.push_str(&hex_rows(&content[start..end], offset - start));
+        excerpt.push_str(&format!("   = hint: {}", hint));
+        ConversionError::Decode {
+            path: path.to_path_buf(),
+            offset,
+            message,
+            excerpt,
+        }
+    }
+
+    /// The parser gave up on the text.
+    pub fn parse(path: &Path, error: &pest::error::Error<Rule>) -> ConversionError {
+        let (line, column) = match error.line_col {
+            LineColLocation::Pos(position) | LineColLocation::Span(position, _) => position,
+        };
+        let text = error.line();
+        // pest counts columns in characters.
+        let offset = text
+            .char_indices()
+            .nth(column - 1)
+            .map_or(text.len(), |(offset, _)| offset);
+        let hint = match text[offset..].chars().next() {
+            None => "the text doesn't end with a line break, --recover adds one".to_string(),
+            Some(character) if character.is_control() => format!(
+                "WordStar doesn't use the control code 0x{:02X} here, --recover keeps the text of the line",
+                character as u32
+            ),
+            Some(_) => "--recover keeps the text of the line".to_string(),
+        };
+
+        // The line itself, without whatever a terminal would make of it:
+        let number = line.to_string();
+        let margin = " ".repeat(number.len());
+        let shown: String = text
+            .trim_end_matches('\n')
+            .chars()
+            .map(|character| {
+                if character.is_control() {
+                    '.'
+                } else {
+                    character
+                }
+            })
+            .collect();
+        let mut excerpt = format!("{} |\n{} | {}\n", margin, number, shown);
+        excerpt.push_str(&format!("{} | {}^\n", margin, " ".repeat(column - 1)));
+
+        // ... and its bytes around the column.
+        let bytes = text.as_bytes();
+        let start = offset.saturating_sub(8);
+        let end = (offset + 8).min(bytes.len());
+        excerpt.push_str(&format!("{} |\n", margin));
+        for row in hex_rows(&bytes[start..end], offset - start).lines() {
+            excerpt.push_str(&format!("{} {}\n", margin, &row[3..]));
+        }
+        excerpt.push_str(&format!("{} = hint: {}", margin, hint));
+        ConversionError::Parse {
+            path: path.to_path_buf(),
+            line,
+            column,
+            excerpt,
+        }
+    }
+}
+
+// Bytes in hex, the same bytes as ASCII below them, and a marker below the
+// one at `marked` (which can be just past the end).
+fn hex_rows(bytes: &[u8], marked: usize) -> String {
+    let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    let text: Vec<String> = bytes
+        .iter()
+        .map(|&byte| {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                format!("{} ", byte as char)
+            } else {
+                ". ".to_string()
+            }
+        })
+        .collect();
+    format!(
+        "   | {}\n   | {}\n   | {}^^\n",
+        hex.join(" "),
+        text.join(" ").trim_end(),
+        "   ".repeat(marked)
+    )
+}
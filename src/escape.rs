@@ -0,0 +1,59 @@
+/* ----- CDDL HEADER -----
+ *
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.1 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * https://spdx.org/licenses/CDDL-1.1.html
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ *
+ * ----- CDDL HEADER END -----
+ */
+
+// WordStar text is just text, but some of its characters mean something in
+// Markdown. Those get a backslash.
+
+/// Characters which are Markdown (or HTML) wherever they are.
+const INLINE_CHARACTERS: [char; 7] = ['\\', '`', '*', '_', '[', ']', '<'];
+
+/// Escapes the characters of `text` which would be Markdown anywhere.
+pub fn escape_inline(text: &str) -> String {
+    let mut escaped_text = String::with_capacity(text.len());
+    for character in text.chars() {
+        if INLINE_CHARACTERS.contains(&character) {
+            escaped_text.push('\\');
+        }
+        escaped_text.push(character);
+    }
+    escaped_text
+}
+
+/// Returns where the marker is if a line would start a Markdown block: a
+/// heading, a quote, a list item, a rule.
+pub fn block_marker(line: &str) -> Option<usize> {
+    let indentation = line.len() - line.trim_start().len();
+    let line = &line[indentation..];
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    if line.starts_with(['#', '>', '-', '+', '=']) {
+        Some(indentation)
+    } else if digits > 0 && line[digits..].starts_with(['.', ')']) {
+        Some(indentation + digits)
+    } else {
+        None
+    }
+}
+
+/// Escapes the block marker `line` starts with, if there is one.
+pub fn escape_block_marker(line: &str) -> String {
+    let mut escaped_line = line.to_string();
+    if let Some(position) = block_marker(line) {
+        escaped_line.insert(position, '\\');
+    }
+    escaped_line
+}
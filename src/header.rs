@@ -0,0 +1,180 @@
+/* ----- CDDL HEADER -----
+ *
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.1 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * https://spdx.org/licenses/CDDL-1.1.html
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ *
+ * ----- CDDL HEADER END -----
+ */
+
+// WordStar 5 and later start their files with a header, which is a symmetric
+// sequence itself (see sequences.rs): 0x1D, its length, ..., 0x1D. It's 128
+// bytes long up to WordStar 6, but WordStar 7 can write longer ones. Older
+// versions (and NewWord) don't have a header at all.
+
+use std::fmt;
+
+use crate::sequences;
+
+/// The WordStar versions which write different files.
+#[derive(Debug, PartialEq)]
+pub enum Version {
+    /// WordStar 3 and 4, NewWord: no header, toggles only.
+    WordStar4,
+    /// WordStar 5 and 6: a 128-byte header, symmetric sequences.
+    WordStar5,
+    /// WordStar 7: a longer header.
+    WordStar7,
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(match self {
+            Version::WordStar4 => "WordStar 3 or 4 (or NewWord)",
+            Version::WordStar5 => "WordStar 5 or 6",
+            Version::WordStar7 => "WordStar 7",
+        })
+    }
+}
+
+/// The length of the header of WordStar 5 and 6.
+const DEFAULT_HEADER_LENGTH: usize = 128;
+
+/// How much of the start of a file has to be text if it's not a header.
+const MIN_PRINTABLE_SHARE: f64 = 0.5;
+
+/// Whether `content` starts with the header of WordStar 5 and later.
+pub fn has_header(content: &[u8]) -> bool {
+    header_length(content) > 0
+}
+
+/// Returns how long the header `content` starts with claims to be, if that's
+/// longer than the file: it has been cut off.
+pub fn truncated_length(content: &[u8]) -> Option<usize> {
+    if content.first() != Some(&0x1d) || has_header(content) {
+        return None;
+    }
+    let length = match content.get(1..3) {
+        Some(length_bytes) => {
+            3 + usize::from(u16::from_le_bytes([length_bytes[0], length_bytes[1]]))
+        }
+        None => DEFAULT_HEADER_LENGTH,
+    };
+    Some(length).filter(|&length| length > content.len())
+}
+
+/// Returns which version wrote `content`. We don't know where (or whether)
+/// the header keeps the version, so its length has to do.
+pub fn version(content: &[u8]) -> Version {
+    match header_length(content) {
+        0 => Version::WordStar4,
+        DEFAULT_HEADER_LENGTH => Version::WordStar5,
+        _ => Version::WordStar7,
+    }
+}
+
+/// Returns what we can tell from the header of `content`: the version which
+/// wrote it and the name of the printer description file it was (probably)
+/// formatted for.
+pub fn metadata(content: &[u8]) -> Vec<(&'static str, String)> {
+    let header_length = header_length(content);
+    if header_length == 0 {
+        return Vec::new();
+    }
+    let mut metadata = vec![("version", version(content).to_string())];
+
+    // The printer is a name of up to 8 characters after the sequence type.
+    let printer: String = content[4..12.min(header_length)]
+        .iter()
+        .take_while(|&&byte| byte.is_ascii_graphic() || byte == b' ')
+        .map(|&byte| char::from(byte))
+        .collect();
+    if !printer.trim().is_empty() {
+        metadata.push(("printer", printer.trim().to_string()));
+    }
+    metadata
+}
+
+/// Returns how many bytes at the start of `content` are the file header.
+pub fn header_length(content: &[u8]) -> usize {
+    if let Some((_, _, end)) = sequences::parse_sequence(content, 0) {
+        return end + 1;
+    }
+
+    // A damaged header is still mostly zeroes, text isn't.
+    let start = content.get(..DEFAULT_HEADER_LENGTH).unwrap_or_default();
+    let printable = start
+        .iter()
+        .filter(|&&byte| byte.is_ascii_graphic() || byte.is_ascii_whitespace())
+        .count();
+    if start.len() == DEFAULT_HEADER_LENGTH
+        && (printable as f64) < DEFAULT_HEADER_LENGTH as f64 * MIN_PRINTABLE_SHARE
+        && start.iter().any(|&byte| byte == 0x1d || byte == 0x00)
+    {
+        DEFAULT_HEADER_LENGTH
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A header of `length` bytes which names `printer`.
+    fn header(length: usize, printer: &str) -> Vec<u8> {
+        let length_bytes = (length as u16 - 3).to_le_bytes();
+        let mut header = vec![0x1d, length_bytes[0], length_bytes[1], 0x80];
+        header.extend(printer.bytes());
+        header.resize(length - 3, 0);
+        header.extend([length_bytes[0], length_bytes[1], 0x1d]);
+        header
+    }
+
+    #[test]
+    fn header_lengths() {
+        let mut content = header(128, "");
+        content.extend(b"Text");
+        assert_eq!(header_length(&content), 128);
+        assert_eq!(header_length(&header(300, "")), 300);
+        assert_eq!(header_length(b"Just text"), 0);
+    }
+
+    #[test]
+    fn damaged_headers() {
+        // Mostly zeroes, but not a sequence.
+        let mut content = vec![0x1d, 0, 0];
+        content.resize(200, 0);
+        assert_eq!(header_length(&content), 128);
+        assert_eq!(header_length(&[b'x'; 200]), 0);
+    }
+
+    #[test]
+    fn versions() {
+        assert_eq!(version(&header(128, "")), Version::WordStar5);
+        assert_eq!(version(&header(300, "")), Version::WordStar7);
+        assert_eq!(version(b"Just text"), Version::WordStar4);
+    }
+
+    #[test]
+    fn header_metadata() {
+        assert_eq!(
+            metadata(&header(128, "HPLASER")),
+            [
+                ("version", String::from("WordStar 5 or 6")),
+                ("printer", String::from("HPLASER"))
+            ]
+        );
+        assert_eq!(metadata(&header(300, "")).len(), 1);
+        assert!(metadata(b"Just text").is_empty());
+    }
+}
@@ -0,0 +1,249 @@
+/* ----- CDDL HEADER -----
+ *
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.1 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * https://spdx.org/licenses/CDDL-1.1.html
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ *
+ * ----- CDDL HEADER END -----
+ */
+
+// HTML from the document model, for the HTML, e-book and PDF output. What
+// we write is XHTML, so that EPUB readers take it as it is.
+
+use crate::document::{attribute, Block, Document, Inline};
+
+/// How our HTML looks.
+pub const STYLE: &str = "body { font-family: serif; line-height: 1.4; }
+.center { text-align: center; }
+.justify { text-align: justify; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid; padding: 0.2em 0.5em; }
+";
+
+// WordStar's lines are 1/6 inch, its columns 1/10 inch. The page is US
+// Letter with margins of an inch unless the dot commands say otherwise.
+const LINES_PER_INCH: f64 = 6.0;
+const COLUMNS_PER_INCH: f64 = 10.0;
+const PAGE_WIDTH: f64 = 8.5;
+const PAGE_HEIGHT: f64 = 11.0;
+const PAGE_MARGIN: f64 = 1.0;
+
+/// Escapes text for XML. Control characters can't be written at all.
+pub fn escape(text: &str) -> String {
+    text.replace(|c: char| c.is_control() && !matches!(c, '\t' | '\n'), "")
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// A full page with `body` in it.
+pub fn page(title: &str, language: &str, style: &str, body: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE html>\n<html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" lang=\"{1}\" xml:lang=\"{1}\">\n<head>\n<meta charset=\"UTF-8\"/>\n<title>{0}</title>\n<style>\n{2}</style>\n</head>\n<body>\n{3}</body>\n</html>\n",
+        escape(title),
+        escape(language),
+        style,
+        body
+    )
+}
+
+// The page as it's printed, from the page layout in the front matter.
+fn page_style(document: &Document) -> String {
+    let lines = |key| {
+        document
+            .layout(key)
+            .map(|lines| lines as f64 / LINES_PER_INCH)
+    };
+    let columns = |key| {
+        document
+            .layout(key)
+            .map(|columns| columns as f64 / COLUMNS_PER_INCH)
+    };
+    let left = columns("page-offset").unwrap_or(PAGE_MARGIN);
+    // The right margin is where the text ends.
+    let right = columns("right-margin")
+        .map(|text_width| PAGE_WIDTH - left - text_width)
+        .filter(|&right| right >= 0.0)
+        .unwrap_or(PAGE_MARGIN);
+    format!(
+        "@page {{ size: {}in {:.2}in; margin: {:.2}in {:.2}in {:.2}in {:.2}in; }}\nhr {{ break-after: page; visibility: hidden; }}\n",
+        PAGE_WIDTH,
+        lines("page-length").unwrap_or(PAGE_HEIGHT),
+        lines("top-margin").unwrap_or(PAGE_MARGIN),
+        right,
+        lines("bottom-margin").unwrap_or(PAGE_MARGIN),
+        left
+    )
+}
+
+/// Writes `document` as a web page, which prints like the WordStar document
+/// would. The title is `title` unless the front matter has one.
+pub fn render(document: &Document, title: &str) -> String {
+    let title = document.metadata("title").unwrap_or(title.to_string());
+    let language = document
+        .metadata("lang")
+        .or(document.metadata("language"))
+        .unwrap_or("en".to_string());
+    let style = format!("{}{}", STYLE, page_style(document));
+    page(&title, &language, &style, &blocks(&document.blocks))
+}
+
+// Pandoc's {.center style="..."} as HTML attributes, ours as they are.
+fn attributes(attributes: &str) -> String {
+    let Some(pandoc) = attributes
+        .strip_prefix('{')
+        .and_then(|attributes| attributes.strip_suffix('}'))
+    else {
+        // ::: center
+        if !attributes.contains('=') && !attributes.is_empty() {
+            return format!("class=\"{}\"", escape(attributes));
+        }
+        // align is gone from XHTML 5.
+        return match attribute(attributes, "align") {
+            Some(alignment) => format!("style=\"text-align:{}\"", alignment),
+            None => attributes.to_string(),
+        };
+    };
+    let mut classes = Vec::new();
+    let mut others = Vec::new();
+    for attribute in pandoc.split_whitespace() {
+        match attribute.strip_prefix('.') {
+            Some(class) => classes.push(class),
+            None => others.push(attribute),
+        }
+    }
+    if !classes.is_empty() {
+        others.insert(0, "");
+        return format!("class=\"{}\"{}", classes.join(" "), others.join(" "));
+    }
+    others.join(" ")
+}
+
+/// Writes `blocks` as HTML.
+pub fn blocks(blocks: &[Block]) -> String {
+    blocks.iter().map(block).collect()
+}
+
+fn block(block: &Block) -> String {
+    match block {
+        Block::Heading { level, content } => {
+            format!("<h{0}>{1}</h{0}>\n", level, inlines(content))
+        }
+        Block::Paragraph(content) => format!("<p>{}</p>\n", inlines(content)),
+        Block::Code { language, text } if language.is_empty() => {
+            format!("<pre><code>{}</code></pre>\n", escape(text))
+        }
+        Block::Code { language, text } => format!(
+            "<pre><code class=\"language-{}\">{}</code></pre>\n",
+            escape(language),
+            escape(text)
+        ),
+        Block::Quote(content) => format!("<blockquote>\n{}</blockquote>\n", blocks(content)),
+        Block::List {
+            ordered,
+            start,
+            items,
+        } => {
+            let tag = match (ordered, start) {
+                (true, 1) => String::from("ol"),
+                (true, start) => format!("ol start=\"{}\"", start),
+                (false, _) => String::from("ul"),
+            };
+            let items: String = items
+                .iter()
+                .map(|item| match item.as_slice() {
+                    // Tight lists don't need paragraphs.
+                    [Block::Paragraph(content)] => format!("<li>{}</li>\n", inlines(content)),
+                    _ => format!("<li>\n{}</li>\n", blocks(item)),
+                })
+                .collect();
+            let end_tag = if *ordered { "ol" } else { "ul" };
+            format!("<{}>\n{}</{}>\n", tag, items, end_tag)
+        }
+        Block::Table { header, rows } => {
+            let row = |cells: &Vec<Vec<Inline>>, tag: &str| {
+                let cells: String = cells
+                    .iter()
+                    .map(|cell| format!("<{0}>{1}</{0}>", tag, inlines(cell)))
+                    .collect();
+                format!("<tr>{}</tr>\n", cells)
+            };
+            let body: String = rows.iter().map(|cells| row(cells, "td")).collect();
+            format!(
+                "<table>\n<thead>\n{}</thead>\n<tbody>\n{}</tbody>\n</table>\n",
+                row(header, "th"),
+                body
+            )
+        }
+        Block::Rule => "<hr/>\n".to_string(),
+        Block::Div {
+            attributes: div_attributes,
+            content,
+        } => {
+            let div_attributes = attributes(div_attributes);
+            if div_attributes.is_empty() {
+                format!("<div>\n{}</div>\n", blocks(content))
+            } else {
+                format!("<div {}>\n{}</div>\n", div_attributes, blocks(content))
+            }
+        }
+        // "--" isn't allowed in XML comments.
+        Block::Comment(comment) => format!("<!-- {} -->\n", comment.replace("--", "- -")),
+        Block::Template(template) => format!("{}\n", template),
+        Block::Space(_) => String::new(),
+    }
+}
+
+/// Writes `inlines` as HTML.
+pub fn inlines(inlines: &[Inline]) -> String {
+    inlines.iter().map(inline).collect()
+}
+
+fn inline(inline: &Inline) -> String {
+    let tagged = |tag: &str, content: &[Inline]| format!("<{0}>{1}</{0}>", tag, inlines(content));
+    match inline {
+        Inline::Text(text) => escape(text),
+        Inline::Bold(content) => tagged("strong", content),
+        Inline::Italic(content) => tagged("em", content),
+        Inline::Underline(content) => tagged("u", content),
+        Inline::Strikeout(content) => tagged("del", content),
+        Inline::Superscript(content) => tagged("sup", content),
+        Inline::Subscript(content) => tagged("sub", content),
+        Inline::Code(code) => format!("<code>{}</code>", escape(code)),
+        Inline::Link { content, target } => {
+            format!("<a href=\"{}\">{}</a>", escape(target), inlines(content))
+        }
+        Inline::Image {
+            description,
+            source,
+        } => format!(
+            "<img src=\"{}\" alt=\"{}\"/>",
+            escape(source),
+            escape(description)
+        ),
+        Inline::Anchor(name) => format!("<a id=\"{}\"></a>", escape(name)),
+        Inline::Span {
+            attributes: span_attributes,
+            content,
+        } => format!(
+            "<span {}>{}</span>",
+            attributes(span_attributes),
+            inlines(content)
+        ),
+        Inline::LineBreak => "<br/>\n".to_string(),
+        Inline::SoftBreak => "\n".to_string(),
+        Inline::Note(content) => format!("<span class=\"note\">{}</span>", inlines(content)),
+        Inline::Template(template) => template.clone(),
+        Inline::Html(html) => html.clone(),
+    }
+}
@@ -0,0 +1,157 @@
+/* ----- CDDL HEADER -----
+ *
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.1 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * https://spdx.org/licenses/CDDL-1.1.html
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ *
+ * ----- CDDL HEADER END -----
+ */
+
+// The WordStar document as the parser sees it, for tools which want more
+// than the Markdown: each line with its rule, where it is, its text and its
+// parts (modifiers, tokens, dot command arguments). What the grammar skips
+// (unknown tokens, control codes it ignores) is kept as "unknown" with its
+// bytes. The offsets are in the text after decoding, not in the file.
+
+use pest::iterators::{Pair, Pairs};
+use std::path::Path;
+
+use crate::diagnostics::json_string;
+use crate::Rule;
+
+fn unknown(text: &str, start: usize) -> String {
+    let bytes: Vec<String> = text.bytes().map(|byte| byte.to_string()).collect();
+    format!(
+        "{{\"rule\":\"unknown\",\"start\":{},\"end\":{},\"text\":{},\"bytes\":[{}]}}",
+        start,
+        start + text.len(),
+        json_string(text),
+        bytes.join(",")
+    )
+}
+
+// Line ends and the end of the file aren't anything the grammar skipped.
+fn is_unknown(text: &str) -> bool {
+    !text.trim_end_matches(['\r', '\n', '\u{1a}']).is_empty()
+}
+
+fn pair(pair: Pair<Rule>) -> String {
+    let span = pair.as_span();
+    let (line, column) = pair.line_col();
+    let mut children = Vec::new();
+    let mut position = span.start();
+    for child in pair.clone().into_inner() {
+        // The value of an unknown token is part of it.
+        if child.as_rule() == Rule::sequence_value && pair.as_rule() == Rule::normal_line {
+            continue;
+        }
+        let start = child.as_span().start();
+        let gap = &span.as_str()[position - span.start()..start - span.start()];
+        if is_unknown(gap) {
+            children.push(unknown(gap, position));
+        }
+        position = child.as_span().end();
+        children.push(self::pair(child));
+    }
+    if !children.is_empty() {
+        let gap = &span.as_str()[position - span.start()..];
+        if is_unknown(gap) {
+            children.push(unknown(gap, position));
+        }
+    }
+    format!(
+        "{{\"rule\":\"{:?}\",\"start\":{},\"end\":{},\"line\":{},\"column\":{},\"text\":{},\"children\":[{}]}}",
+        pair.as_rule(),
+        span.start(),
+        span.end(),
+        line,
+        column,
+        json_string(span.as_str()),
+        children.join(",")
+    )
+}
+
+fn file(path: &Path, lines: Vec<String>) -> String {
+    format!(
+        "{{\"file\":{},\"lines\":[\n{}\n]}}\n",
+        json_string(&path.to_string_lossy()),
+        lines.join(",\n")
+    )
+}
+
+/// Writes the parsed `lines` of the file at `path` as JSON.
+pub fn render(lines: Pairs<Rule>, path: &Path) -> String {
+    file(path, lines.map(pair).collect())
+}
+
+/// Writes the lines of a non-document, which has nothing to parse, as JSON.
+pub fn render_text(text: &str, path: &Path) -> String {
+    let mut start = 0;
+    let lines = text
+        .split_inclusive('\n')
+        .enumerate()
+        .map(|(index, line)| {
+            let json = format!(
+                "{{\"rule\":\"text\",\"start\":{},\"end\":{},\"line\":{},\"column\":1,\"text\":{},\"children\":[]}}",
+                start,
+                start + line.len(),
+                index + 1,
+                json_string(line)
+            );
+            start += line.len();
+            json
+        })
+        .collect();
+    file(path, lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WSParser;
+    use pest::Parser;
+
+    fn rendered(text: &str) -> String {
+        let file = WSParser::parse(Rule::file, text).unwrap().next().unwrap();
+        render(file.into_inner(), Path::new("test.ws"))
+    }
+
+    #[test]
+    fn lines_with_their_spans() {
+        let json = rendered("Hello\r\nWorld\r\n");
+        assert!(json.starts_with(
+            "{\"file\":\"test.ws\",\"lines\":[\n{\"rule\":\"normal_line\",\"start\":0,\"end\":7,\"line\":1,\"column\":1,\"text\":\"Hello\\r\\n\""
+        ));
+        assert!(json
+            .contains("{\"rule\":\"normal_line\",\"start\":7,\"end\":14,\"line\":2,\"column\":1,"));
+    }
+
+    #[test]
+    fn unknown_bytes() {
+        assert_eq!(
+            unknown("\u{1}x", 3),
+            "{\"rule\":\"unknown\",\"start\":3,\"end\":5,\"text\":\"\\u0001x\",\"bytes\":[1,120]}"
+        );
+        assert!(!is_unknown("\r\n\u{1a}"));
+    }
+
+    #[test]
+    fn non_documents() {
+        assert_eq!(
+            render_text("one\ntwo", Path::new("test.txt")),
+            "{\"file\":\"test.txt\",\"lines\":[\n\
+             {\"rule\":\"text\",\"start\":0,\"end\":4,\"line\":1,\"column\":1,\"text\":\"one\\n\",\"children\":[]},\n\
+             {\"rule\":\"text\",\"start\":4,\"end\":7,\"line\":2,\"column\":1,\"text\":\"two\",\"children\":[]}\n\
+             ]}\n"
+        );
+    }
+}
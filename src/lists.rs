@@ -0,0 +1,189 @@
+/* ----- CDDL HEADER -----
+ *
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.1 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * https://spdx.org/licenses/CDDL-1.1.html
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ *
+ * ----- CDDL HEADER END -----
+ */
+
+// WordStar has no lists, people just typed them. These are the heuristics to
+// find them again.
+
+/// A list item at the start of a line.
+pub struct ListItem {
+    /// The column of the item's marker, relative to the start of the text.
+    pub column: usize,
+    /// The length of the marker in bytes, including the spaces around it.
+    pub length: usize,
+    pub marker: Marker,
+}
+
+/// What a typed marker makes of a list item.
+#[derive(Debug, PartialEq)]
+pub enum Marker {
+    Bullet,
+    /// An item of an ordered list, with its number
+    Number(usize),
+}
+
+/// Returns the marker for a typed one: bullets are bullets, numbers stay
+/// as they are and letters are counted ("b." is 2).
+fn marker(typed_marker: &str) -> Option<Marker> {
+    if matches!(typed_marker, "-" | "*" | "o" | "•") {
+        return Some(Marker::Bullet);
+    }
+
+    let number = typed_marker.strip_suffix(['.', ')'])?;
+    if !number.is_empty() && number.len() <= 9 && number.bytes().all(|b| b.is_ascii_digit()) {
+        // CommonMark only allows up to 9 digits.
+        return number.parse().ok().map(Marker::Number);
+    }
+
+    let mut letters = number.chars();
+    match (letters.next(), letters.next()) {
+        (Some(letter), None) if letter.is_ascii_alphabetic() => Some(Marker::Number(usize::from(
+            letter.to_ascii_lowercase() as u8 - b'a' + 1,
+        ))),
+        _ => None,
+    }
+}
+
+/// Returns the list item the text starts with, if any.
+pub fn list_item(text: &str) -> Option<ListItem> {
+    let column = text.len() - text.trim_start_matches(' ').len();
+    let typed_marker = text[column..].split(' ').next()?;
+    let marker = marker(typed_marker)?;
+
+    // The marker needs a space and some text after it.
+    let item_text = &text[column + typed_marker.len()..];
+    let spaces = item_text.len() - item_text.trim_start_matches(' ').len();
+    if spaces == 0 || item_text.trim().is_empty() {
+        return None;
+    }
+    Some(ListItem {
+        column,
+        length: column + typed_marker.len() + spaces,
+        marker,
+    })
+}
+
+/// Returns the list item for a paragraph with a hanging indent. Its first
+/// text can be a typed marker on its own (followed by a tab), the start of a
+/// list item, or just text, which makes the paragraph a bullet.
+pub fn hanging_item(text: &str) -> ListItem {
+    if let Some(item) = list_item(text) {
+        return item;
+    }
+    let column = text.len() - text.trim_start_matches(' ').len();
+    match marker(text.trim()) {
+        Some(marker) => ListItem {
+            column,
+            length: text.len(),
+            marker,
+        },
+        None => ListItem {
+            column,
+            length: 0,
+            marker: Marker::Bullet,
+        },
+    }
+}
+
+/// Keeps track of the nesting of a list by the columns of its items.
+#[derive(Default)]
+pub struct ListNesting {
+    columns: Vec<usize>,
+}
+
+impl ListNesting {
+    /// Adds an item in the given column.
+    pub fn add_item(&mut self, column: usize) {
+        while self.columns.last().is_some_and(|&last| last > column) {
+            self.columns.pop();
+        }
+        if self.columns.last() != Some(&column) {
+            self.columns.push(column);
+        }
+    }
+
+    /// Whether text in the given column continues the current item.
+    pub fn is_continuation(&self, column: usize) -> bool {
+        self.columns.last().is_some_and(|&last| column > last)
+    }
+
+    /// The number of nested lists.
+    pub fn depth(&self) -> usize {
+        self.columns.len()
+    }
+
+    /// Ends the list.
+    pub fn clear(&mut self) {
+        self.columns.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bullets() {
+        let item = list_item("  * Text").unwrap();
+        assert_eq!(
+            (item.column, item.length, item.marker),
+            (2, 4, Marker::Bullet)
+        );
+        assert_eq!(list_item("o   Text").unwrap().length, 4);
+        assert_eq!(list_item("• Text").unwrap().marker, Marker::Bullet);
+    }
+
+    #[test]
+    fn numbers_and_letters() {
+        assert_eq!(list_item("12. Text").unwrap().marker, Marker::Number(12));
+        assert_eq!(list_item("3) Text").unwrap().marker, Marker::Number(3));
+        assert_eq!(list_item("b. Text").unwrap().marker, Marker::Number(2));
+        assert_eq!(list_item("C) Text").unwrap().marker, Marker::Number(3));
+    }
+
+    #[test]
+    fn not_list_items() {
+        // Markers need a space and text after them.
+        assert!(list_item("-Text").is_none());
+        assert!(list_item("- ").is_none());
+        assert!(list_item("1.5 percent").is_none());
+        assert!(list_item("ab. Text").is_none());
+        assert!(list_item("1234567890. Text").is_none());
+        assert!(list_item("Text").is_none());
+    }
+
+    #[test]
+    fn hanging_items() {
+        assert_eq!(hanging_item("1.").marker, Marker::Number(1));
+        let item = hanging_item("Text");
+        assert_eq!((item.length, item.marker), (0, Marker::Bullet));
+    }
+
+    #[test]
+    fn nesting() {
+        let mut nesting = ListNesting::default();
+        nesting.add_item(0);
+        nesting.add_item(4);
+        assert_eq!(nesting.depth(), 2);
+        assert!(nesting.is_continuation(6));
+        nesting.add_item(4);
+        assert_eq!(nesting.depth(), 2);
+        nesting.add_item(0);
+        assert_eq!(nesting.depth(), 1);
+        assert!(!nesting.is_continuation(0));
+    }
+}
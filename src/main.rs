@@ -17,43 +17,2932 @@
  * ----- CDDL HEADER END -----
  */
 
-use anyhow::Result;
-use pest::Parser;
+use anyhow::{Context, Result};
+use pest::{error::LineColLocation, iterators::Pair, Parser};
 use pest_derive::Parser;
 use rfd::FileDialog;
 use std::{
+    collections::HashMap,
     env,
     ffi::OsStr,
-    fs::{self, File},
-    io::{Read, Write},
+    fs::{self, File, OpenOptions},
+    io::Write,
     path::{self, Path, PathBuf},
+    process,
     str::FromStr,
 };
 
+mod codepage;
+mod diagnostics;
+mod djot;
+mod document;
+mod docx;
+mod dotcommands;
+mod epub;
+mod error;
+mod escape;
+mod header;
+mod html;
+mod json;
+mod lists;
+mod markdown;
+mod merge;
+mod modifiers;
+mod nondocument;
+mod org;
+mod pandoc;
+mod pdf;
+mod postprocess;
+mod ruler;
+mod sequences;
+mod text;
+mod typography;
+mod wrap;
+mod ws2000;
+mod wsd;
+mod zip;
+
+use codepage::{CharacterMap, CodePage, Decoder, Undecodable};
+use diagnostics::{Diagnostic, Severity};
+use document::{plain_text, Block, Builder, Container, Document, Inline};
+use error::ConversionError;
+use lists::{ListNesting, Marker};
+use merge::TemplateEngine;
+use modifiers::{Format, Modifiers};
+use ruler::Ruler;
+
 #[derive(Parser)]
 #[grammar = "wordstar.pest"]
 pub struct WSParser;
 
-fn show_usage() {
-    let exe_name: Option<String> = env::args()
-        .next()
-        .as_ref()
-        .map(Path::new)
-        .and_then(Path::file_name)
-        .and_then(OsStr::to_str)
-        .map(String::from);
+/// What to do with .he and .h1 to .h5 lines.
+#[derive(Default, PartialEq)]
+enum HeaderMode {
+    /// Write them as Markdown headings.
+    #[default]
+    Heading,
+    /// Drop them.
+    Drop,
+    /// Write them into the YAML front matter.
+    FrontMatter,
+    /// Write them once at the top of the document, in italics.
+    Italic,
+}
+
+/// What to do with .fo and .f1 to .f9 lines.
+#[derive(Default, PartialEq)]
+enum FooterMode {
+    /// Drop them.
+    #[default]
+    Drop,
+    /// Write them into the YAML front matter.
+    FrontMatter,
+    /// Write them once at the end of the document, in italics.
+    Italic,
+}
+
+/// How to write the alignment of text.
+#[derive(Default, PartialEq)]
+enum AlignMode {
+    /// <div align="...">
+    #[default]
+    Html,
+    /// ::: {.center} etc.
+    Pandoc,
+}
+
+/// How to write the left margin of text.
+#[derive(Default, PartialEq)]
+enum MarginMode {
+    /// &nbsp; for every column
+    #[default]
+    Nbsp,
+    /// "> " for every tab stop
+    Blockquote,
+    /// four spaces for every tab stop
+    Indent,
+    /// <div style="padding-left:...">
+    Div,
+}
+
+/// What to do with line spacing (.ls).
+#[derive(Default, PartialEq)]
+enum LineSpacingMode {
+    /// Ignore it.
+    #[default]
+    Ignore,
+    /// Add blank lines after every line.
+    Blank,
+    /// <div class="line-spacing-N">
+    Css,
+}
+
+/// Where to write the table of contents (.tc).
+#[derive(Default, PartialEq)]
+enum TocMode {
+    /// At the top of the document.
+    #[default]
+    Top,
+    /// Where the first .tc is.
+    Inline,
+}
+
+/// What expanded print becomes.
+#[derive(Default, PartialEq)]
+enum ExpandedMode {
+    /// A heading if it's a line of its own, <big> otherwise.
+    #[default]
+    Heading,
+    /// <big> always.
+    Big,
+}
+
+/// Which code page the characters above 0x7F are in.
+#[derive(Default, PartialEq)]
+enum CodePageMode {
+    /// The usual one of the input format.
+    #[default]
+    Default,
+    /// Guess from the characters.
+    Auto,
+    /// This one.
+    Fixed(CodePage),
+}
+
+/// How the output files are encoded.
+#[derive(Default, PartialEq)]
+enum OutputEncoding {
+    #[default]
+    Utf8,
+    Latin1,
+    CodePage(CodePage),
+}
+
+/// What to write: Markdown, or something made from it.
+#[derive(Default, PartialEq)]
+enum OutputFormat {
+    #[default]
+    Markdown,
+    Org,
+    Text,
+    Docx,
+    Epub,
+    Pdf,
+    PandocJson,
+    Json,
+    Djot,
+}
+
+/// What underlined text becomes. The spaces between words are only
+/// underlined after .ul on, as in WordStar.
+#[derive(Clone, Copy, PartialEq)]
+enum UnderlineMode {
+    UTag,
+    InsTag,
+    Emphasis,
+    Drop,
+}
+
+/// How to write headings: # Heading, or underlined with = and -.
+#[derive(Default, PartialEq)]
+enum HeadingStyle {
+    #[default]
+    Atx,
+    Setext,
+}
+
+/// Which Markdown to write, if it has to be a particular one.
+#[derive(Clone, Copy, PartialEq)]
+enum Flavor {
+    CommonMark,
+    Gfm,
+    MultiMarkdown,
+    Pandoc,
+}
+
+/// How much may get lost in the conversion.
+#[derive(Default, PartialEq)]
+enum ConversionMode {
+    /// Warn about what's lost, stop if the file can't be parsed.
+    #[default]
+    Default,
+    /// Stop at anything we don't fully understand.
+    Strict,
+    /// Never stop, convert what we can.
+    Lossy,
+}
+
+/// How warnings and errors are written.
+#[derive(Default, PartialEq)]
+enum DiagnosticsFormat {
+    /// For people.
+    #[default]
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+/// How lines end in the output.
+#[derive(Default, PartialEq)]
+enum Newline {
+    /// Unix.
+    #[default]
+    Lf,
+    /// DOS and Windows.
+    Crlf,
+}
+
+/// What to do with dot leaders (text ..... page).
+#[derive(Default, PartialEq)]
+enum DotLeaderMode {
+    /// Keep them.
+    #[default]
+    Keep,
+    /// Replace them by a separator.
+    Collapse,
+    /// Turn the lines into a table.
+    Table,
+}
+
+/// Which program wrote the input files.
+#[derive(Clone, Copy, Default, PartialEq)]
+enum InputFormat {
+    /// Whatever it looks like.
+    #[default]
+    Auto,
+    /// WordStar 5 to 7.
+    WordStar,
+    /// WordStar 3 and 4, NewWord: no header, no sequences.
+    WordStar4,
+    /// WordStar 2000.
+    WordStar2000,
+    /// WordStar for Windows (.wsd).
+    WordStarWindows,
+    /// Non-documents: plain text.
+    NonDocument,
+}
+
+/// What a paragraph style (WordStar 6 and 7) becomes.
+#[derive(PartialEq)]
+enum ParagraphStyle {
+    /// # to ######
+    Heading(usize),
+    /// >
+    Blockquote,
+    /// ```
+    Code,
+    /// Just text.
+    Paragraph,
+}
+
+/// The alignment of the text, as far as we write it.
+#[derive(Clone, Copy, PartialEq)]
+enum Alignment {
+    Left,
+    Center,
+    Justify,
+}
+
+/// The containers text goes into, from the outside in: the alignment, the
+/// line spacing and the left margin, as far as the options want them.
+fn containers(
+    options: &Options,
+    alignment: Alignment,
+    line_spacing: usize,
+    margin: usize,
+    ruler: &Ruler,
+) -> Vec<Container> {
+    let mut containers = Vec::new();
+    match (alignment, &options.align) {
+        (Alignment::Left, _) => {}
+        (Alignment::Center, AlignMode::Html) => {
+            containers.push(Container::Div(String::from("align=\"center\"")))
+        }
+        (Alignment::Center, AlignMode::Pandoc) => {
+            containers.push(Container::Div(String::from("{.center}")))
+        }
+        (Alignment::Justify, AlignMode::Html) => {
+            containers.push(Container::Div(String::from("align=\"justify\"")))
+        }
+        (Alignment::Justify, AlignMode::Pandoc) => {
+            containers.push(Container::Div(String::from("{.justify}")))
+        }
+    }
+    if options.line_spacing == LineSpacingMode::Css && line_spacing > 1 {
+        containers.push(Container::Div(format!(
+            "class=\"line-spacing-{}\"",
+            line_spacing
+        )));
+    }
+    match options.margins {
+        MarginMode::Blockquote if ruler.tab_level(margin) > 0 => {
+            containers.push(Container::Quote(ruler.tab_level(margin)))
+        }
+        MarginMode::Div if margin > 0 => containers.push(Container::Div(format!(
+            "style=\"padding-left:{}ch\"",
+            margin
+        ))),
+        _ => {}
+    }
+    containers
+}
+
+/// Closes the open containers which the text isn't in anymore, and opens the
+/// ones it's in now.
+fn switch_containers(builder: &mut Builder, open: &mut Vec<Container>, containers: Vec<Container>) {
+    let kept = open
+        .iter()
+        .zip(&containers)
+        .take_while(|(open, container)| open == container)
+        .count();
+    while open.len() > kept {
+        builder.close();
+        open.pop();
+    }
+    for container in containers.into_iter().skip(kept) {
+        builder.open(container.clone());
+        open.push(container);
+    }
+}
+
+/// WordStar's character width (in 1/120 inch) if there is no .cw.
+const DEFAULT_CHARACTER_WIDTH: usize = 12;
+
+/// WordStar's right margin if there is no .rm.
+const DEFAULT_RIGHT_MARGIN: usize = 65;
+
+/// What a line of text becomes.
+#[derive(Clone, Copy, PartialEq)]
+enum LineKind {
+    Paragraph,
+    Quote,
+}
+
+/// Adds a line of text (or a reflowed paragraph) to the document, wrapped at
+/// the right margin if the user wants that.
+fn finish_line(
+    builder: &mut Builder,
+    mut content: Vec<Inline>,
+    kind: LineKind,
+    options: &Options,
+    right_margin: Option<usize>,
+) {
+    // Lines without text are blank lines.
+    if content.iter().all(
+        |inline| matches!(inline, Inline::Text(text) if text.trim_matches([' ', '\t']).is_empty()),
+    ) {
+        builder.space(1);
+        return;
+    }
+    if options.wrap {
+        wrap::wrap_inlines(&mut content, right_margin.unwrap_or(DEFAULT_RIGHT_MARGIN));
+    }
+    match kind {
+        LineKind::Paragraph => builder.paragraph_line(content),
+        LineKind::Quote => builder.quote_line(content),
+    }
+}
+
+/// Command line switches which influence the generated Markdown.
+#[derive(Default)]
+struct Options {
+    /// Use Pandoc's ^superscript^ and ~subscript~ instead of HTML tags.
+    pandoc_scripts: bool,
+    /// Use <del> instead of GFM's ~~strikeout~~.
+    html_strikeout: bool,
+    /// Keep soft hyphens as U+00AD instead of dropping them.
+    soft_hyphens: bool,
+    /// Write binding spaces as U+00A0 instead of &nbsp;.
+    unicode_nbsp: bool,
+    /// Opening and closing strings for the user print codes, by letter.
+    print_codes: HashMap<char, (String, String)>,
+    /// Wrap font changes into <span style="font-family:...">.
+    font_spans: bool,
+    /// Wrap color changes into <span style="color:...">.
+    emit_color: bool,
+    /// Drop annotations instead of writing them as HTML comments.
+    drop_notes: bool,
+    /// Write comment lines (.. and .ig) as HTML comments.
+    keep_comments: bool,
+    /// Write dot commands which leave no trace in the output as HTML comments.
+    preserve_dot_commands: bool,
+    /// How to write .he and .h1 to .h5 lines.
+    headers: HeaderMode,
+    /// How to write .fo and .f1 to .f9 lines.
+    footers: FooterMode,
+    /// Write <!-- page n --> after each page break.
+    page_markers: bool,
+    /// What to write for a page break, instead of a horizontal rule.
+    page_separator: Option<String>,
+    /// Treat conditional page breaks (.cp) like page breaks.
+    honor_conditional_breaks: bool,
+    /// How to write the alignment of text.
+    align: AlignMode,
+    /// Mark justified text (.oj) as such.
+    justify: bool,
+    /// Keep tabs instead of expanding them to the next tab stop.
+    keep_tabs: bool,
+    /// Join the hard-wrapped lines of paragraphs.
+    reflow: bool,
+    /// Wrap paragraphs at the right margin.
+    wrap: bool,
+    /// The maximum number of consecutive blank lines (default: 1).
+    max_blank_lines: Option<usize>,
+    /// Remove spaces at the end of lines.
+    trim_spaces: bool,
+    /// Collapse runs of spaces inside lines.
+    collapse_spaces: bool,
+    /// Remove form feeds.
+    strip_form_feeds: bool,
+    /// Keep the text of lines which can't be parsed instead of giving up.
+    recover: bool,
+    /// Write the control codes we'd drop as ^C and \x1D instead.
+    show_controls: bool,
+    /// How much may get lost in the conversion.
+    mode: ConversionMode,
+    /// What to write.
+    output_format: OutputFormat,
+    /// Write underline, strikeout, footnotes, tables and line breaks the way
+    /// this Markdown does.
+    flavor: Option<Flavor>,
+    /// What underlined text becomes, if not what the flavor has (<u>).
+    underline: Option<UnderlineMode>,
+    /// How to write headings.
+    heading_style: HeadingStyle,
+    /// How many levels to move all headings down (up, if negative).
+    shift_headings: isize,
+    /// The program which prints the PDF output.
+    pdf_engine: Option<String>,
+    /// How to write warnings and errors.
+    diagnostics: DiagnosticsFormat,
+    /// Append the diagnostics to this file instead of writing them to stderr.
+    diagnostics_file: Option<PathBuf>,
+    /// Turn typed lists into Markdown lists.
+    lists: bool,
+    /// How to write the left margin of text.
+    margins: MarginMode,
+    /// What to do with line spacing (.ls).
+    line_spacing: LineSpacingMode,
+    /// Write the page layout (.mt, .mb, .pl, .po, .co, .rm) and page numbering
+    /// (.pn, .op, .pg) into the front matter.
+    layout_metadata: bool,
+    /// Write text in a narrow fixed-pitch character width (.cw) as code blocks.
+    code_blocks: bool,
+    /// Write mail merge fields (&NAME&) as placeholders of this engine.
+    merge_fields: Option<TemplateEngine>,
+    /// Perform the mail merge with this data file.
+    merge_data: Option<PathBuf>,
+    /// Values for .av and .sv variables when merging, by upper case name.
+    variables: HashMap<String, String>,
+    /// Convert included files (.fi) into the document instead of linking them.
+    inline_includes: bool,
+    /// Include files from outside of the input file's directory.
+    allow_external_includes: bool,
+    /// Where to write the table of contents.
+    toc: TocMode,
+    /// Link table of contents entries to the headings with the same text.
+    toc_links: bool,
+    /// Which program wrote the input files.
+    input_format: InputFormat,
+    /// The code page of the characters above 0x7F.
+    code_page: CodePageMode,
+    /// Bytes which are something else than their code page says.
+    charmap: CharacterMap,
+    /// What to do with bytes which are no characters at all.
+    undecodable: Undecodable,
+    /// How to encode the output.
+    output_encoding: OutputEncoding,
+    /// Start UTF-8 output with a byte order mark.
+    bom: bool,
+    /// How lines end in the output.
+    newline: Newline,
+    /// Tell what we found out about the input files.
+    verbose: bool,
+    /// Tell which dot commands were left out, and how often.
+    report_ignored: bool,
+    /// Don't escape characters which mean something in Markdown.
+    no_escape: bool,
+    /// Curly quotes and ellipses instead of typewriter punctuation.
+    smart_punctuation: bool,
+    /// En and em dashes instead of -- and ---.
+    smart_dashes: bool,
+    /// Write what the file header tells into the front matter.
+    header_metadata: bool,
+    /// What the paragraph styles become, by name.
+    styles: HashMap<String, ParagraphStyle>,
+    /// Put box drawings and ASCII art into code blocks.
+    fence_drawings: bool,
+    /// Turn text aligned in columns into tables.
+    tables: bool,
+    /// Write text in the alternate pitch (^A ... ^N) as code.
+    pitch_code: bool,
+    /// The print code (^PE, ^PQ, ^PR, ^PW) which switches to expanded print.
+    expanded_code: Option<char>,
+    /// What expanded print becomes.
+    expanded: ExpandedMode,
+    /// What to do with dot leaders.
+    dot_leaders: DotLeaderMode,
+    /// What collapsed dot leaders become.
+    leader_separator: Option<String>,
+    /// Copy inset graphics into this directory (the output file's).
+    copy_graphics: Option<PathBuf>,
+}
+
+/// Returns the style of the <span> which the text in the given font and
+/// color needs, if it needs one.
+fn span_style(options: &Options, font: &Option<String>, color: &Option<String>) -> Option<String> {
+    let mut styles = Vec::new();
+    if let Some(font) = font.as_ref().filter(|_| options.font_spans) {
+        styles.push(format!("font-family:{}", font));
+    }
+    if let Some(color) = color.as_ref().filter(|_| options.emit_color) {
+        styles.push(format!("color:{}", color));
+    }
+    Some(styles.join(";")).filter(|style| !style.is_empty())
+}
+
+/// Switches off the modifiers which are still on at the end of a paragraph
+/// (before `line`), with a warning.
+fn close_modifiers(
+    modifiers: &mut Modifiers,
+    line: usize,
+    options: &Options,
+    path: &Path,
+) -> Result<()> {
+    for name in modifiers.close_all() {
+        warn(
+            options,
+            Diagnostic::warning(
+                path,
+                "unclosed-modifier",
+                format!(
+                    "{} is still on at the end of the paragraph, switching it off",
+                    name
+                ),
+            )
+            .at_line(line),
+        )?;
+    }
+    Ok(())
+}
+
+/// Reads the value of a margin command: a column, or +n or -n columns from
+/// the `current` one.
+fn margin(value: &str, current: usize) -> usize {
+    let columns = |number: &str| usize::from_str(number).unwrap_or(0);
+    match value.strip_prefix('+') {
+        Some(number) => current + columns(number),
+        None => match value.strip_prefix('-') {
+            Some(number) => current.saturating_sub(columns(number)),
+            None => columns(value),
+        },
+    }
+}
+
+/// Remembers that the dot command `pair` was left out.
+fn count_dot_command(ignored: &mut Vec<(String, usize, usize)>, pair: &Pair<Rule>) {
+    // All dot commands have two letters.
+    let name: String = pair
+        .as_str()
+        .chars()
+        .take(3)
+        .collect::<String>()
+        .to_lowercase();
+    match ignored.iter_mut().find(|(known, _, _)| *known == name) {
+        Some((_, count, _)) => *count += 1,
+        None => ignored.push((name, 1, pair.as_span().start_pos().line_col().0)),
+    }
+}
+
+/// Tells the user about something which couldn't be converted as it was. In
+/// strict mode, that's an error.
+fn warn(options: &Options, diagnostic: Diagnostic) -> Result<()> {
+    if options.mode == ConversionMode::Strict {
+        return Err(ConversionError::Render { diagnostic }.into());
+    }
+    report(options, &diagnostic)
+}
+
+/// Writes a warning or error in the chosen format.
+fn report(options: &Options, diagnostic: &Diagnostic) -> Result<()> {
+    let line = match options.diagnostics {
+        DiagnosticsFormat::Text => match diagnostic.severity {
+            Severity::Note => format!("note: {}", diagnostic),
+            Severity::Warning => format!("warning: {}", diagnostic),
+            Severity::Error => format!("error: {}", diagnostic),
+        },
+        DiagnosticsFormat::Json => diagnostic.to_json(),
+    };
+    match &options.diagnostics_file {
+        Some(file) => OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(file)
+            .and_then(|mut handle| writeln!(handle, "{}", line))
+            .with_context(|| format!("could not write to {}", file.display()))?,
+        None => eprintln!("{}", line),
+    }
+    Ok(())
+}
+
+/// Makes `text` parseable: lines the grammar doesn't understand are replaced
+/// by their printable characters, or dropped if that doesn't help either.
+/// Returns the text and what had to be done to it.
+fn recover_text(
+    text: &str,
+    path: &Path,
+) -> Result<(String, Vec<Diagnostic>), Box<pest::error::Error<Rule>>> {
+    let mut recovered_text = text.to_string();
+    let mut warnings = Vec::new();
+    while let Err(error) = WSParser::parse(Rule::file, &recovered_text) {
+        let line_number = match error.line_col {
+            LineColLocation::Pos((line, _)) | LineColLocation::Span((line, _), _) => line,
+        };
+        let mut lines: Vec<String> = recovered_text.split('\n').map(String::from).collect();
+        let index = (line_number - 1).min(lines.len() - 1);
+
+        // The last line needs a line break (or ^Z).
+        if index == lines.len() - 1 && !lines[index].is_empty() {
+            recovered_text.push('\n');
+            warnings.push(
+                Diagnostic::warning(
+                    path,
+                    "missing-line-break",
+                    "no line break at the end, adding one".to_string(),
+                )
+                .at_line(line_number),
+            );
+            continue;
+        }
+
+        let line = &lines[index];
+        let printable: String = line
+            .chars()
+            .filter(|&character| !character.is_control() || character == '\t')
+            .collect();
+        if line.is_empty() {
+            // Nothing left to drop, this is a bug in the grammar.
+            return Err(Box::new(error));
+        } else if printable != *line {
+            warnings.push(
+                Diagnostic::warning(
+                    path,
+                    "unparseable-line",
+                    "could not be parsed, keeping its text only".to_string(),
+                )
+                .at_line(line_number),
+            );
+            lines[index] = printable;
+        } else {
+            warnings.push(
+                Diagnostic::warning(
+                    path,
+                    "unparseable-line",
+                    "could not be parsed, dropping it".to_string(),
+                )
+                .at_line(line_number),
+            );
+            lines[index] = String::new();
+        }
+        recovered_text = lines.join("\n");
+    }
+    Ok((recovered_text, warnings))
+}
+
+/// Applies the typographic options to `text`. `previous` is the character
+/// before it, if there is one.
+fn typeset(text: &str, options: &Options, previous: Option<char>) -> String {
+    let mut typeset_text = text.to_string();
+    if options.smart_dashes {
+        typeset_text = typography::smart_dashes(&typeset_text);
+    }
+    if options.smart_punctuation {
+        typeset_text = typography::smart_punctuation(&typeset_text, previous);
+    }
+    typeset_text
+}
+
+/// Whether a normal_line contains any visible text.
+fn contains_text(line: &Pair<Rule>) -> bool {
+    line.clone().into_inner().any(|pair| match pair.as_rule() {
+        Rule::displayed_text => !pair.as_str().trim().is_empty(),
+        Rule::overprint | Rule::nonbreaking_space => true,
+        _ => false,
+    })
+}
+
+/// Returns the letter of a user print code: ^PE, ^PQ, ^PR or ^PW.
+fn print_code_letter(code: &str) -> char {
+    match code {
+        "\u{05}" => 'E',
+        "\u{11}" => 'Q',
+        "\u{12}" => 'R',
+        _ => 'W',
+    }
+}
+
+/// Whether all visible text of a normal_line is in expanded print (switched
+/// by the print code `expanded_code`). `expanded` tells whether it's on at
+/// the start of the line.
+fn expanded_only(line: &Pair<Rule>, expanded_code: char, mut expanded: bool) -> bool {
+    for pair in line.clone().into_inner() {
+        match pair.as_rule() {
+            Rule::allowed_modifiers => {
+                for modifier_pair in pair.into_inner() {
+                    if modifier_pair.as_rule() == Rule::user_print_code
+                        && print_code_letter(modifier_pair.as_str()) == expanded_code
+                    {
+                        expanded = !expanded;
+                    }
+                }
+            }
+            Rule::displayed_text if pair.as_str().trim().is_empty() => {}
+            Rule::displayed_text | Rule::overprint | Rule::nonbreaking_space if !expanded => {
+                return false
+            }
+            _ => {}
+        }
+    }
+    true
+}
+
+/// Returns the text a normal_line starts with, after its leading tabs.
+fn leading_text<'a>(line: &Pair<'a, Rule>) -> Option<&'a str> {
+    line.clone()
+        .into_inner()
+        .find(|pair| pair.as_rule() != Rule::tab)
+        .filter(|pair| pair.as_rule() == Rule::displayed_text)
+        .map(|pair| pair.as_str())
+}
+
+/// Returns the column the text of a normal_line starts in.
+fn text_column(line: &Pair<Rule>, left_margin: usize, ruler: &Ruler) -> usize {
+    let mut column = left_margin;
+    for _ in line
+        .clone()
+        .into_inner()
+        .take_while(|pair| pair.as_rule() == Rule::tab)
+    {
+        column = ruler.next_tab_stop(column);
+    }
+    column + leading_text(line).map_or(0, |text| text.len() - text.trim_start_matches(' ').len())
+}
+
+/// Appends the next line of a paragraph to the text so far.
+fn join_lines(text: &mut String, hyphenated: bool) {
+    // Wrapped lines might end with trailing spaces.
+    let trimmed_length = text.trim_end_matches(' ').len();
+    text.truncate(trimmed_length);
+
+    // Hyphenated words are joined without a space. This includes compound
+    // words which were wrapped after their hyphen.
+    let mut last_characters = text.chars().rev();
+    let compound = last_characters.next() == Some('-')
+        && last_characters.next().is_some_and(char::is_alphabetic);
+    if !hyphenated && !compound {
+        text.push(' ');
+    }
+}
+
+/// Quotes a string for the YAML front matter.
+fn yaml_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Formats a list of strings for the YAML front matter.
+fn yaml_list(values: &[String]) -> String {
+    let quoted_values: Vec<String> = values.iter().map(|value| yaml_quote(value)).collect();
+    format!("[{}]", quoted_values.join(", "))
+}
+
+/// Returns the anchor of a heading, the way GitHub makes them: lower case,
+/// no punctuation, dashes for spaces, and numbered if there are several
+/// headings with the same text.
+fn heading_anchor(text: &str, anchors: &mut HashMap<String, usize>) -> String {
+    let anchor: String = text
+        .trim()
+        .to_lowercase()
+        .chars()
+        .filter(|&c| c.is_alphanumeric() || c == ' ' || c == '-' || c == '_')
+        .map(|c| if c == ' ' { '-' } else { c })
+        .collect();
+    let count = anchors.entry(anchor.clone()).or_insert(0);
+    *count += 1;
+    match *count {
+        1 => anchor,
+        count => format!("{}-{}", anchor, count - 1),
+    }
+}
+
+/// Switches the <span> for the style of the text, if it changes.
+fn switch_span(modifiers: &mut Modifiers, open_style: &mut Option<String>, style: Option<String>) {
+    if *open_style == style {
+        return;
+    }
+    modifiers.switch("span", false, Format::Plain);
+    if let Some(style) = &style {
+        modifiers.switch("span", true, Format::Span(style.clone()));
+    }
+    *open_style = style;
+}
+
+/// WordStar (DOS) sets bit 7 on the last character of each word, on soft
+/// spaces and on soft returns. Clear it again so the parser sees plain ASCII.
+/// Soft returns are kept as U+008D though, so the parser can tell them apart
+/// from hard returns. Extended characters (0x1B <char> 0x1C) and the tokens
+/// from rewrite_sequences (0x1D ... 0x1D) are left alone, except that the
+/// characters above 0x7F are decoded by `decoder`. Older versions have
+/// neither extended characters nor sequences, 0x1B and 0x1D are just control
+/// codes there.
+fn clear_high_bits(content: &[u8], sequences: bool, decoder: &Decoder) -> Vec<u8> {
+    let mut in_extended_character = false;
+    let mut in_token = false;
+    let mut cleared_content = Vec::with_capacity(content.len());
+    for &byte in content {
+        match byte {
+            0x1b if sequences => {
+                in_extended_character = true;
+                cleared_content.push(byte);
+            }
+            0x1c => {
+                in_extended_character = false;
+                cleared_content.push(byte);
+            }
+            0x1d if sequences => {
+                in_token = !in_token;
+                cleared_content.push(byte);
+            }
+            0x80..=0xff if in_extended_character => {
+                cleared_content.extend_from_slice(decoder.decode(byte).as_bytes());
+            }
+            _ if in_extended_character || in_token => cleared_content.push(byte),
+            // Soft spaces were inserted for justification.
+            0xa0 => cleared_content.push(b' '),
+            // Soft returns: U+008D in UTF-8.
+            0x8d => cleared_content.extend_from_slice(&[0xc2, 0x8d]),
+            // Flagged word endings etc.
+            0x80..=0xff => cleared_content.push(byte & 0x7f),
+            _ => cleared_content.push(byte),
+        }
+    }
+    cleared_content
+}
+
+/// Makes the control codes in `content` which the grammar would drop visible:
+/// ^C for control bytes, \x1D\xNN for sequences we don't know (of type NN).
+fn show_controls(content: &[u8], sequences: bool) -> Vec<u8> {
+    let mut in_extended_character = false;
+    let mut shown_content = Vec::with_capacity(content.len());
+    let mut position = 0;
+    while position < content.len() {
+        let byte = content[position];
+        match byte {
+            0x1b if sequences => {
+                in_extended_character = true;
+                shown_content.push(byte);
+            }
+            0x1c if sequences => {
+                in_extended_character = false;
+                shown_content.push(byte);
+            }
+            _ if in_extended_character => shown_content.push(byte),
+            0x1d if sequences => {
+                // Tokens end with the next 0x1D.
+                let end = content[position + 1..]
+                    .iter()
+                    .position(|&byte| byte == 0x1d)
+                    .map_or(content.len(), |end| position + end + 2);
+                let token = &content[position..end];
+                match token.strip_prefix(b"\x1dunknown:") {
+                    Some(sequence_type) => {
+                        shown_content.extend_from_slice(b"\\x1D");
+                        let sequence_type = sequence_type.strip_suffix(b"\x1d").unwrap_or_default();
+                        if !sequence_type.is_empty() {
+                            shown_content.extend_from_slice(b"\\x");
+                            shown_content.extend_from_slice(sequence_type);
+                        }
+                    }
+                    None => shown_content.extend_from_slice(token),
+                }
+                position = end;
+                continue;
+            }
+            // What ignored_modifiers and unknown_token skip (but ^H, which
+            // might be overprinting):
+            0x00 | 0x03 | 0x06 | 0x07 | 0x10 | 0x15 | 0x1b..=0x1e => {
+                shown_content.push(b'^');
+                shown_content.push(byte + 0x40);
+            }
+            _ => shown_content.push(byte),
+        }
+        position += 1;
+    }
+    shown_content
+}
+
+/// Turns CR LF and bare CR (left by old Mac transfer tools) into LF, so all
+/// lines end the same way. Soft returns keep their U+008D.
+fn normalize_line_endings(content: &[u8]) -> Vec<u8> {
+    let mut normalized_content = Vec::with_capacity(content.len());
+    let mut bytes = content.iter().peekable();
+    while let Some(&byte) = bytes.next() {
+        if byte == b'\r' {
+            bytes.next_if_eq(&&b'\n');
+            normalized_content.push(b'\n');
+        } else {
+            normalized_content.push(byte);
+        }
+    }
+    normalized_content
+}
+
+/// Returns the bytes of `content` which are extended characters (above 0x7F)
+/// rather than flagged ASCII or binary data, with their offsets.
+fn extended_characters(content: &[u8], input_format: InputFormat) -> Vec<(usize, u8)> {
+    match input_format {
+        // The high bit is a flag there, or dropped.
+        InputFormat::WordStar4 | InputFormat::WordStar2000 => Vec::new(),
+        // 0x1B <char> 0x1C
+        InputFormat::WordStar | InputFormat::Auto => content
+            .windows(3)
+            .enumerate()
+            .filter(|(_, window)| window[0] == 0x1b && window[1] >= 0x80 && window[2] == 0x1c)
+            .map(|(offset, window)| (offset + 1, window[1]))
+            .collect(),
+        // Only the ones next to letters, binary data has high bytes too.
+        InputFormat::WordStarWindows => content
+            .windows(3)
+            .enumerate()
+            .filter(|(_, window)| {
+                window[1] >= 0x80
+                    && (window[0].is_ascii_alphabetic() || window[2].is_ascii_alphabetic())
+            })
+            .map(|(offset, window)| (offset + 1, window[1]))
+            .collect(),
+        InputFormat::NonDocument => content
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|&(_, byte)| byte >= 0x80)
+            .collect(),
+    }
+}
+
+/// Encodes the output the way the user wants it, with the line endings they
+/// want. Characters the encoding doesn't have are written as HTML entities.
+fn encode_output(text: &str, options: &Options) -> Vec<u8> {
+    let crlf_text;
+    let text = match options.newline {
+        Newline::Lf => text,
+        Newline::Crlf => {
+            crlf_text = text.replace('\n', "\r\n");
+            &crlf_text
+        }
+    };
+
+    let encode = |character: char| match options.output_encoding {
+        OutputEncoding::Utf8 => None,
+        OutputEncoding::Latin1 => u8::try_from(character).ok(),
+        OutputEncoding::CodePage(code_page) => code_page.encode(character),
+    };
+    if options.output_encoding == OutputEncoding::Utf8 {
+        let bom = if options.bom { "\u{feff}" } else { "" };
+        return format!("{}{}", bom, text).into_bytes();
+    }
+
+    let mut encoded_text = Vec::with_capacity(text.len());
+    for character in text.chars() {
+        match encode(character) {
+            Some(byte) => encoded_text.push(byte),
+            None => {
+                encoded_text.extend_from_slice(format!("&#x{:X};", u32::from(character)).as_bytes())
+            }
+        }
+    }
+    encoded_text
+}
+
+/// Accents which were overprinted on a character, with the precomposed
+/// characters they form and the combining mark to use for everything else.
+const OVERPRINT_ACCENTS: &[(char, &str, &str, char)] = &[
+    ('\'', "aeiouyAEIOUY", "áéíóúýÁÉÍÓÚÝ", '\u{301}'),
+    ('`', "aeiouAEIOU", "àèìòùÀÈÌÒÙ", '\u{300}'),
+    ('^', "aeiouAEIOU", "âêîôûÂÊÎÔÛ", '\u{302}'),
+    ('~', "anoANO", "ãñõÃÑÕ", '\u{303}'),
+    ('"', "aeiouyAEIOUY", "äëïöüÿÄËÏÖÜŸ", '\u{308}'),
+    (',', "cC", "çÇ", '\u{327}'),
+    ('/', "oO", "øØ", '\u{338}'),
+];
+
+/// Collapses two overprinted characters into one.
+fn combine_overprint(first: char, second: char) -> String {
+    if first == second {
+        // Overprinting a character with itself makes it darker, nothing else.
+        return first.to_string();
+    }
+
+    // The accent could have been printed first.
+    let accent_position = OVERPRINT_ACCENTS
+        .iter()
+        .position(|(accent, ..)| *accent == second)
+        .map(|position| (first, position))
+        .or_else(|| {
+            OVERPRINT_ACCENTS
+                .iter()
+                .position(|(accent, ..)| *accent == first)
+                .map(|position| (second, position))
+        });
+
+    match accent_position {
+        Some((base, position)) => {
+            let (_, bases, precomposed, combining_mark) = OVERPRINT_ACCENTS[position];
+            match bases.chars().position(|c| c == base) {
+                Some(index) => precomposed.chars().nth(index).unwrap().to_string(),
+                None => format!("{}{}", base, combining_mark),
+            }
+        }
+        // No idea what that's supposed to be. Keep both.
+        None => format!("{}{}", first, second),
+    }
+}
+
+/// Reads the replacements for the user print codes (^PE, ^PQ, ^PR, ^PW)
+/// from a TOML file like this:
+///
+/// [Q]
+/// open = "<kbd>"
+/// close = "</kbd>"
+fn read_print_codes(path: &Path) -> Result<HashMap<char, (String, String)>> {
+    let config = fs::read_to_string(path)
+        .with_context(|| format!("could not read {}", path.display()))?
+        .parse::<toml::Table>()
+        .with_context(|| format!("could not parse {}", path.display()))?;
+
+    let mut print_codes = HashMap::new();
+    for (code, replacement) in config {
+        let letter = match code.to_ascii_uppercase().as_str() {
+            "E" => 'E',
+            "Q" => 'Q',
+            "R" => 'R',
+            "W" => 'W',
+            _ => anyhow::bail!("{}: no such user print code: {}", path.display(), code),
+        };
+        let get_string = |key: &str| {
+            replacement
+                .get(key)
+                .and_then(toml::Value::as_str)
+                .unwrap_or_default()
+                .to_string()
+        };
+        print_codes.insert(letter, (get_string("open"), get_string("close")));
+    }
+    Ok(print_codes)
+}
+
+/// Reads what the paragraph styles become from a TOML file like this:
+///
+/// "Heading 1" = "h1"
+/// Quotation = "blockquote"
+/// Listing = "code"
+fn read_styles(path: &Path) -> Result<HashMap<String, ParagraphStyle>> {
+    let config = fs::read_to_string(path)
+        .with_context(|| format!("could not read {}", path.display()))?
+        .parse::<toml::Table>()
+        .with_context(|| format!("could not parse {}", path.display()))?;
+
+    let mut styles = HashMap::new();
+    for (style, construct) in config {
+        let construct = construct.as_str().unwrap_or_default();
+        let paragraph_style = match construct {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                ParagraphStyle::Heading(usize::from_str(&construct[1..]).unwrap())
+            }
+            "blockquote" => ParagraphStyle::Blockquote,
+            "code" => ParagraphStyle::Code,
+            "paragraph" => ParagraphStyle::Paragraph,
+            _ => anyhow::bail!(
+                "{}: unknown construct for {}: {}",
+                path.display(),
+                style,
+                construct
+            ),
+        };
+        styles.insert(style, paragraph_style);
+    }
+    Ok(styles)
+}
+
+/// Reads what single bytes become instead of their code page characters,
+/// from a TOML file like this:
+///
+/// 0xF0 = "§"
+///
+/// or, if its name ends in .csv, a comma-separated one like this:
+///
+/// 0xF0,§
+fn read_charmap(path: &Path) -> Result<CharacterMap> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("could not read {}", path.display()))?;
+    let mappings: Vec<(String, String)> = if path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"))
+    {
+        content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let fields = merge::arguments(line);
+                (
+                    fields[0].clone(),
+                    fields.get(1).cloned().unwrap_or_default(),
+                )
+            })
+            .collect()
+    } else {
+        content
+            .parse::<toml::Table>()
+            .with_context(|| format!("could not parse {}", path.display()))?
+            .into_iter()
+            .map(|(byte, text)| (byte, text.as_str().unwrap_or_default().to_string()))
+            .collect()
+    };
+
+    let mut charmap = CharacterMap::new();
+    for (byte, text) in mappings {
+        let value = match byte.strip_prefix("0x").or(byte.strip_prefix("0X")) {
+            Some(hex) => u8::from_str_radix(hex, 16),
+            None => u8::from_str(&byte),
+        };
+        match value {
+            Ok(value) => charmap.insert(value, text),
+            Err(_) => anyhow::bail!("{}: not a byte: {}", path.display(), byte),
+        };
+    }
+    Ok(charmap)
+}
+
+fn show_usage() {
+    let exe_name: Option<String> = env::args()
+        .next()
+        .as_ref()
+        .map(Path::new)
+        .and_then(Path::file_name)
+        .and_then(OsStr::to_str)
+        .map(String::from);
+
+    println!("\tws2markdown: a WordStar to Markdown converter.");
+    println!(
+        "\tUsage: {0} [inputfile.ws] [outputfile.md]",
+        exe_name.as_deref().unwrap_or("ws2markdown")
+    );
+    println!("\tIf inputfile = empty, a file picker will appear.");
+    println!("\tIf outputfile = empty, the output will be printed to stdout.");
+    println!();
+    println!("\tOptions:");
+    println!("\t  --to=FORMAT\t\tWrite markdown (default), djot, org (Emacs Org mode), text");
+    println!("\t\t\t\t(plain text, one line per paragraph, or wrapped with");
+    println!("\t\t\t\t--wrap), docx (Word), epub (e-book, in chapters), pdf");
+    println!("\t\t\t\t(printed from HTML), pandoc-json (Pandoc's AST, for pandoc");
+    println!("\t\t\t\t-f json) or json (the WordStar lines as parsed). docx, epub");
+    println!("\t\t\t\tand pdf need an output file.");
+    println!("\t  --pdf-engine=PROGRAM\tPrint PDFs with PROGRAM (chromium, google-chrome,");
+    println!("\t\t\t\twkhtmltopdf or weasyprint) instead of the first one found.");
+    println!("\t  --flavor=FLAVOR\tWrite the Markdown of commonmark, gfm, multimarkdown (mmd)");
+    println!("\t\t\t\tor pandoc: underline, strikeout, footnotes, tables and line");
+    println!("\t\t\t\tbreaks the way it has them.");
+    println!("\t  --underline=MODE\tWrite underlined text as: u-tag (<u>, default), ins-tag");
+    println!("\t\t\t\t(<ins>), emphasis (_text_) or drop (plain text).");
+    println!("\t  --heading-style=STYLE\tWrite headings as: atx (# Heading, default) or setext");
+    println!("\t\t\t\t(underlined with = and -, for the first two levels).");
+    println!("\t  --shift-headings=N\tMove all headings N levels down (# becomes ## for 1), or");
+    println!("\t\t\t\tup for negative N.");
+    println!("\t  --pandoc-scripts\tWrite ^superscript^ and ~subscript~ instead of <sup>/<sub>.");
+    println!("\t  --html-strikeout\tWrite <del>strikeout</del> instead of ~~strikeout~~.");
+    println!("\t  --no-escape\t\tDon't escape characters which mean something in Markdown.");
+    println!(
+        "\t  --smart-punctuation\tWrite curly quotes and ellipses (…) instead of \" ' and ..."
+    );
+    println!("\t  --smart-dashes\tWrite -- and --- as en and em dashes (– and —).");
+    println!("\t  --soft-hyphens\tKeep soft hyphens (as U+00AD) instead of dropping them.");
+    println!("\t  --unicode-nbsp\tWrite binding spaces as U+00A0 instead of &nbsp;.");
+    println!("\t  --print-codes=FILE\tRead replacements for ^PE/^PQ/^PR/^PW from a TOML file.");
+    println!("\t  --font-spans\t\tWrap text in other fonts into <span style=\"font-family:...\">.");
+    println!("\t  --emit-color\t\tWrap colored text into <span style=\"color:...\">.");
+    println!("\t  --drop-notes\t\tDrop annotations instead of keeping them as HTML comments.");
+    println!("\t  --keep-comments\tWrite comment lines (.. and .ig) as HTML comments.");
+    println!("\t  --preserve-dot-commands\tWrite dot commands which leave no trace in the output");
+    println!("\t\t\t\tas HTML comments (<!-- .lm 5 -->).");
+    println!("\t  --headers=MODE\tWrite .he/.h1-.h5 lines as: heading (default), drop,");
+    println!("\t\t\t\tfront-matter, italic (once at the top).");
+    println!("\t  --footers=MODE\tWrite .fo/.f1-.f9 lines as: drop (default), front-matter,");
+    println!("\t\t\t\titalic (once at the end).");
+    println!("\t  --page-markers\tWrite <!-- page n --> after each page break.");
+    println!("\t  --page-separator=TEXT\tWrite TEXT for page breaks instead of ----.");
+    println!("\t  --honor-conditional-breaks\tTreat conditional page breaks (.cp) as page breaks.");
+    println!("\t  --align=MODE\t\tWrite aligned text as: html (<div>, default), pandoc (:::).");
+    println!("\t  --justify\t\tMark justified text (.oj) as such, not just centered text.");
+    println!("\t  --keep-tabs\t\tKeep tabs instead of expanding them to the ruler's tab stops.");
+    println!("\t  --reflow\t\tJoin hard-wrapped lines into paragraphs.");
+    println!("\t  --wrap\t\tWrap paragraphs at the right margin (.rm, default: 65).");
+    println!("\t  --max-blank-lines=N\tCollapse runs of blank lines to N lines (default: 1).");
+    println!("\t  --trim-spaces\t\tRemove spaces at the end of lines.");
+    println!("\t  --collapse-spaces\tCollapse runs of spaces inside lines (not in code).");
+    println!("\t  --strip-form-feeds\tRemove form feeds left over from page breaks.");
+    println!("\t  --normalize-whitespace\tAll of the three above.");
+    println!("\t  --margins=MODE\tWrite left margins as: nbsp (&nbsp;, default), blockquote (>),");
+    println!("\t\t\t\tindent (four spaces per tab stop), div (<div> with padding).");
+    println!(
+        "\t  --line-spacing=MODE\tLine spacing (.ls): ignore (default), blank (add blank lines,"
+    );
+    println!("\t\t\t\tsee --max-blank-lines), css (<div class=\"line-spacing-N\">).");
+    println!("\t  --layout-metadata\tWrite the page layout (.mt, .mb, .pl, .po, .co, .rm) and");
+    println!("\t\t\t\tpage numbering (.pn, .op, .pg) into the front matter.");
+    println!(
+        "\t  --code-blocks\t\tWrite text in a narrow fixed-pitch character width (.cw, not .ps)"
+    );
+    println!("\t\t\t\tas code blocks.");
+    println!("\t  --fence-drawings\tWrite box drawings and ASCII art as code blocks.");
+    println!("\t  --dot-leaders=MODE\tWrite dot leaders (text ..... page) as they are: keep");
+    println!("\t\t\t\t(default), collapse (see --leader-separator), table.");
+    println!(
+        "\t  --leader-separator=TEXT\tWrite collapsed dot leaders as TEXT (default: \" — \")."
+    );
+    println!("\t  --pitch-code\t\tWrite text in the alternate pitch (^A ... ^N) as `code`.");
+    println!(
+        "\t  --expanded-code=LETTER\tThe print code (E, Q, R, W) which switches expanded print."
+    );
+    println!("\t  --expanded=MODE\tWrite expanded print as: heading (lines of their own, <big>");
+    println!("\t\t\t\totherwise, default), big (<big> always).");
+    println!("\t  --tables\t\tWrite text aligned in columns (3+ lines) as tables.");
+    println!("\t  --merge-fields=ENGINE\tWrite merge fields (&NAME&) as placeholders for:");
+    println!("\t\t\t\thandlebars ({{{{name}}}}), tera, jinja2 ({{{{ name }}}}).");
+    println!("\t  --merge-data=FILE\tPerform the mail merge with FILE (CSV, named by .rv or by");
+    println!("\t\t\t\tits first line), write one document per record.");
+    println!("\t  --var=NAME=VALUE\tUse VALUE for NAME (.av, .sv) when merging, don't ask.");
+    println!(
+        "\t  --inline-includes\tConvert included files (.fi) into the document, don't link them."
+    );
+    println!(
+        "\t  --allow-external-includes\tInclude files from outside of the input file's directory."
+    );
+    println!("\t  --copy-graphics\tCopy inset graphics next to the output file.");
+    println!("\t  --toc=MODE\t\tWrite the table of contents (.tc) at the: top (default),");
+    println!("\t\t\t\tinline (where the first .tc is).");
+    println!("\t  --toc-links\t\tLink table of contents entries to the matching headings.");
+    println!(
+        "\t  --input-format=FORMAT\tRead the input files as: auto (default), wordstar (5 to 7),"
+    );
+    println!("\t\t\t\tws4 (WordStar 3 and 4, NewWord), ws2000 (WordStar 2000, text");
+    println!("\t\t\t\tonly), wsd (WordStar for Windows, text only), nondocument (plain");
+    println!("\t\t\t\ttext).");
+    println!("\t  --codepage=CODEPAGE\tDecode characters above 0x7F as cp437 (default), cp850");
+    println!("\t\t\t\t(Western Europe), cp865 (Nordic), windows-1252 (default for");
+    println!("\t\t\t\tWordStar for Windows), or auto to guess it from the text.");
+    println!("\t  --charmap=FILE\t\tMap single bytes to other text, after the code page (TOML:");
+    println!("\t\t\t\t0xF0 = \"§\", or CSV: 0xF0,§).");
+    println!("\t  --undecodable=POLICY\tWhat to do with bytes which are no characters: replace");
+    println!("\t\t\t\t(with U+FFFD, default), drop, escape (as \\xNN) or error.");
+    println!(
+        "\t  --output-encoding=ENC\tEncode the output as utf-8 (default), latin-1 or one of the"
+    );
+    println!("\t\t\t\tcode pages above. Missing characters become &#xNNNN;.");
+    println!("\t  --bom\t\t\tStart UTF-8 output with a byte order mark.");
+    println!("\t  --newline=NEWLINE\tEnd the output lines with lf (default) or crlf.");
+    println!("\t  --recover\t\tKeep the text of lines which can't be parsed (with a warning)");
+    println!("\t\t\t\tinstead of giving up.");
+    println!("\t  --show-controls\tWrite the control codes and sequences which would be dropped");
+    println!("\t\t\t\tas ^C and \\x1D\\xNN (for finding out what a file contains).");
+    println!("\t  --strict\t\tStop at anything which can't be converted as it is: unknown");
+    println!("\t\t\t\tsequences, undecodable bytes, everything we'd warn about.");
+    println!("\t  --lossy\t\tNever stop, convert what can be converted (implies --recover).");
+    println!("\t  --diagnostics=FORMAT\tWrite warnings and errors as text (default) or json (one");
+    println!("\t\t\t\tobject per line with code, severity, file, message, offset and");
+    println!("\t\t\t\tline).");
+    println!("\t  --diagnostics-file=FILE\tAppend the diagnostics to FILE instead of stderr.");
+    println!("\t  --verbose, -v\t\tTell which version wrote the input files.");
+    println!("\t  --report-ignored\tList the dot commands which were left out, how often and");
+    println!("\t\t\t\twhere first (as notes in the diagnostics).");
+    println!("\t  --header-metadata\tWrite the version and printer from the file header into the");
+    println!("\t\t\t\tfront matter.");
+    println!("\t  --styles=FILE\t\tMap paragraph styles to h1-h6, blockquote, code, paragraph");
+    println!("\t\t\t\t(TOML: \"Style name\" = \"h1\").");
+    println!("\t  --lists\t\tTurn lines starting with -, *, o, 1., a) into Markdown lists.");
+}
+
+/// Returns the path with a number added to the file name: letter.md becomes
+/// letter-1.md.
+fn numbered_path(path: &Path, number: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let file_name = match path.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, number, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, number),
+    };
+    path.with_file_name(file_name)
+}
+
+/// Reads a WordStar file and prepares its text for the parser. Non-documents
+/// are Markdown already.
+fn read_wordstar_file(path: &Path, options: &Options) -> Result<(InputFormat, String)> {
+    // Note that we'll need to disable safe UTF-8 parsing here, because it might
+    // well be that WordStar files contain "invalid" UTF-8.
+    let file_content = fs::read(path).map_err(|source| ConversionError::Io {
+        path: path.to_path_buf(),
+        action: "read",
+        source,
+    })?;
+
+    if matches!(
+        options.input_format,
+        InputFormat::Auto | InputFormat::WordStar
+    ) {
+        if let Some(header_length) = header::truncated_length(&file_content) {
+            let message = format!("the header of {} bytes is cut off", header_length);
+            // There's no text after the header.
+            if options.mode == ConversionMode::Lossy {
+                warn(
+                    options,
+                    Diagnostic::warning(path, "truncated-header", message)
+                        .at_offset(file_content.len()),
+                )?;
+                return Ok((InputFormat::WordStar, String::new()));
+            }
+            return Err(ConversionError::decode(
+                path,
+                &file_content,
+                file_content.len(),
+                message,
+                "--lossy converts what's there, which is nothing",
+            )
+            .into());
+        }
+    }
+
+    let input_format = match options.input_format {
+        InputFormat::Auto if wsd::is_wsd(path) => InputFormat::WordStarWindows,
+        InputFormat::Auto if nondocument::is_nondocument(&file_content) => InputFormat::NonDocument,
+        InputFormat::Auto if ws2000::is_ws2000(&file_content) => InputFormat::WordStar2000,
+        InputFormat::Auto => match header::version(&file_content) {
+            header::Version::WordStar4 => InputFormat::WordStar4,
+            _ => InputFormat::WordStar,
+        },
+        input_format => input_format,
+    };
+    if options.input_format == InputFormat::Auto && input_format == InputFormat::WordStar2000 {
+        warn(
+            options,
+            Diagnostic::warning(
+                path,
+                "guessed-format",
+                "no header and codes WordStar 3 to 7 don't write, so this is taken for WordStar 2000 \
+                 and its formatting is left out; --input-format chooses another format"
+                    .to_string(),
+            ),
+        )?;
+    }
+    if options.verbose {
+        let description = match input_format {
+            InputFormat::WordStar2000 => "WordStar 2000".to_string(),
+            InputFormat::WordStarWindows => "WordStar for Windows".to_string(),
+            InputFormat::NonDocument => "a non-document".to_string(),
+            _ => header::version(&file_content).to_string(),
+        };
+        eprintln!("{}: {}", path.display(), description);
+    }
+    // WordStar for Windows is the only Windows program of the lot.
+    let default_code_page = match input_format {
+        InputFormat::WordStarWindows => CodePage::Windows1252,
+        _ => CodePage::Cp437,
+    };
+    let code_page = match options.code_page {
+        CodePageMode::Default => default_code_page,
+        CodePageMode::Fixed(code_page) => code_page,
+        CodePageMode::Auto => {
+            let mut candidates = vec![default_code_page];
+            candidates.extend(
+                [
+                    CodePage::Cp437,
+                    CodePage::Cp850,
+                    CodePage::Cp865,
+                    CodePage::Windows1252,
+                ]
+                .into_iter()
+                .filter(|&code_page| code_page != default_code_page),
+            );
+            let samples: Vec<u8> = extended_characters(&file_content, input_format)
+                .into_iter()
+                .map(|(_, byte)| byte)
+                .collect();
+            let code_page = codepage::detect(&samples, &candidates);
+            eprintln!(
+                "{}: code page {} ({} extended characters)",
+                path.display(),
+                code_page,
+                samples.len()
+            );
+            code_page
+        }
+    };
+    let decoder = Decoder {
+        code_page,
+        charmap: &options.charmap,
+        undecodable: options.undecodable,
+    };
+    // Binary data in WordStar for Windows files is no text to lose.
+    if options.undecodable == Undecodable::Error && input_format != InputFormat::WordStarWindows {
+        if let Some((offset, byte)) = extended_characters(&file_content, input_format)
+            .into_iter()
+            .find(|&(_, byte)| !decoder.knows(byte))
+        {
+            return Err(ConversionError::decode(
+                path,
+                &file_content,
+                offset,
+                format!("byte 0x{:02X} is not in code page {}", byte, code_page),
+                "try another --codepage, a --charmap or --undecodable=replace",
+            )
+            .into());
+        }
+    }
+    if input_format == InputFormat::NonDocument {
+        return Ok((
+            input_format,
+            nondocument::read_text(&normalize_line_endings(&file_content), &decoder),
+        ));
+    }
+    let file_content = match input_format {
+        InputFormat::WordStar2000 => ws2000::read_text(&file_content),
+        InputFormat::WordStarWindows => wsd::read_text(&file_content, &decoder),
+        // No header, no symmetric sequences, no extended characters.
+        InputFormat::WordStar4 => {
+            let cleared_content = clear_high_bits(&file_content, false, &decoder);
+            if options.show_controls {
+                show_controls(&cleared_content, false)
+            } else {
+                cleared_content
+            }
+        }
+        // Skip the file header, whatever its length.
+        _ => {
+            let header_length = header::header_length(&file_content);
+            let (rewritten_content, skipped_sequences) =
+                sequences::rewrite_sequences(&file_content[header_length..]);
+            // Everything else drops what it doesn't know.
+            if options.mode == ConversionMode::Strict {
+                if let Some(&(offset, sequence_type)) = skipped_sequences.first() {
+                    return Err(ConversionError::decode(
+                        path,
+                        &file_content,
+                        header_length + offset,
+                        match sequence_type {
+                            Some(sequence_type) => {
+                                format!("unknown sequence type 0x{:02X}", sequence_type)
+                            }
+                            None => "broken sequence".to_string(),
+                        },
+                        "without --strict, the sequence is left out",
+                    )
+                    .into());
+                }
+            }
+            let cleared_content = clear_high_bits(&rewritten_content, true, &decoder);
+            if options.show_controls {
+                show_controls(&cleared_content, true)
+            } else {
+                cleared_content
+            }
+        }
+    };
+    // Only now, sequences are binary data.
+    let file_content = normalize_line_endings(&file_content);
+    Ok((
+        input_format,
+        String::from_utf8_lossy(&file_content).into_owned(),
+    ))
+}
+
+/// How deep included files (.fi) may include other files.
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// Returns the path of a file included (.fi) by the last of `files`, if it
+/// may be included. Included files are relative to the including one and
+/// must be in the input file's directory, unless the user allows otherwise.
+fn resolve_include(files: &[PathBuf], name: &str, options: &Options) -> Result<PathBuf> {
+    let including_directory = files
+        .last()
+        .and_then(|file| file.parent())
+        .unwrap_or(Path::new(""));
+    let included_file = fs::canonicalize(including_directory.join(name))
+        .with_context(|| format!("could not find the included file {}", name))?;
+
+    if files.contains(&included_file) {
+        anyhow::bail!(
+            "not including {} again, it includes itself",
+            included_file.display()
+        );
+    }
+    if files.len() > MAX_INCLUDE_DEPTH {
+        anyhow::bail!(
+            "not including {}, too many nested includes",
+            included_file.display()
+        );
+    }
+    let input_directory = files[0].parent().unwrap_or(Path::new(""));
+    if !options.allow_external_includes && !included_file.starts_with(input_directory) {
+        anyhow::bail!(
+            "not including {} from outside of {}",
+            included_file.display(),
+            input_directory.display()
+        );
+    }
+    Ok(included_file)
+}
+
+/// Returns the image of an inset graphic of the last of `files`. The
+/// graphic is copied next to the output file if the user wants that.
+fn graphic_image(files: &[PathBuf], name: &str, options: &Options) -> Result<Inline> {
+    // DOS paths, mostly.
+    let name = name.replace('\\', "/");
+    let file_name = Path::new(&name)
+        .file_name()
+        .map_or(name.clone(), |file_name| {
+            file_name.to_string_lossy().to_string()
+        });
+
+    let Some(graphics_directory) = &options.copy_graphics else {
+        return Ok(Inline::Image {
+            description: file_name,
+            source: name,
+        });
+    };
+    let including_directory = files
+        .last()
+        .and_then(|file| file.parent())
+        .unwrap_or(Path::new(""));
+    if let Err(error) = fs::copy(
+        including_directory.join(&name),
+        graphics_directory.join(&file_name),
+    ) {
+        warn(
+            options,
+            Diagnostic::warning(
+                &files[files.len() - 1],
+                "missing-graphic",
+                format!("could not copy the graphic {}: {}", name, error),
+            ),
+        )?;
+    }
+    Ok(Inline::Image {
+        description: file_name.clone(),
+        source: file_name,
+    })
+}
+
+/// Prepares the text of a WordStar file for the parser: dot commands as
+/// WordStar would have read them, and the lines which can't be parsed
+/// recovered, if the user wants that.
+fn prepare(text: &str, options: &Options, path: &Path) -> Result<String> {
+    let (normalized_text, warnings) = dotcommands::normalize(text, &options.mode, path);
+    for warning in warnings {
+        warn(options, warning)?;
+    }
+    if !options.recover {
+        return Ok(normalized_text);
+    }
+    let (recovered_text, warnings) = recover_text(&normalized_text, path)
+        .map_err(|error| ConversionError::parse(path, &error))?;
+    for warning in warnings {
+        warn(options, warning)?;
+    }
+    Ok(recovered_text)
+}
+
+/// Parses the (prepared) text of a WordStar file.
+fn parse<'a>(text: &'a str, path: &Path) -> Result<Pair<'a, Rule>> {
+    let mut file =
+        WSParser::parse(Rule::file, text).map_err(|error| ConversionError::parse(path, &error))?;
+    Ok(file.next().unwrap())
+}
+
+/// What a document and the files it includes (.fi) have in common: the
+/// headings and what refers to them.
+#[derive(Default)]
+struct Collected {
+    /// Headings (level, text, anchor), for linking to them
+    headings: Vec<(usize, String, String)>,
+    heading_anchors: HashMap<String, usize>,
+    /// Index entries (.ix and ^PK) with the heading they're under, if any
+    index_entries: Vec<(String, Option<usize>)>,
+    /// Table of contents entries (indentation, text) and where they go
+    toc_entries: Vec<(usize, String)>,
+    toc_position: usize,
+}
+
+/// Converts the (pre-processed) text of a WordStar file into a document.
+/// `files` is the file the text comes from, after the files which include
+/// it (.fi), if any.
+fn convert(text: &str, options: &Options, files: &[PathBuf]) -> Result<Document> {
+    convert_part(text, options, files, &mut Collected::default())
+}
+
+/// Converts a document or a file it includes. Only the document itself gets
+/// the index and the table of contents, of everything in `collected`.
+fn convert_part(
+    text: &str,
+    options: &Options,
+    files: &[PathBuf],
+    collected: &mut Collected,
+) -> Result<Document> {
+    let mut left_margin: usize = 0;
+
+    // The paragraph margin (.pm). If it's left of the left margin, paragraphs
+    // have a hanging indent and become list items. If it's right of it, the
+    // first lines of paragraphs are indented.
+    let mut paragraph_margin: Option<usize> = None;
+    let mut ruler = Ruler::default();
+    let mut centered = false;
+    let mut justified = false;
+    let mut alignment = Alignment::Left;
+
+    // The line spacing (.ls)
+    let mut line_spacing: usize = 1;
+
+    // Narrow text (.cw) goes into a code block, unless it's in a
+    // proportional font (.ps) where columns wouldn't line up anyway.
+    let mut character_width = DEFAULT_CHARACTER_WIDTH;
+    let mut proportional = false;
+    let mut narrow_code = false;
+    let mut in_code_block = false;
+
+    // The paragraph style (WordStar 6 and 7), and the one of the last line
+    let mut current_style: Option<String> = None;
+    let mut last_style: Option<String> = None;
+
+    // The left margin of the text, and the containers it's in
+    let mut open_margin: usize = 0;
+    let mut open_containers: Vec<Container> = Vec::new();
+
+    // In reflow mode, a paragraph goes on until a line without text or
+    // anything else than a normal_line.
+    let mut paragraph_open = false;
+    let mut paragraph_kind = LineKind::Paragraph;
+
+    // The right margin (.rm), and the first one for the front matter
+    let mut right_margin: Option<usize> = None;
+    let mut document_right_margin: Option<usize> = None;
+    let mut paragraph_hyphenated = false;
+
+    // The lists we are in, if we look for lists
+    let mut list_nesting = ListNesting::default();
+
+    // The blocks so far, and the modifiers which are on with the text of
+    // the line
+    let mut builder = Builder::default();
+    let mut modifiers = Modifiers::default();
+
+    // Underlines only cover the spaces between words after .ul on.
+    let mut continuous_underline = false;
+
+    // The font and color of the text, if they're not the default ones
+    let mut current_font: Option<String> = None;
+    let mut current_color: Option<String> = None;
+    let mut open_style: Option<String> = None;
+
+    // The text of an index entry (^PK) so far
+    let mut index_mark: Option<String> = None;
+
+    // Everything for the YAML front matter, with already formatted values
+    let mut front_matter: Vec<(String, String)> = Vec::new();
+    let mut running_headers: Vec<String> = Vec::new();
+    let mut running_footers: Vec<String> = Vec::new();
+
+    // Page numbering
+    let mut page_number: usize = 1;
+    let mut first_page_number: Option<usize> = None;
+    let mut print_page_numbers: Option<bool> = None;
+
+    // Mail merge: the data file, the declared variables and the fields
+    // which are used
+    let mut merge_data_file: Option<String> = None;
+    let mut merge_variables: Vec<String> = Vec::new();
+    let mut merge_fields: Vec<String> = Vec::new();
+    // .av and .sv, already formatted for the front matter
+    let mut merge_inputs: Vec<String> = Vec::new();
+
+    // Page layout, as it is at the start of the document
+    let mut page_layout: Vec<(&str, usize)> = Vec::new();
+
+    // Dot commands we left out: name, how often, first line
+    let mut ignored_dot_commands: Vec<(String, usize, usize)> = Vec::new();
+
+    // The file the text comes from, for errors and warnings
+    let path = &files[files.len() - 1];
+    let text = prepare(text, options, path)?;
+    let parser = parse(&text, path)?;
+
+    // Text with the merge fields in it as placeholders
+    let text_inlines = |text: &str| match &options.merge_fields {
+        Some(engine) => merge::split_fields(text, engine),
+        None => vec![Inline::Text(text.to_string())],
+    };
+
+    for record in parser.into_inner() {
+        // DEBUG:
+        // println!("{:#?}", record);
+
+        // Paragraph styles apply to the whole line they're set in.
+        if record.as_rule() == Rule::normal_line {
+            for pair in record.clone().into_inner() {
+                if pair.as_rule() == Rule::style_change {
+                    let style = pair.into_inner().next().unwrap().as_str();
+                    current_style = Some(style.to_string()).filter(|style| !style.is_empty());
+                }
+            }
+        }
+        let paragraph_style = current_style
+            .as_ref()
+            .and_then(|style| options.styles.get(style))
+            .filter(|_| record.as_rule() == Rule::normal_line);
+        let code = if record.as_rule() == Rule::normal_line {
+            narrow_code || paragraph_style == Some(&ParagraphStyle::Code)
+        } else {
+            in_code_block
+        };
+        // A line in expanded print only is a title.
+        let expanded_heading = options.expanded == ExpandedMode::Heading
+            && record.as_rule() == Rule::normal_line
+            && contains_text(&record)
+            && options
+                .expanded_code
+                .is_some_and(|code| expanded_only(&record, code, modifiers.is_on("expanded")));
+        let styled = expanded_heading
+            || matches!(
+                paragraph_style,
+                Some(ParagraphStyle::Heading(_) | ParagraphStyle::Blockquote)
+            );
+        let style_changed = record.as_rule() == Rule::normal_line && current_style != last_style;
+        if record.as_rule() == Rule::normal_line {
+            last_style = current_style.clone();
+        }
+
+        let hanging_indent = paragraph_margin
+            .filter(|_| !code && !styled)
+            .filter(|&margin| {
+                margin < left_margin
+                    && record.as_rule() == Rule::normal_line
+                    && contains_text(&record)
+            });
+        let list_item = if hanging_indent.is_some() {
+            Some(lists::hanging_item(
+                leading_text(&record).unwrap_or_default(),
+            ))
+        } else {
+            leading_text(&record)
+                .filter(|_| {
+                    options.lists && !code && !styled && record.as_rule() == Rule::normal_line
+                })
+                .and_then(lists::list_item)
+        };
+        let line_column = text_column(&record, hanging_indent.unwrap_or(left_margin), &ruler);
+
+        // List items always start a new paragraph, and so does the end of
+        // a list.
+        let continues_paragraph = paragraph_open
+            && !code
+            && !style_changed
+            && !matches!(paragraph_style, Some(ParagraphStyle::Heading(_)))
+            && !expanded_heading
+            && record.as_rule() == Rule::normal_line
+            && contains_text(&record)
+            && list_item.is_none()
+            && (list_nesting.depth() == 0 || list_nesting.is_continuation(line_column));
+        // Modifiers which are still on at the end of a paragraph were most
+        // likely never switched off.
+        let paragraph_ends = (paragraph_open && !continues_paragraph)
+            || record.as_rule() == Rule::header_line
+            || (record.as_rule() == Rule::normal_line && !contains_text(&record));
+        if paragraph_ends {
+            let line = record.as_span().start_pos().line_col().0;
+            close_modifiers(&mut modifiers, line, options, path)?;
+        }
+        if paragraph_open && !continues_paragraph {
+            let content = modifiers.take();
+            finish_line(&mut builder, content, paragraph_kind, options, right_margin);
+            if options.line_spacing == LineSpacingMode::Blank {
+                builder.space(line_spacing - 1);
+            }
+        }
+        paragraph_open = false;
+        if !matches!(
+            record.as_rule(),
+            Rule::normal_line | Rule::dot_command_line | Rule::comment_line
+        ) {
+            list_nesting.clear();
+            builder.end_lists();
+        }
+        if record.as_rule() == Rule::normal_line {
+            if code != in_code_block {
+                builder.end();
+                in_code_block = code;
+            }
+            // Keep differently styled paragraphs apart.
+            if style_changed && !code {
+                builder.space(1);
+            }
+        }
+
+        match record.as_rule() {
+            Rule::header_line if options.headers != HeaderMode::Heading => {
+                // Running headers rather than headings. They're repeated on
+                // every page, so we keep each of them only once.
+                let header_text = record.into_inner().nth(1).unwrap().as_str().trim();
+                if !running_headers.iter().any(|header| header == header_text) {
+                    running_headers.push(header_text.to_string());
+                }
+            }
+            Rule::footer_line => {
+                // Just like running headers.
+                let footer_text = record.into_inner().nth(1).unwrap().as_str().trim();
+                if !running_footers.iter().any(|footer| footer == footer_text) {
+                    running_footers.push(footer_text.to_string());
+                }
+            }
+            Rule::header_line => {
+                // h1 to h5
+                let headline = &mut record.into_inner();
+
+                // headline[0] -> inner -> rule = dot_h1 .. dot_h5
+                let headline_define = headline.next().unwrap().into_inner().peek().unwrap();
+                let level = match headline_define.as_rule() {
+                    Rule::dot_h2 => 2,
+                    Rule::dot_h3 => 3,
+                    Rule::dot_h4 => 4,
+                    Rule::dot_h5 => 5,
+                    _ => 1,
+                };
+
+                // headline[1] -> span -> str = text
+                let headline_text = headline.next().unwrap().as_str();
+                let anchor = heading_anchor(headline_text, &mut collected.heading_anchors);
+                collected
+                    .headings
+                    .push((level, headline_text.trim().to_string(), anchor));
+                merge_fields.extend(merge::field_names(headline_text));
+                builder.push(Block::Heading {
+                    level,
+                    content: text_inlines(typeset(headline_text, options, None).trim()),
+                });
+            }
+            Rule::normal_line if in_code_block => {
+                // Code is written as it is, only tabs are expanded.
+                let mut column = left_margin;
+                let mut line = String::new();
+                for pair in record.into_inner() {
+                    match pair.as_rule() {
+                        Rule::displayed_text => {
+                            column += pair.as_str().chars().count();
+                            line.push_str(pair.as_str());
+                        }
+                        Rule::nonbreaking_space => {
+                            column += 1;
+                            line.push(' ');
+                        }
+                        Rule::overprint => {
+                            column += 1;
+                            let characters = &mut pair.into_inner();
+                            let first = characters.next().unwrap().as_str().chars().next().unwrap();
+                            let second =
+                                characters.next().unwrap().as_str().chars().next().unwrap();
+                            line.push_str(&combine_overprint(first, second));
+                        }
+                        Rule::tab => {
+                            let next_tab_stop = ruler.next_tab_stop(column);
+                            line.push_str(&" ".repeat(next_tab_stop - column));
+                            column = next_tab_stop;
+                        }
+                        Rule::soft_return => {
+                            column = left_margin;
+                            line.push('\n');
+                        }
+                        Rule::font_change => {
+                            let value = pair.into_inner().next().unwrap().as_str().replace('"', "");
+                            current_font = Some(value).filter(|value| !value.is_empty());
+                        }
+                        Rule::color_change => {
+                            let value = pair.into_inner().next().unwrap().as_str().replace('"', "");
+                            current_color = Some(value).filter(|value| !value.is_empty());
+                        }
+                        _ => {}
+                    }
+                }
+                builder.code_line(&line);
+            }
+            Rule::normal_line => {
+                let line_contains_text = contains_text(&record);
+                let record_copy = record.clone();
+
+                // Leading tabs indent the line to the tab stops of the ruler.
+                let line_pairs: Vec<_> = record.into_inner().collect();
+                let leading_tabs = line_pairs
+                    .iter()
+                    .take_while(|pair| pair.as_rule() == Rule::tab)
+                    .count();
+                let mut indentation = hanging_indent.unwrap_or(left_margin);
+                for _ in 0..leading_tabs {
+                    indentation = ruler.next_tab_stop(indentation);
+                }
+
+                // The first line of a paragraph can be indented further.
+                let first_line_indent = paragraph_margin
+                    .filter(|&margin| {
+                        margin > left_margin && line_contains_text && !continues_paragraph
+                    })
+                    .map_or(0, |margin| margin - left_margin);
+
+                // The length of the list marker to skip in the first text
+                let mut list_marker_length = 0;
+                let mut list_continuation = false;
+
+                // A marker on its own is followed by a tab to the left margin.
+                let mut skip_marker_tab = list_item.as_ref().is_some_and(|item| {
+                    item.length > 0
+                        && item.length == leading_text(&record_copy).unwrap_or_default().len()
+                });
+
+                // The level of a heading
+                let mut heading_level = None;
+
+                if continues_paragraph {
+                    // The previous line is part of the same paragraph.
+                    join_lines(modifiers.text_end(), paragraph_hyphenated);
+                } else if expanded_heading {
+                    heading_level = Some(1);
+                } else if let Some(paragraph_style) = paragraph_style.filter(|_| styled) {
+                    // Styled paragraphs don't have margins.
+                    if let ParagraphStyle::Heading(level) = paragraph_style {
+                        heading_level = Some(*level);
+                    } else {
+                        paragraph_kind = LineKind::Quote;
+                    }
+                } else if let Some(item) = &list_item {
+                    list_nesting.add_item(indentation + item.column);
+                    let (ordered, start) = match item.marker {
+                        Marker::Bullet => (false, 1),
+                        Marker::Number(number) => (true, number),
+                    };
+                    builder.list_item(list_nesting.depth(), ordered, start);
+                    paragraph_kind = LineKind::Paragraph;
+                    list_marker_length = item.length;
+                } else if line_contains_text && list_nesting.is_continuation(line_column) {
+                    // Indented text after a list item belongs to it.
+                    paragraph_kind = LineKind::Paragraph;
+                    list_continuation = true;
+                } else {
+                    paragraph_kind = LineKind::Paragraph;
+                    if line_contains_text {
+                        // Everything else ends the list.
+                        if list_nesting.depth() > 0 {
+                            builder.end_lists();
+                            list_nesting.clear();
+                        }
+
+                        // Add left margin where applicable.
+                        open_margin = indentation;
+                        switch_containers(
+                            &mut builder,
+                            &mut open_containers,
+                            containers(options, alignment, line_spacing, open_margin, &ruler),
+                        );
+                        match options.margins {
+                            MarginMode::Nbsp => {
+                                modifiers.push(Inline::Text("\u{a0}".repeat(indentation)))
+                            }
+                            MarginMode::Indent => modifiers
+                                .push(Inline::Text("    ".repeat(ruler.tab_level(indentation)))),
+                            _ => {}
+                        }
+                        modifiers.push(Inline::Text("\u{a0}".repeat(first_line_indent)));
+                    }
+                }
+
+                // Traverse through the inner pairs.
+                // Soft returns join the wrapped lines of a paragraph, so we need
+                // to know whether we're at the start of such a wrapped line.
+                let line_pairs = line_pairs.into_iter().skip(leading_tabs);
+                let mut column = indentation + first_line_indent;
+                // (This is also where list continuations lose their indentation.)
+                let mut after_soft_return = continues_paragraph || list_continuation || styled;
+                let mut after_soft_hyphen = false;
+                for pair in line_pairs {
+                    let rule = pair.as_rule();
+
+                    // Spans are opened right before the text they apply to,
+                    // like the modifiers.
+                    if matches!(
+                        rule,
+                        Rule::displayed_text | Rule::nonbreaking_space | Rule::overprint
+                    ) {
+                        let style = span_style(options, &current_font, &current_color);
+                        switch_span(&mut modifiers, &mut open_style, style);
+                    }
+
+                    match rule {
+                        // Possible rules:
+                        // - displayed_text: just push it
+                        // - allowed_modifiers: format first
+                        // - soft_hyphen: drop or keep as U+00AD
+                        // - nonbreaking_space: U+00A0
+                        // - overprint: combine both characters
+                        // - font_change, color_change: remember for the next text
+                        // - endnote: a note, where its number goes
+                        // - annotation: HTML comment or drop
+                        // - graphic: image
+                        // - soft_return: join with the next line
+                        // - tab: expand to the next tab stop
+                        // - everything else: skip
+                        Rule::displayed_text => {
+                            column += pair.as_str().chars().count();
+
+                            // The list marker is the list's.
+                            let text = &pair.as_str()[list_marker_length..];
+                            list_marker_length = 0;
+
+                            if let Some(index_mark) = &mut index_mark {
+                                index_mark.push_str(text);
+                            }
+
+                            merge_fields.extend(merge::field_names(text));
+                            // Inline code is shown as it is.
+                            let text = match modifiers.is_on("alternate pitch") {
+                                true => text.to_string(),
+                                // Quotes look at what's before them, not at
+                                // the modifiers in between.
+                                false => typeset(text, options, modifiers.last_character()),
+                            };
+
+                            // Indentation of wrapped lines is layout only.
+                            let text = match after_soft_return {
+                                true => text.trim_start(),
+                                false => text.as_str(),
+                            };
+                            if modifiers.is_on("underline") && !continuous_underline {
+                                // Without .ul on, the spaces between the words
+                                // aren't underlined.
+                                let underline = underline_format(options);
+                                let words = text.trim_matches(' ');
+                                let leading_spaces =
+                                    text.len() - text.trim_start_matches(' ').len();
+                                modifiers.write(Inline::Text(text[..leading_spaces].to_string()));
+                                for (index, word) in words.split(' ').enumerate() {
+                                    if index > 0 {
+                                        modifiers.switch("underline", false, underline.clone());
+                                        modifiers.write(Inline::Text(String::from(" ")));
+                                    }
+                                    if !word.is_empty() {
+                                        modifiers.switch("underline", true, underline.clone());
+                                    }
+                                    for inline in text_inlines(word) {
+                                        modifiers.write(inline);
+                                    }
+                                }
+                                let trailing_spaces = &text[leading_spaces + words.len()..];
+                                modifiers.write(Inline::Text(trailing_spaces.to_string()));
+                            } else {
+                                for inline in text_inlines(text) {
+                                    modifiers.write(inline);
+                                }
+                            }
+                        }
+                        Rule::soft_hyphen if options.soft_hyphens => {
+                            modifiers.write(Inline::Text(String::from("\u{ad}")))
+                        }
+                        Rule::index_mark => match index_mark.take() {
+                            Some(entry) if !entry.trim().is_empty() => {
+                                collected.index_entries.push((
+                                    entry.split_whitespace().collect::<Vec<_>>().join(" "),
+                                    collected.headings.len().checked_sub(1),
+                                ));
+                            }
+                            Some(_) => {}
+                            None => index_mark = Some(String::new()),
+                        },
+                        Rule::tab if skip_marker_tab => column = left_margin,
+                        Rule::tab => {
+                            let next_tab_stop = ruler.next_tab_stop(column);
+                            if options.keep_tabs {
+                                modifiers.push(Inline::Text(String::from("\t")));
+                            } else {
+                                modifiers.push(Inline::Text(" ".repeat(next_tab_stop - column)));
+                            }
+                            column = next_tab_stop;
+                        }
+                        Rule::nonbreaking_space => {
+                            column += 1;
+                            modifiers.write(Inline::Text(String::from("\u{a0}")));
+                        }
+                        Rule::overprint => {
+                            column += 1;
+                            let characters = &mut pair.into_inner();
+                            let first = characters.next().unwrap().as_str().chars().next().unwrap();
+                            let second =
+                                characters.next().unwrap().as_str().chars().next().unwrap();
+                            modifiers.write(Inline::Text(combine_overprint(first, second)));
+                        }
+                        Rule::font_change | Rule::color_change => {
+                            let value = pair.into_inner().next().unwrap().as_str().replace('"', "");
+                            let value = Some(value).filter(|value| !value.is_empty());
+                            if rule == Rule::font_change {
+                                current_font = value;
+                            } else {
+                                current_color = value;
+                            }
+                        }
+                        Rule::endnote => {
+                            let endnote = pair.into_inner().next().unwrap().as_str();
+                            modifiers.push(Inline::Note(text_inlines(endnote)));
+                        }
+                        Rule::annotation if !options.drop_notes => {
+                            // "--" would end the comment early.
+                            let annotation = pair.into_inner().next().unwrap().as_str();
+                            modifiers.push(Inline::Html(format!(
+                                "<!-- note: {} -->",
+                                annotation.replace("--", "- -")
+                            )));
+                        }
+                        Rule::graphic => {
+                            let graphic = pair.into_inner().next().unwrap().as_str();
+                            modifiers.write(graphic_image(files, graphic, options)?);
+                        }
+                        Rule::soft_return => {
+                            column = left_margin;
+                            join_lines(modifiers.text_end(), after_soft_hyphen);
+                        }
+                        Rule::allowed_modifiers => {
+                            let modifier_pairs = &mut pair.into_inner();
+                            for modifier_pair in modifier_pairs {
+                                match modifier_pair.as_rule() {
+                                    // Possible rules:
+                                    // - bold_modifier
+                                    // - doublestrike_modifier
+                                    // - italics_modifier
+                                    // - underline_modifier
+                                    // - superscript_modifier
+                                    // - subscript_modifier
+                                    // - strikeout_modifier
+                                    // - user_print_code
+                                    // - alternate_pitch, normal_pitch
+                                    Rule::bold_modifier => modifiers.toggle("bold", Format::Bold),
+                                    // Double-strike was the dot-matrix way of writing bold text.
+                                    Rule::doublestrike_modifier => {
+                                        modifiers.toggle("double-strike", Format::Bold)
+                                    }
+                                    Rule::italics_modifier => {
+                                        modifiers.toggle("italics", Format::Italic)
+                                    }
+                                    Rule::underline_modifier => {
+                                        modifiers.toggle("underline", underline_format(options))
+                                    }
+                                    Rule::superscript_modifier => {
+                                        modifiers.toggle("superscript", Format::Superscript)
+                                    }
+                                    Rule::subscript_modifier => {
+                                        modifiers.toggle("subscript", Format::Subscript)
+                                    }
+                                    Rule::strikeout_modifier => {
+                                        modifiers.toggle("strikeout", Format::Strikeout)
+                                    }
+                                    // Alternate pitch is dropped unless it's code.
+                                    Rule::alternate_pitch | Rule::normal_pitch
+                                        if options.pitch_code =>
+                                    {
+                                        modifiers.switch(
+                                            "alternate pitch",
+                                            modifier_pair.as_rule() == Rule::alternate_pitch,
+                                            Format::Code,
+                                        );
+                                    }
+                                    Rule::user_print_code
+                                        if options.expanded_code
+                                            == Some(print_code_letter(modifier_pair.as_str())) =>
+                                    {
+                                        // Headings are big enough.
+                                        let format = if expanded_heading {
+                                            Format::Plain
+                                        } else {
+                                            Format::Html(
+                                                String::from("<big>"),
+                                                String::from("</big>"),
+                                            )
+                                        };
+                                        modifiers.toggle("expanded", format);
+                                    }
+                                    Rule::user_print_code => {
+                                        let letter = print_code_letter(modifier_pair.as_str());
+                                        // Unmapped print codes are dropped.
+                                        if let Some((open, close)) =
+                                            options.print_codes.get(&letter)
+                                        {
+                                            let name = match letter {
+                                                'E' => "^PE",
+                                                'Q' => "^PQ",
+                                                'R' => "^PR",
+                                                _ => "^PW",
+                                            };
+                                            modifiers.toggle(
+                                                name,
+                                                Format::Html(open.clone(), close.clone()),
+                                            );
+                                        }
+                                    }
+                                    _ => {}
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                    after_soft_return = rule == Rule::soft_return;
+                    after_soft_hyphen = rule == Rule::soft_hyphen;
+                    skip_marker_tab = skip_marker_tab && rule == Rule::displayed_text;
+                }
+
+                // In reflow mode, the next line might belong to this paragraph.
+                let paragraph_continues =
+                    options.reflow && line_contains_text && heading_level.is_none();
+
+                // Spans don't cross lines.
+                switch_span(&mut modifiers, &mut open_style, None);
+
+                if let Some(level) = heading_level {
+                    let content = modifiers.take();
+                    let heading_text = plain_text(&content).trim().to_string();
+                    let anchor = heading_anchor(&heading_text, &mut collected.heading_anchors);
+                    collected.headings.push((level, heading_text, anchor));
+                    builder.push(Block::Heading { level, content });
+                } else if paragraph_continues {
+                    paragraph_open = true;
+                    paragraph_hyphenated = after_soft_hyphen;
+                } else {
+                    let content = modifiers.take();
+                    finish_line(&mut builder, content, paragraph_kind, options, right_margin);
+                    if options.line_spacing == LineSpacingMode::Blank && line_contains_text {
+                        builder.space(line_spacing - 1);
+                    }
+                }
+            }
+            Rule::comment_line if options.keep_comments => {
+                // Comments can be empty.
+                let comment = record.into_inner().next();
+                let comment = comment.as_ref().map_or("", |pair| pair.as_str().trim());
+                builder.push(Block::Comment(comment.to_string()));
+            }
+            Rule::page_break => {
+                // Page breaks which WordStar inserted itself.
+                page_number += 1;
+                if options.page_markers {
+                    builder.space(1);
+                    builder.push(Block::Comment(format!("page {}", page_number)));
+                    builder.space(1);
+                }
+            }
+            Rule::dot_command_line => {
+                // Right now, these are either one of the allowed_dot_commands
+                // or can safely be discarded. There can be only one inner
+                // allowed_dot_command per line due to how they are structured.
+                let dot_pair = &mut record.into_inner().next().unwrap();
+                // Whatever doesn't change the output, unknown dot commands
+                // included, can be kept as a comment.
+                let dot_command_text = dot_pair.as_str().trim().to_string();
+                let changes = builder.changes();
+                if dot_pair.as_rule() == Rule::allowed_dot_commands {
+                    let dot_command = dot_pair.clone().into_inner().next().unwrap();
+                    match dot_command.as_rule() {
+                        // Currently possible: dot_centering, dot_justification,
+                        // dot_insert_file, dot_left_margin, dot_paragraph_margin,
+                        // dot_right_margin, dot_line_spacing, dot_page_layout,
+                        // dot_columns, dot_column_break, dot_character_width,
+                        // dot_proportional_spacing, dot_continuous_underline,
+                        // dot_ask_variable, dot_data_file, dot_read_variables, dot_math,
+                        // dot_index, dot_toc, dot_if, dot_else, dot_end_if, dot_ruler,
+                        // dot_page_break and the page numbering.
+                        Rule::dot_centering | Rule::dot_justification => {
+                            // ".oc" and ".oc on" start centering, ".oc off" ends it.
+                            // Same for justification (".oj").
+                            let rule = dot_command.as_rule();
+                            let on = dot_command
+                                .into_inner()
+                                .next()
+                                .is_none_or(|value| value.as_str().eq_ignore_ascii_case("on"));
+                            if rule == Rule::dot_centering {
+                                centered = on;
+                            } else {
+                                justified = on;
+                            }
+
+                            // Centering wins over justification.
+                            alignment = if centered {
+                                Alignment::Center
+                            } else if justified && options.justify {
+                                Alignment::Justify
+                            } else {
+                                Alignment::Left
+                            };
+                            switch_containers(
+                                &mut builder,
+                                &mut open_containers,
+                                containers(options, alignment, line_spacing, open_margin, &ruler),
+                            );
+                        }
+                        Rule::dot_data_file => {
+                            // ".df file, delimiter": we only want the file.
+                            let data_file_command = dot_command.into_inner().next().unwrap();
+                            merge_data_file = merge::arguments(data_file_command.as_str())
+                                .into_iter()
+                                .next()
+                                .filter(|data_file| !data_file.is_empty());
+                        }
+                        Rule::dot_read_variables => {
+                            // ".rv NAME, ADDRESS"
+                            let variables_command = dot_command.into_inner().next().unwrap();
+                            for argument in merge::arguments(variables_command.as_str()) {
+                                merge::declare(&mut merge_variables, &argument);
+                            }
+                        }
+                        Rule::dot_ask_variable | Rule::dot_set_variable => {
+                            // Inputs of the template: ".av "prompt", NAME, length"
+                            // and ".sv NAME, default"
+                            let rule = dot_command.as_rule();
+                            let variable_command =
+                                dot_command.into_inner().next().unwrap().as_str();
+                            let input = if rule == Rule::dot_ask_variable {
+                                merge::ask_variable(variable_command).map(|(variable, prompt)| {
+                                    (variable, prompt.map(|prompt| ("prompt", prompt)))
+                                })
+                            } else {
+                                merge::set_variable(variable_command)
+                                    .map(|(variable, value)| (variable, Some(("default", value))))
+                            };
+                            if let Some((variable, setting)) = input {
+                                merge::declare(&mut merge_variables, &variable);
+                                let mut fields = vec![format!("name: {}", yaml_quote(&variable))];
+                                if let Some((key, value)) = setting {
+                                    fields.push(format!("{}: {}", key, yaml_quote(&value)));
+                                }
+                                merge_inputs.push(format!("{{{}}}", fields.join(", ")));
+                            }
+                        }
+                        Rule::dot_math => {
+                            // Calculations can only be done when merging, but they
+                            // declare a variable.
+                            let math = dot_command.into_inner().next().unwrap().as_str();
+                            let (variable, calculation) = merge::split_math(math);
+                            merge_fields.extend(merge::field_names(calculation));
+                            merge::declare(&mut merge_variables, variable);
+                            builder.push(merge::math_comment(math, options.merge_fields.as_ref()));
+                        }
+                        Rule::dot_index => {
+                            // The entry isn't in the text, only in the index.
+                            let entry = dot_command.into_inner().next().unwrap().as_str().trim();
+                            if !entry.is_empty() {
+                                let heading = collected.headings.len().checked_sub(1);
+                                collected.index_entries.push((entry.to_string(), heading));
+                            }
+                        }
+                        Rule::dot_toc => {
+                            // ".tc   Entry #": the indentation is the level, # is
+                            // the page number.
+                            let entry = dot_command.into_inner().next().unwrap().as_str();
+                            let text = entry.trim().trim_end_matches('#').trim_end();
+                            if !text.is_empty() {
+                                if collected.toc_entries.is_empty()
+                                    && options.toc == TocMode::Inline
+                                    && files.len() == 1
+                                {
+                                    // The contents go between the paragraphs.
+                                    builder.end();
+                                    collected.toc_position = builder.position();
+                                }
+                                let indentation = entry.len() - entry.trim_start().len();
+                                collected.toc_entries.push((indentation, text.to_string()));
+                            }
+                        }
+                        Rule::dot_if => {
+                            let condition = dot_command.into_inner().next().unwrap().as_str();
+                            merge_fields.extend(merge::field_names(condition));
+                            builder.push(merge::if_block(condition, options.merge_fields.as_ref()));
+                        }
+                        Rule::dot_else => {
+                            builder.push(merge::else_block(options.merge_fields.as_ref()));
+                        }
+                        Rule::dot_end_if => {
+                            builder.push(merge::end_if_block(options.merge_fields.as_ref()));
+                        }
+                        Rule::dot_insert_file => {
+                            // This requires a file name.
+                            let insert_file_command = dot_command.into_inner().next();
+                            if let Some(value) = insert_file_command {
+                                let included = match options
+                                    .inline_includes
+                                    .then(|| {
+                                        let included_file =
+                                            resolve_include(files, value.as_str().trim(), options)?;
+                                        let (included_format, included_text) =
+                                            read_wordstar_file(&included_file, options)?;
+                                        anyhow::Ok((included_file, included_format, included_text))
+                                    })
+                                    .transpose()
+                                {
+                                    Ok(included) => included,
+                                    Err(error) => {
+                                        warn(
+                                            options,
+                                            Diagnostic::warning(
+                                                path,
+                                                "include-failed",
+                                                format!("{:#}", error),
+                                            ),
+                                        )?;
+                                        None
+                                    }
+                                };
+                                if let Some((included_file, included_format, included_text)) =
+                                    included
+                                {
+                                    let included_files = [files, &[included_file]].concat();
+                                    let had_toc = !collected.toc_entries.is_empty();
+                                    let included_blocks =
+                                        if included_format == InputFormat::NonDocument {
+                                            nondocument::blocks(&included_text)
+                                        } else {
+                                            convert_part(
+                                                &included_text,
+                                                options,
+                                                &included_files,
+                                                collected,
+                                            )?
+                                            .blocks
+                                        };
+                                    builder.space(1);
+                                    // The first .tc was in there.
+                                    if !had_toc
+                                        && !collected.toc_entries.is_empty()
+                                        && options.toc == TocMode::Inline
+                                        && files.len() == 1
+                                    {
+                                        collected.toc_position = builder.position();
+                                    }
+                                    for block in included_blocks {
+                                        builder.push(block);
+                                    }
+                                } else {
+                                    // Otherwise, insert the file as a link.
+                                    let name = Path::new(value.as_str())
+                                        .file_name()
+                                        .and_then(OsStr::to_str)
+                                        .unwrap_or(value.as_str());
+                                    builder.push(Block::Paragraph(vec![Inline::Link {
+                                        content: vec![Inline::Text(name.to_string())],
+                                        target: value.as_str().to_string(),
+                                    }]));
+                                }
+                            }
+                        }
+                        Rule::dot_left_margin => {
+                            // This can either come with a number (set margin) or without
+                            // one (reset margin). We shall simulate it with a number of
+                            // non-breaking spaces (set left_margin).
+                            let left_margin_command = dot_command.into_inner().next();
+                            if let Some(value) = left_margin_command {
+                                left_margin = margin(value.as_str(), left_margin);
+                            } else {
+                                left_margin = 0;
+                            }
+                        }
+                        Rule::dot_character_width | Rule::dot_proportional_spacing => {
+                            if dot_command.as_rule() == Rule::dot_character_width {
+                                // Without a number, it's the default width.
+                                character_width = dot_command
+                                    .into_inner()
+                                    .next()
+                                    .and_then(|value| usize::from_str(value.as_str()).ok())
+                                    .unwrap_or(DEFAULT_CHARACTER_WIDTH);
+                            } else {
+                                // ".ps" and ".ps on" switch to proportional spacing.
+                                proportional = dot_command
+                                    .into_inner()
+                                    .next()
+                                    .is_none_or(|value| value.as_str().eq_ignore_ascii_case("on"));
+                            }
+
+                            // Narrow fixed-pitch text is usually a table or a listing.
+                            narrow_code = options.code_blocks
+                                && !proportional
+                                && character_width < DEFAULT_CHARACTER_WIDTH;
+                            let style_code = current_style
+                                .as_ref()
+                                .and_then(|style| options.styles.get(style))
+                                == Some(&ParagraphStyle::Code);
+                            if in_code_block != (narrow_code || style_code) {
+                                builder.end();
+                                in_code_block = narrow_code || style_code;
+                            }
+                        }
+                        Rule::dot_line_spacing => {
+                            // Without a number (or with a silly one), it's single spacing.
+                            line_spacing = dot_command
+                                .into_inner()
+                                .next()
+                                .and_then(|value| usize::from_str(value.as_str()).ok())
+                                .filter(|&spacing| spacing > 0)
+                                .unwrap_or(1);
+                            switch_containers(
+                                &mut builder,
+                                &mut open_containers,
+                                containers(options, alignment, line_spacing, open_margin, &ruler),
+                            );
+                        }
+                        Rule::dot_right_margin => {
+                            // Without a number, it's reset.
+                            let current = right_margin.unwrap_or(DEFAULT_RIGHT_MARGIN);
+                            right_margin = dot_command
+                                .into_inner()
+                                .next()
+                                .map(|value| margin(value.as_str(), current));
+                            document_right_margin = document_right_margin.or(right_margin);
+                        }
+                        Rule::dot_paragraph_margin => {
+                            // Same as above, without a number it's reset.
+                            let current = paragraph_margin.unwrap_or(left_margin);
+                            paragraph_margin = dot_command
+                                .into_inner()
+                                .next()
+                                .map(|value| margin(value.as_str(), current));
+                        }
+                        Rule::dot_ruler => {
+                            // Rulers set the left margin and the tab stops.
+                            let ruler_command = dot_command.into_inner().next().unwrap();
+                            ruler = Ruler::parse(ruler_command.as_str());
+                            left_margin = ruler.left_margin;
+                            paragraph_margin = ruler.paragraph_margin;
+                            if ruler.right_margin.is_some() {
+                                right_margin = ruler.right_margin;
+                                document_right_margin = document_right_margin.or(right_margin);
+                            }
+                        }
+                        Rule::dot_conditional_page_break if !options.honor_conditional_breaks => {
+                            count_dot_command(&mut ignored_dot_commands, &dot_command)
+                        }
+                        Rule::dot_continuous_underline => {
+                            // ".ul" and ".ul on" underline the spaces, too, ".ul off" stops that.
+                            continuous_underline = dot_command
+                                .into_inner()
+                                .next()
+                                .is_none_or(|value| value.as_str().eq_ignore_ascii_case("on"));
+                        }
+                        Rule::dot_page_break | Rule::dot_conditional_page_break => {
+                            // We can't really mirror page breaks in Markdown.
+                            // Let's add a horizontal rule (or what the user wants) instead.
+                            builder.space(1);
+                            builder.push(Block::Rule);
+                            builder.space(1);
+
+                            page_number += 1;
+                            if options.page_markers {
+                                builder.push(Block::Comment(format!("page {}", page_number)));
+                                builder.space(1);
+                            }
+                        }
+                        Rule::dot_page_number => {
+                            // Sets the number of the current page. Without a
+                            // number, it does nothing for us.
+                            let page_number_command = dot_command.into_inner().next();
+                            if let Some(value) = page_number_command {
+                                page_number = usize::from_str(value.as_str()).unwrap_or(1);
+                                first_page_number.get_or_insert(page_number);
+                            }
+                        }
+                        Rule::dot_page_layout => {
+                            // We keep the first value of every setting.
+                            let layout_command = &mut dot_command.into_inner();
+                            let key = match layout_command.next().unwrap().as_str() {
+                                "mt" => "top-margin",
+                                "mb" => "bottom-margin",
+                                "pl" => "page-length",
+                                _ => "page-offset",
+                            };
+                            let value = layout_command
+                                .next()
+                                .and_then(|value| usize::from_str(value.as_str()).ok());
+                            if let Some(value) = value {
+                                if !page_layout.iter().any(|&(setting, _)| setting == key) {
+                                    page_layout.push((key, value));
+                                }
+                            }
+                        }
+                        Rule::dot_columns => {
+                            // The text is in reading order already, only the
+                            // layout is worth keeping.
+                            let settings = &mut dot_command
+                                .into_inner()
+                                .filter_map(|value| usize::from_str(value.as_str()).ok());
+                            for (key, value) in ["columns", "column-gap"].into_iter().zip(settings)
+                            {
+                                if !page_layout.iter().any(|&(setting, _)| setting == key) {
+                                    page_layout.push((key, value));
+                                }
+                            }
+                        }
+                        Rule::dot_column_break => {
+                            // The next column starts a new paragraph.
+                            builder.space(1);
+                        }
+                        Rule::dot_page_number_print => print_page_numbers = Some(true),
+                        Rule::dot_page_number_omit => print_page_numbers = Some(false),
+                        _ => {}
+                    }
+                } else {
+                    count_dot_command(&mut ignored_dot_commands, dot_pair);
+                }
+                if options.preserve_dot_commands && builder.changes() == changes {
+                    builder.push(Block::Comment(dot_command_text));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    close_modifiers(&mut modifiers, text.lines().count(), options, path)?;
+    if options.report_ignored {
+        for (name, count, line) in ignored_dot_commands {
+            report(
+                options,
+                &Diagnostic {
+                    severity: Severity::Note,
+                    ..Diagnostic::warning(
+                        path,
+                        "ignored-dot-command",
+                        match count {
+                            1 => format!("{} was left out", name),
+                            _ => format!("{} was left out {} times, first here", name, count),
+                        },
+                    )
+                    .at_line(line)
+                },
+            )?;
+        }
+    }
+    if paragraph_open {
+        let content = modifiers.take();
+        finish_line(&mut builder, content, paragraph_kind, options, right_margin);
+    }
+
+    // Don't leave the containers open.
+    switch_containers(&mut builder, &mut open_containers, Vec::new());
+    let mut blocks = builder.finish();
+
+    // The sections are about the whole document, included files and all.
+    if files.len() == 1 && !collected.index_entries.is_empty() {
+        let headings = &collected.headings;
+        // Alphabetized, with links to the headings the entries are under
+        let mut index: Vec<(String, Vec<usize>)> = Vec::new();
+        let mut index_entries = std::mem::take(&mut collected.index_entries);
+        index_entries.sort_by_key(|(entry, _)| entry.to_lowercase());
+        for (entry, heading) in index_entries {
+            if index
+                .last()
+                .is_none_or(|(last, _)| !last.eq_ignore_ascii_case(&entry))
+            {
+                index.push((entry, Vec::new()));
+            }
+            let headings_of_entry = &mut index.last_mut().unwrap().1;
+            if let Some(heading) = heading.filter(|heading| !headings_of_entry.contains(heading)) {
+                headings_of_entry.push(heading);
+            }
+        }
+
+        let items = index
+            .into_iter()
+            .map(|(entry, headings_of_entry)| {
+                let mut content = vec![Inline::Text(entry)];
+                for (number, &heading) in headings_of_entry.iter().enumerate() {
+                    let separator = if number == 0 { ": " } else { ", " };
+                    content.push(Inline::Text(separator.to_string()));
+                    content.push(Inline::Link {
+                        content: vec![Inline::Text(headings[heading].1.clone())],
+                        target: format!("#{}", headings[heading].2),
+                    });
+                }
+                vec![Block::Paragraph(content)]
+            })
+            .collect();
+        blocks.extend(section("Index", items));
+    }
 
-    println!("\tws2markdown: a WordStar to Markdown converter.");
-    println!(
-        "\tUsage: {0} [inputfile.ws] [outputfile.md]",
-        exe_name.as_deref().unwrap_or("ws2markdown")
-    );
-    println!("\tIf inputfile = empty, a file picker will appear.");
-    println!("\tIf outputfile = empty, the output will be printed to stdout.");
+    if files.len() == 1 && !collected.toc_entries.is_empty() {
+        let toc_entries = &collected.toc_entries;
+        let headings = &collected.headings;
+        // The levels are the different indentations.
+        let mut indentations: Vec<usize> = toc_entries
+            .iter()
+            .map(|(indentation, _)| *indentation)
+            .collect();
+        indentations.sort();
+        indentations.dedup();
+
+        let mut toc = Builder::default();
+        for (indentation, text) in toc_entries {
+            let level = indentations.binary_search(indentation).unwrap();
+            let heading = headings
+                .iter()
+                .find(|(_, heading_text, _)| heading_text.eq_ignore_ascii_case(text))
+                .filter(|_| options.toc_links);
+            let entry = match heading {
+                Some((_, _, anchor)) => Inline::Link {
+                    content: vec![Inline::Text(text.to_string())],
+                    target: format!("#{}", anchor),
+                },
+                None => Inline::Text(text.to_string()),
+            };
+            toc.list_item(level + 1, false, 1);
+            toc.paragraph_line(vec![entry]);
+        }
+        let toc = match toc.finish().pop() {
+            Some(Block::List { items, .. }) => items,
+            _ => Vec::new(),
+        };
+        let mut toc = section("Contents", toc);
+        toc.push(Block::Space(1));
+        let toc_position = collected.toc_position;
+        blocks.splice(toc_position..toc_position, toc);
+    }
+
+    // Only the input file's header is about the document.
+    if options.header_metadata && files.len() == 1 {
+        let file_content = fs::read(&files[0]).unwrap_or_default();
+        for (key, value) in header::metadata(&file_content) {
+            front_matter.push((format!("wordstar-{}", key), yaml_quote(&value)));
+        }
+    }
+    if options.headers == HeaderMode::FrontMatter && !running_headers.is_empty() {
+        front_matter.push(("header".to_string(), yaml_list(&running_headers)));
+    }
+    if let Some(first_page_number) = first_page_number.filter(|_| options.layout_metadata) {
+        front_matter.push((
+            "page-number-start".to_string(),
+            first_page_number.to_string(),
+        ));
+    }
+    if let Some(print_page_numbers) = print_page_numbers.filter(|_| options.layout_metadata) {
+        front_matter.push(("page-numbers".to_string(), print_page_numbers.to_string()));
+    }
+    if let Some(document_right_margin) = document_right_margin.filter(|_| options.layout_metadata) {
+        front_matter.push((
+            "right-margin".to_string(),
+            document_right_margin.to_string(),
+        ));
+    }
+    if options.layout_metadata && !page_layout.is_empty() {
+        let settings: Vec<String> = page_layout
+            .iter()
+            .map(|(setting, value)| format!("{}: {}", setting, value))
+            .collect();
+        front_matter.push((
+            "wordstar".to_string(),
+            format!("{{{}}}", settings.join(", ")),
+        ));
+    }
+    if let Some(merge_data_file) = &merge_data_file {
+        front_matter.push(("merge-data-file".to_string(), yaml_quote(merge_data_file)));
+    }
+    if !merge_inputs.is_empty() {
+        front_matter.push((
+            "merge-inputs".to_string(),
+            format!("[{}]", merge_inputs.join(", ")),
+        ));
+    }
+    if !merge_variables.is_empty() {
+        front_matter.push(("merge-variables".to_string(), yaml_list(&merge_variables)));
+
+        // Fields which are never declared are probably typos.
+        let mut undeclared_fields: Vec<&String> = merge_fields
+            .iter()
+            .filter(|field| {
+                !merge_variables
+                    .iter()
+                    .any(|variable| variable.eq_ignore_ascii_case(field))
+            })
+            .collect();
+        undeclared_fields.sort();
+        undeclared_fields.dedup();
+        for field in undeclared_fields {
+            warn(
+                options,
+                Diagnostic::warning(
+                    path,
+                    "undeclared-merge-field",
+                    format!("merge field &{}& is not declared", field),
+                ),
+            )?;
+        }
+    }
+    if options.footers == FooterMode::FrontMatter && !running_footers.is_empty() {
+        front_matter.push(("footer".to_string(), yaml_list(&running_footers)));
+    }
+    if options.footers == FooterMode::Italic {
+        for footer in &running_footers {
+            blocks.push(Block::Space(1));
+            blocks.push(Block::Paragraph(vec![Inline::Italic(vec![Inline::Text(
+                footer.clone(),
+            )])]));
+        }
+    }
+    if options.headers == HeaderMode::Italic {
+        let headers = running_headers.iter().map(|header| {
+            Block::Paragraph(vec![Inline::Italic(vec![Inline::Text(header.clone())])])
+        });
+        blocks.splice(0..0, headers);
+    }
+
+    if options.fence_drawings {
+        postprocess::fence_drawings(&mut blocks);
+    }
+    match options.dot_leaders {
+        DotLeaderMode::Keep => {}
+        DotLeaderMode::Collapse => {
+            let separator = options.leader_separator.as_deref().unwrap_or(" — ");
+            postprocess::collapse_leaders(&mut blocks, separator);
+        }
+        DotLeaderMode::Table => postprocess::tabulate_leaders(&mut blocks),
+    }
+    if options.tables {
+        postprocess::format_tables(&mut blocks);
+    }
+    normalize_whitespace(&mut blocks, options);
+
+    // Included files are part of another document.
+    Ok(Document {
+        metadata: if files.len() == 1 {
+            front_matter
+        } else {
+            Vec::new()
+        },
+        blocks,
+        right_margin: document_right_margin,
+    })
+}
+
+/// A section at the end of the document: a heading and a list.
+fn section(title: &str, items: Vec<Vec<Block>>) -> Vec<Block> {
+    vec![
+        Block::Space(1),
+        Block::Heading {
+            level: 2,
+            content: vec![Inline::Text(title.to_string())],
+        },
+        Block::Space(1),
+        Block::List {
+            ordered: false,
+            start: 1,
+            items,
+        },
+    ]
+}
+
+/// What underlined text becomes: underlined, unless the user drops it.
+fn underline_format(options: &Options) -> Format {
+    match options.underline {
+        Some(UnderlineMode::Drop) => Format::Plain,
+        _ => Format::Underline,
+    }
+}
+
+/// Removes the horizontal whitespace the user doesn't want.
+fn normalize_whitespace(blocks: &mut [Block], options: &Options) {
+    if options.strip_form_feeds {
+        postprocess::strip_form_feeds(blocks);
+    }
+    if options.collapse_spaces {
+        postprocess::collapse_spaces(blocks);
+    }
+    if options.trim_spaces {
+        postprocess::trim_trailing_spaces(blocks);
+    }
+}
+
+/// Returns the absolute path of the input file `name`.
+fn find_file(name: &str) -> Result<PathBuf> {
+    fs::canonicalize(name).map_err(|source| {
+        ConversionError::Io {
+            path: PathBuf::from(name),
+            action: "find",
+            source,
+        }
+        .into()
+    })
 }
 
 fn main() -> Result<()> {
-    let args: Vec<String> = env::args().collect();
+    // Everything starting with a dash is a switch, the rest are file names.
+    let (switches, args): (Vec<String>, Vec<String>) =
+        env::args().partition(|arg| arg.starts_with('-'));
 
     // Common usage: ws2markdown <input file> <output file>.
     let inputfile: Option<PathBuf>;
@@ -61,12 +2950,269 @@ fn main() -> Result<()> {
     let mut output_to_stdout = false;
 
     // Output options
-    let mut left_margin: usize = 0;
-
-    if args.len() > 1 && (&args[1] == "--help" || &args[1] == "-h") {
-        // Print usage information.
-        show_usage();
-        return Ok(());
+    let mut options = Options::default();
+    for switch in &switches {
+        // Switches with a value are written as --switch=value.
+        let (name, value) = match switch.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (switch.as_str(), None),
+        };
+        match (name, value) {
+            ("--help" | "-h", None) => {
+                // Print usage information.
+                show_usage();
+                return Ok(());
+            }
+            ("--flavor", Some(flavor)) => {
+                options.flavor = Some(match flavor {
+                    "commonmark" => Flavor::CommonMark,
+                    "gfm" => Flavor::Gfm,
+                    "multimarkdown" | "mmd" => Flavor::MultiMarkdown,
+                    "pandoc" => Flavor::Pandoc,
+                    _ => anyhow::bail!("unknown Markdown flavor: {}", flavor),
+                })
+            }
+            ("--underline", Some(mode)) => {
+                options.underline = Some(match mode {
+                    "u-tag" => UnderlineMode::UTag,
+                    "ins-tag" => UnderlineMode::InsTag,
+                    "emphasis" => UnderlineMode::Emphasis,
+                    "drop" => UnderlineMode::Drop,
+                    _ => anyhow::bail!("unknown underline mode: {}", mode),
+                })
+            }
+            ("--heading-style", Some(style)) => {
+                options.heading_style = match style {
+                    "atx" => HeadingStyle::Atx,
+                    "setext" => HeadingStyle::Setext,
+                    _ => anyhow::bail!("unknown heading style: {}", style),
+                }
+            }
+            ("--shift-headings", Some(number)) => {
+                options.shift_headings = isize::from_str(number)
+                    .with_context(|| format!("not a number of levels: {}", number))?
+            }
+            ("--pandoc-scripts", None) => options.pandoc_scripts = true,
+            ("--html-strikeout", None) => options.html_strikeout = true,
+            ("--soft-hyphens", None) => options.soft_hyphens = true,
+            ("--unicode-nbsp", None) => options.unicode_nbsp = true,
+            ("--font-spans", None) => options.font_spans = true,
+            ("--emit-color", None) => options.emit_color = true,
+            ("--drop-notes", None) => options.drop_notes = true,
+            ("--keep-comments", None) => options.keep_comments = true,
+            ("--preserve-dot-commands", None) => options.preserve_dot_commands = true,
+            ("--headers", Some(mode)) => {
+                options.headers = match mode {
+                    "heading" => HeaderMode::Heading,
+                    "drop" => HeaderMode::Drop,
+                    "front-matter" => HeaderMode::FrontMatter,
+                    "italic" => HeaderMode::Italic,
+                    _ => anyhow::bail!("unknown header mode: {}", mode),
+                }
+            }
+            ("--footers", Some(mode)) => {
+                options.footers = match mode {
+                    "drop" => FooterMode::Drop,
+                    "front-matter" => FooterMode::FrontMatter,
+                    "italic" => FooterMode::Italic,
+                    _ => anyhow::bail!("unknown footer mode: {}", mode),
+                }
+            }
+            ("--page-markers", None) => options.page_markers = true,
+            ("--page-separator", Some(separator)) => {
+                options.page_separator = Some(separator.to_string())
+            }
+            ("--honor-conditional-breaks", None) => options.honor_conditional_breaks = true,
+            ("--align", Some(mode)) => {
+                options.align = match mode {
+                    "html" => AlignMode::Html,
+                    "pandoc" => AlignMode::Pandoc,
+                    _ => anyhow::bail!("unknown alignment mode: {}", mode),
+                }
+            }
+            ("--margins", Some(mode)) => {
+                options.margins = match mode {
+                    "nbsp" => MarginMode::Nbsp,
+                    "blockquote" => MarginMode::Blockquote,
+                    "indent" => MarginMode::Indent,
+                    "div" => MarginMode::Div,
+                    _ => anyhow::bail!("unknown margin mode: {}", mode),
+                }
+            }
+            ("--line-spacing", Some(mode)) => {
+                options.line_spacing = match mode {
+                    "ignore" => LineSpacingMode::Ignore,
+                    "blank" => LineSpacingMode::Blank,
+                    "css" => LineSpacingMode::Css,
+                    _ => anyhow::bail!("unknown line spacing mode: {}", mode),
+                }
+            }
+            ("--justify", None) => options.justify = true,
+            ("--keep-tabs", None) => options.keep_tabs = true,
+            ("--reflow", None) => options.reflow = true,
+            ("--wrap", None) => options.wrap = true,
+            ("--lists", None) => options.lists = true,
+            ("--layout-metadata", None) => options.layout_metadata = true,
+            ("--code-blocks", None) => options.code_blocks = true,
+            ("--tables", None) => options.tables = true,
+            ("--pitch-code", None) => options.pitch_code = true,
+            ("--expanded-code", Some(letter)) => {
+                options.expanded_code = match letter.to_uppercase().as_str() {
+                    "E" => Some('E'),
+                    "Q" => Some('Q'),
+                    "R" => Some('R'),
+                    "W" => Some('W'),
+                    _ => anyhow::bail!("not a user print code: {}", letter),
+                }
+            }
+            ("--expanded", Some(mode)) => {
+                options.expanded = match mode {
+                    "heading" => ExpandedMode::Heading,
+                    "big" => ExpandedMode::Big,
+                    _ => anyhow::bail!("unknown expanded print mode: {}", mode),
+                }
+            }
+            ("--dot-leaders", Some(mode)) => {
+                options.dot_leaders = match mode {
+                    "keep" => DotLeaderMode::Keep,
+                    "collapse" => DotLeaderMode::Collapse,
+                    "table" => DotLeaderMode::Table,
+                    _ => anyhow::bail!("unknown dot leader mode: {}", mode),
+                }
+            }
+            ("--leader-separator", Some(separator)) => {
+                options.leader_separator = Some(separator.to_string())
+            }
+            ("--fence-drawings", None) => options.fence_drawings = true,
+            ("--inline-includes", None) => options.inline_includes = true,
+            ("--toc", Some(mode)) => {
+                options.toc = match mode {
+                    "top" => TocMode::Top,
+                    "inline" => TocMode::Inline,
+                    _ => anyhow::bail!("unknown table of contents mode: {}", mode),
+                }
+            }
+            ("--toc-links", None) => options.toc_links = true,
+            ("--to", Some(format)) => {
+                options.output_format = match format {
+                    "markdown" | "md" => OutputFormat::Markdown,
+                    "org" => OutputFormat::Org,
+                    "text" | "txt" => OutputFormat::Text,
+                    "docx" => OutputFormat::Docx,
+                    "epub" => OutputFormat::Epub,
+                    "pdf" => OutputFormat::Pdf,
+                    "pandoc-json" => OutputFormat::PandocJson,
+                    "json" => OutputFormat::Json,
+                    "djot" | "dj" => OutputFormat::Djot,
+                    _ => anyhow::bail!("unknown output format: {}", format),
+                }
+            }
+            ("--pdf-engine", Some(engine)) => options.pdf_engine = Some(engine.to_string()),
+            ("--verbose" | "-v", None) => options.verbose = true,
+            ("--report-ignored", None) => options.report_ignored = true,
+            ("--no-escape", None) => options.no_escape = true,
+            ("--smart-punctuation", None) => options.smart_punctuation = true,
+            ("--smart-dashes", None) => options.smart_dashes = true,
+            ("--header-metadata", None) => options.header_metadata = true,
+            ("--input-format", Some(format)) => {
+                options.input_format = match format {
+                    "auto" => InputFormat::Auto,
+                    "wordstar" => InputFormat::WordStar,
+                    "ws4" => InputFormat::WordStar4,
+                    "nondocument" => InputFormat::NonDocument,
+                    "ws2000" => InputFormat::WordStar2000,
+                    "wsd" => InputFormat::WordStarWindows,
+                    _ => anyhow::bail!("unknown input format: {}", format),
+                }
+            }
+            ("--codepage", Some(code_page)) => {
+                options.code_page = match CodePage::from_name(code_page) {
+                    _ if code_page == "auto" => CodePageMode::Auto,
+                    Some(code_page) => CodePageMode::Fixed(code_page),
+                    None => anyhow::bail!("unknown code page: {}", code_page),
+                }
+            }
+            ("--output-encoding", Some(encoding)) => {
+                options.output_encoding = match encoding.to_lowercase().as_str() {
+                    "utf-8" | "utf8" => OutputEncoding::Utf8,
+                    "latin-1" | "latin1" | "iso-8859-1" => OutputEncoding::Latin1,
+                    _ => match CodePage::from_name(encoding) {
+                        Some(code_page) => OutputEncoding::CodePage(code_page),
+                        None => anyhow::bail!("unknown output encoding: {}", encoding),
+                    },
+                }
+            }
+            ("--bom", None) => options.bom = true,
+            ("--newline", Some(newline)) => {
+                options.newline = match newline {
+                    "lf" => Newline::Lf,
+                    "crlf" => Newline::Crlf,
+                    _ => anyhow::bail!("unknown line ending: {}", newline),
+                }
+            }
+            ("--allow-external-includes", None) => options.allow_external_includes = true,
+            ("--copy-graphics", None) => options.copy_graphics = Some(PathBuf::new()),
+            ("--merge-data", Some(file)) => options.merge_data = Some(PathBuf::from(file)),
+            ("--var", Some(assignment)) => {
+                let (variable, value) = assignment
+                    .split_once('=')
+                    .with_context(|| format!("not a variable assignment: {}", assignment))?;
+                options
+                    .variables
+                    .insert(variable.to_uppercase(), value.to_string());
+            }
+            ("--merge-fields", Some(engine)) => {
+                options.merge_fields = Some(match engine {
+                    "handlebars" => TemplateEngine::Handlebars,
+                    "tera" => TemplateEngine::Tera,
+                    "jinja2" => TemplateEngine::Jinja2,
+                    _ => anyhow::bail!("unknown template engine: {}", engine),
+                })
+            }
+            ("--max-blank-lines", Some(number)) => {
+                options.max_blank_lines = Some(
+                    usize::from_str(number)
+                        .with_context(|| format!("not a number of lines: {}", number))?,
+                )
+            }
+            ("--recover", None) => options.recover = true,
+            ("--show-controls", None) => options.show_controls = true,
+            ("--strict", None) => options.mode = ConversionMode::Strict,
+            ("--lossy", None) => options.mode = ConversionMode::Lossy,
+            ("--diagnostics", Some(format)) => {
+                options.diagnostics = match format {
+                    "text" => DiagnosticsFormat::Text,
+                    "json" => DiagnosticsFormat::Json,
+                    _ => anyhow::bail!("unknown diagnostics format: {}", format),
+                }
+            }
+            ("--diagnostics-file", Some(file)) => {
+                options.diagnostics_file = Some(PathBuf::from(file))
+            }
+            ("--trim-spaces", None) => options.trim_spaces = true,
+            ("--collapse-spaces", None) => options.collapse_spaces = true,
+            ("--strip-form-feeds", None) => options.strip_form_feeds = true,
+            ("--normalize-whitespace", None) => {
+                options.trim_spaces = true;
+                options.collapse_spaces = true;
+                options.strip_form_feeds = true;
+            }
+            ("--styles", Some(file)) => options.styles = read_styles(Path::new(file))?,
+            ("--charmap", Some(file)) => options.charmap = read_charmap(Path::new(file))?,
+            ("--undecodable", Some(policy)) => {
+                options.undecodable = match policy {
+                    "replace" => Undecodable::Replace,
+                    "drop" => Undecodable::Drop,
+                    "escape" => Undecodable::Escape,
+                    "error" => Undecodable::Error,
+                    _ => anyhow::bail!("unknown policy for undecodable bytes: {}", policy),
+                }
+            }
+            ("--print-codes", Some(file)) => {
+                options.print_codes = read_print_codes(Path::new(file))?
+            }
+            _ => anyhow::bail!("unknown option: {}", switch),
+        }
     }
 
     if args.len() < 3 {
@@ -74,7 +3220,7 @@ fn main() -> Result<()> {
         if args.len() < 2 {
             // Ask for the input file.
             inputfile = FileDialog::new()
-                .add_filter("WordStar File", &["ws", "ws5", "ws6", "ws7"])
+                .add_filter("WordStar File", &["ws", "ws5", "ws6", "ws7", "wsd"])
                 .set_directory("/")
                 .pick_file();
 
@@ -85,156 +3231,535 @@ fn main() -> Result<()> {
             }
         } else {
             // Input is there, output is missing.
-            inputfile = Some(fs::canonicalize(&args[1])?);
+            inputfile = Some(find_file(&args[1])?);
         }
 
         // If no output file was specified: output to stdout.
         output_to_stdout = true;
     } else {
         // Both input and output are there.
-        inputfile = Some(fs::canonicalize(&args[1])?);
+        inputfile = Some(find_file(&args[1])?);
 
         // We cannot assume the outputfile to be there yet.
         // -> Don't use canonicalize.
         outputfile = Some(path::absolute(&args[2])?);
     }
 
-    // Read the input file into a string and pass it to the parser.
-    // Note that we'll need to disable safe UTF-8 parsing here, because it might
-    // well be that WordStar files contain "invalid" UTF-8.
-    let mut file_content = Vec::new();
-    let mut file = File::open(inputfile.unwrap()).expect("Unable to open file");
-    file.read_to_end(&mut file_content).expect("Unable to read");
-
-    let file_content_string = String::from_utf8_lossy(&file_content);
+    // What the flavor has is what the switches would do.
+    match options.flavor {
+        Some(Flavor::CommonMark | Flavor::MultiMarkdown) => options.html_strikeout = true,
+        Some(Flavor::Pandoc) => options.pandoc_scripts = true,
+        _ => {}
+    }
 
-    // The first 128 characters are reserved for the file header.
-    let parser = WSParser::parse(Rule::file, &file_content_string[128..])
-        .expect("invalid WordStar file!")
-        .next()
-        .unwrap();
+    match options.mode {
+        ConversionMode::Strict if options.recover => {
+            anyhow::bail!("--strict and --recover don't go together")
+        }
+        ConversionMode::Strict => options.undecodable = Undecodable::Error,
+        ConversionMode::Lossy if options.undecodable == Undecodable::Error => {
+            anyhow::bail!("--lossy and --undecodable=error don't go together")
+        }
+        ConversionMode::Lossy => options.recover = true,
+        ConversionMode::Default => {}
+    }
+    if options.bom && options.output_encoding != OutputEncoding::Utf8 {
+        anyhow::bail!("--bom needs UTF-8 output");
+    }
+    if matches!(
+        options.output_format,
+        OutputFormat::Docx | OutputFormat::Epub | OutputFormat::Pdf
+    ) && output_to_stdout
+    {
+        anyhow::bail!("--to=docx, --to=epub and --to=pdf need an output file");
+    }
+    // Graphics are copied next to the output file.
+    if options.copy_graphics.is_some() {
+        match outputfile.as_ref().and_then(|file| file.parent()) {
+            Some(directory) if !output_to_stdout => {
+                options.copy_graphics = Some(directory.to_path_buf())
+            }
+            _ => anyhow::bail!("--copy-graphics needs an output file"),
+        }
+    }
 
-    // Output:
-    let mut output_string: String = String::from("");
-    for record in parser.into_inner() {
-        // DEBUG:
-        // println!("{:#?}", record);
-        match record.as_rule() {
-            Rule::header_line => {
-                // h1 to h5
-                let headline = &mut record.into_inner();
+    // Read the input file into a string and pass it to the parser.
+    let files = [inputfile.unwrap()];
+    match run(&files, outputfile, output_to_stdout, &options) {
+        // Errors go where the warnings go.
+        Err(error)
+            if options.diagnostics == DiagnosticsFormat::Json
+                || options.diagnostics_file.is_some() =>
+        {
+            let diagnostic = match error.downcast_ref::<ConversionError>() {
+                Some(error) => error.diagnostic(),
+                None => Diagnostic {
+                    severity: Severity::Error,
+                    ..Diagnostic::warning(&files[0], "error", format!("{:#}", error))
+                },
+            };
+            report(&options, &diagnostic)?;
+            process::exit(1);
+        }
+        result => result,
+    }
+}
 
-                // headline[0] -> inner -> rule = dot_h1 .. dot_h5
-                let headline_define = headline.next().unwrap().into_inner().peek().unwrap();
-                match headline_define.as_rule() {
-                    Rule::dot_h1 => output_string.push_str("# "),
-                    Rule::dot_h2 => output_string.push_str("## "),
-                    Rule::dot_h3 => output_string.push_str("### "),
-                    Rule::dot_h4 => output_string.push_str("#### "),
-                    Rule::dot_h5 => output_string.push_str("##### "),
-                    _ => {}
-                }
-                // headline[1] -> span -> str = text
-                let headline_text = headline.next().unwrap();
-                output_string.push_str(headline_text.as_str());
+/// Turns the document converted from `path` into the output format, encoded
+/// for writing.
+fn render(document: &Document, path: &Path, options: &Options) -> Result<Vec<u8>> {
+    if options.output_format == OutputFormat::Markdown {
+        return Ok(encode_output(&markdown::render(document, options), options));
+    }
 
-                output_string.push('\n');
+    // Pandoc has notes of its own, the other formats have them at the end.
+    let mut document = document.clone();
+    if options.output_format != OutputFormat::PandocJson {
+        document::endnotes(&mut document.blocks);
+    }
+    let title = path.file_stem().unwrap_or_default().to_string_lossy();
+    let output = match options.output_format {
+        OutputFormat::Org => org::render(&document),
+        OutputFormat::Djot => djot::render(&document),
+        OutputFormat::Text => {
+            // Wrapped at .rm, if there was one.
+            let width = document.right_margin.unwrap_or(DEFAULT_RIGHT_MARGIN);
+            text::render(&document, options.wrap.then_some(width))
+        }
+        OutputFormat::PandocJson => pandoc::render(&document),
+        // These aren't text.
+        OutputFormat::Docx => {
+            let (docx, left_out) = docx::render(&document);
+            for html in left_out {
+                warn(
+                    options,
+                    Diagnostic::warning(
+                        path,
+                        "html-left-out",
+                        format!("Word has no place for the HTML {}, leaving it out", html),
+                    ),
+                )?;
             }
-            Rule::normal_line => {
-                // Add left margin where applicable.
-                output_string.push_str(&"&nbsp;".repeat(left_margin));
+            return Ok(docx);
+        }
+        OutputFormat::Epub => return Ok(epub::render(&document, &title)),
+        OutputFormat::Pdf => {
+            let html = html::render(&document, &title);
+            return pdf::render(&html, options.pdf_engine.as_deref());
+        }
+        // The parse is all there is to JSON, see run().
+        OutputFormat::Markdown | OutputFormat::Json => unreachable!(),
+    };
+    Ok(encode_output(&output, options))
+}
 
-                // Traverse through the inner pairs.
-                let line_pairs = &mut record.into_inner();
-                for pair in line_pairs {
-                    match pair.as_rule() {
-                        // Possible rules:
-                        // - displayed_text: just push it
-                        // - allowed_modifiers: format first
-                        // - everything else: skip
-                        Rule::displayed_text => output_string.push_str(pair.as_str()),
-                        Rule::allowed_modifiers => {
-                            let modifier_pairs = &mut pair.into_inner();
-                            for modifier_pair in modifier_pairs {
-                                match modifier_pair.as_rule() {
-                                    // Possible rules:
-                                    // - bold_modifier
-                                    // - italics_modifier
-                                    // - underline_modifier
-                                    Rule::bold_modifier => output_string.push_str("**"),
-                                    Rule::italics_modifier => output_string.push('*'),
-                                    Rule::underline_modifier => output_string.push_str("__"),
-                                    _ => {}
-                                }
-                            }
-                        }
-                        _ => {}
-                    }
-                }
+/// Converts the file (the last one of `files`) and writes the output.
+fn run(
+    files: &[PathBuf],
+    outputfile: Option<PathBuf>,
+    output_to_stdout: bool,
+    options: &Options,
+) -> Result<()> {
+    let (input_format, file_content_string) = read_wordstar_file(&files[0], options)?;
+    let path = &files[0];
 
-                output_string.push('\n');
-            }
-            Rule::dot_command_line => {
-                // Right now, these are either one of the allowed_dot_commands
-                // or can safely be discarded. There can be only one inner
-                // allowed_dot_command per line due to how they are structured.
-                let dot_pair = &mut record.into_inner().next().unwrap();
-                if dot_pair.as_rule() == Rule::allowed_dot_commands {
-                    let dot_command = dot_pair.clone().into_inner().next().unwrap();
-                    match dot_command.as_rule() {
-                        // Currently possible: dot_insert_file, dot_left_margin, dot_page_break
-                        Rule::dot_insert_file => {
-                            // This requires a file name.
-                            let insert_file_command = dot_command.into_inner().next();
-                            if let Some(value) = insert_file_command {
-                                // Insert the file as a link.
-                                let file_link = format!(
-                                    "\n[{0}]({1})\n",
-                                    Path::new(value.as_str())
-                                        .file_name()
-                                        .unwrap()
-                                        .to_str()
-                                        .unwrap(),
-                                    value.as_str()
-                                );
-                                output_string.push_str(&file_link);
-                            }
-                        }
-                        Rule::dot_left_margin => {
-                            // This can either come with a number (set margin) or without
-                            // one (reset margin). We shall simulate it with a number of
-                            // non-breaking spaces (set left_margin).
-                            let left_margin_command = dot_command.into_inner().next();
-                            if let Some(value) = left_margin_command {
-                                left_margin = usize::from_str(value.as_str()).unwrap_or(0);
-                            } else {
-                                left_margin = 0;
-                            }
-                        }
-                        Rule::dot_page_break => {
-                            // We can't really mirror page breaks in Markdown.
-                            // Let's add a horizontal rule instead.
-                            output_string.push_str("\n----\n\n");
-                        }
-                        _ => {}
-                    }
+    // In merge mode, there's one document per data record.
+    let texts: Vec<String> = match &options.merge_data {
+        Some(data_file) if input_format != InputFormat::NonDocument => {
+            merge::read_data_file(data_file, &file_content_string)?
+                .iter()
+                .map(|record| merge::merge_record(&file_content_string, record, &options.variables))
+                .collect()
+        }
+        _ => vec![file_content_string],
+    };
+
+    let outputs: Vec<Vec<u8>> = if options.output_format == OutputFormat::Json {
+        // The parse is all there is to --to=json. Non-documents are just text.
+        texts
+            .iter()
+            .map(|text| {
+                let json = if input_format == InputFormat::NonDocument {
+                    json::render_text(text, path)
+                } else {
+                    let text = prepare(text, options, path)?;
+                    json::render(parse(&text, path)?.into_inner(), path)
+                };
+                Ok(encode_output(&json, options))
+            })
+            .collect::<Result<_>>()?
+    } else {
+        let mut documents: Vec<Document> = texts
+            .iter()
+            .map(|text| {
+                if input_format == InputFormat::NonDocument {
+                    let mut blocks = nondocument::blocks(text);
+                    normalize_whitespace(&mut blocks, options);
+                    Ok(Document {
+                        metadata: Vec::new(),
+                        blocks,
+                        right_margin: None,
+                    })
+                } else {
+                    convert(text, options, files)
                 }
+            })
+            .collect::<Result<_>>()?;
+        if output_to_stdout && documents.len() > 1 {
+            // Merged documents are printed one after the other, separated
+            // like pages.
+            let mut merged = Document {
+                metadata: documents[0].metadata.clone(),
+                blocks: Vec::new(),
+                right_margin: documents[0].right_margin,
+            };
+            for (index, document) in documents.drain(..).enumerate() {
+                if index > 0 {
+                    merged
+                        .blocks
+                        .extend([Block::Space(1), Block::Rule, Block::Space(1)]);
+                }
+                merged.blocks.extend(document.blocks);
             }
-            _ => {}
+            documents.push(merged);
         }
-    }
+        // Rendering can fail (PDF), which shouldn't leave an empty file.
+        documents
+            .iter()
+            .map(|document| render(document, path, options))
+            .collect::<Result<_>>()?
+    };
 
     if output_to_stdout {
         // print the output
-        println!("{}", output_string);
+        for output in outputs {
+            std::io::stdout()
+                .write_all(&output)
+                .context("could not write the output")?;
+        }
     } else {
-        // write the output to our output file
-        let mut outputfile_handle =
-            File::create(outputfile.unwrap()).expect("could not create the output file");
-        outputfile_handle
-            .write_all(output_string.as_bytes())
-            .expect("could not write to the output file");
+        // write the output to our output file(s)
+        let outputfile = outputfile.unwrap();
+        for (index, output) in outputs.iter().enumerate() {
+            let outputfile = if options.merge_data.is_some() {
+                numbered_path(&outputfile, index + 1)
+            } else {
+                outputfile.clone()
+            };
+            let io_error = |action| {
+                let path = outputfile.clone();
+                move |source| ConversionError::Io {
+                    path,
+                    action,
+                    source,
+                }
+            };
+            let mut outputfile_handle = File::create(&outputfile).map_err(io_error("create"))?;
+            outputfile_handle
+                .write_all(output)
+                .map_err(io_error("write"))?;
+        }
         println!("Done.");
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn converted(text: &str, options: &Options) -> String {
+        let document = convert(text, options, &[PathBuf::from("test.ws")]).unwrap();
+        markdown::render(&document, options)
+    }
+
+    #[test]
+    fn lines_stay_apart_without_reflow() {
+        let options = Options::default();
+        assert_eq!(
+            converted("First line\r\nsecond line\r\n", &options),
+            "First line\nsecond line\n"
+        );
+    }
+
+    #[test]
+    fn reflow_joins_paragraph_lines() {
+        let options = Options {
+            reflow: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            converted(
+                "First line\r\nsecond line\r\n\r\nNext paragraph\r\n",
+                &options
+            ),
+            "First line second line\n\nNext paragraph\n"
+        );
+    }
+
+    #[test]
+    fn reflow_joins_hyphenated_words() {
+        let options = Options {
+            reflow: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            converted("A hyphen\u{1f}\r\nated word\r\n", &options),
+            "A hyphenated word\n"
+        );
+    }
+
+    #[test]
+    fn reflow_keeps_headings_apart() {
+        let options = Options {
+            reflow: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            converted(".h1 Heading\r\nText\r\nmore text\r\n", &options),
+            "# Heading\nText more text\n"
+        );
+    }
+
+    #[test]
+    fn underlines_skip_the_spaces_between_words() {
+        let options = Options::default();
+        assert_eq!(
+            converted("\u{13}one two  three\u{13} four\r\n", &options),
+            "<u>one</u> <u>two</u>  <u>three</u> four\n"
+        );
+    }
+
+    #[test]
+    fn continuous_underlines_cover_the_spaces() {
+        let options = Options::default();
+        assert_eq!(
+            converted(
+                ".ul on\r\n\u{13}one two\u{13}\r\n.ul off\r\n\u{13}one two\u{13}\r\n",
+                &options
+            ),
+            "<u>one two</u>\n<u>one</u> <u>two</u>\n"
+        );
+    }
+
+    #[test]
+    fn quotes_look_past_the_tags_before_them() {
+        let options = Options {
+            smart_punctuation: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            converted("\u{13}\"hello\"\u{13} and \u{14}'x'\u{14}\r\n", &options),
+            "<u>\u{201c}hello\u{201d}</u> and <sup>\u{2018}x\u{2019}</sup>\n"
+        );
+    }
+
+    #[test]
+    fn index_entries_are_escaped() {
+        let options = Options::default();
+        assert_eq!(
+            converted(".h1 Big_Deal\r\n.ix *ptr\r\n.ix a_b\r\n", &options),
+            "# Big\\_Deal\n\n## Index\n\n- \\*ptr: [Big\\_Deal](#big_deal)\n- a\\_b: [Big\\_Deal](#big_deal)\n"
+        );
+    }
+
+    #[test]
+    fn unknown_dot_commands_are_left_out() {
+        let options = Options::default();
+        assert_eq!(
+            converted(".uj on\r\nText\r\n.bp\r\n.xl 3, 4\r\n", &options),
+            "Text\n"
+        );
+        let mut ignored = Vec::new();
+        for line in [".uj on\n", ".bp\n", ".UJ off\n"] {
+            let line = WSParser::parse(Rule::dot_command_line, line)
+                .unwrap()
+                .next()
+                .unwrap();
+            count_dot_command(&mut ignored, &line.into_inner().next().unwrap());
+        }
+        assert_eq!(
+            ignored,
+            [(".uj".to_string(), 2, 1), (".bp".to_string(), 1, 1)]
+        );
+    }
+
+    #[test]
+    fn unknown_dot_commands_are_preserved() {
+        let options = Options {
+            preserve_dot_commands: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            converted(".uj on\r\nText\r\n.xl 3, 4\r\n", &options),
+            "<!-- .uj on -->\nText\n<!-- .xl 3, 4 -->\n"
+        );
+    }
+
+    #[test]
+    fn plain_text_is_wrapped_at_the_right_margin() {
+        let options = Options {
+            output_format: OutputFormat::Text,
+            wrap: true,
+            ..Default::default()
+        };
+        // (Without wrapping the document already.)
+        let document = convert(
+            ".rm 10\r\none two three four\r\n",
+            &Options {
+                wrap: false,
+                ..Default::default()
+            },
+            &[PathBuf::from("test.ws")],
+        )
+        .unwrap();
+        let text = render(&document, Path::new("test.ws"), &options).unwrap();
+        assert_eq!(String::from_utf8(text).unwrap(), "one two\nthree four\n");
+    }
+
+    #[test]
+    fn yaml_values_are_quoted() {
+        assert_eq!(yaml_quote("Chapter \"One\""), "\"Chapter \\\"One\\\"\"");
+        assert_eq!(yaml_quote("C:\\WS"), "\"C:\\\\WS\"");
+        assert_eq!(
+            yaml_list(&[String::from("One"), String::from("Two")]),
+            "[\"One\", \"Two\"]"
+        );
+        assert_eq!(yaml_list(&[]), "[]");
+    }
+
+    #[test]
+    fn header_modes() {
+        let text = ".he Report\r\nText\r\n.he Report\r\n";
+        let converted_with = |headers| {
+            converted(
+                text,
+                &Options {
+                    headers,
+                    ..Default::default()
+                },
+            )
+        };
+        assert_eq!(converted_with(HeaderMode::Drop), "Text\n");
+        // Running headers are repeated on every page, but written once.
+        assert_eq!(
+            converted_with(HeaderMode::FrontMatter),
+            "---\nheader: [\"Report\"]\n---\n\nText\n"
+        );
+        assert_eq!(converted_with(HeaderMode::Italic), "*Report*\n\nText\n");
+    }
+
+    #[test]
+    fn footer_lines() {
+        for (line, text) in [
+            (".fo Page #\n", "Page #"),
+            (".f2 Second footer\n", "Second footer"),
+        ] {
+            let footer = WSParser::parse(Rule::footer_line, line)
+                .unwrap()
+                .next()
+                .unwrap();
+            assert_eq!(footer.into_inner().nth(1).unwrap().as_str().trim(), text);
+        }
+        // A footer needs its text.
+        assert!(WSParser::parse(Rule::footer_line, ".fo\n").is_err());
+    }
+
+    #[test]
+    fn footer_modes() {
+        let text = ".fo Page #\r\nText\r\n";
+        let converted_with = |footers| {
+            converted(
+                text,
+                &Options {
+                    footers,
+                    ..Default::default()
+                },
+            )
+        };
+        assert_eq!(converted_with(FooterMode::Drop), "Text\n");
+        assert_eq!(
+            converted_with(FooterMode::FrontMatter),
+            "---\nfooter: [\"Page #\"]\n---\n\nText\n"
+        );
+        assert_eq!(converted_with(FooterMode::Italic), "Text\n\n*Page #*\n");
+    }
+
+    #[test]
+    fn joined_lines_get_one_space() {
+        let mut text = String::from("First line  ");
+        join_lines(&mut text, false);
+        assert_eq!(text, "First line ");
+    }
+
+    #[test]
+    fn hyphenated_lines_are_joined_without_a_space() {
+        let mut text = String::from("hyphen");
+        join_lines(&mut text, true);
+        assert_eq!(text, "hyphen");
+
+        // Compound words keep their hyphen.
+        let mut text = String::from("well-");
+        join_lines(&mut text, false);
+        assert_eq!(text, "well-");
+
+        // Dashes don't.
+        let mut text = String::from("one -");
+        join_lines(&mut text, false);
+        assert_eq!(text, "one - ");
+    }
+
+    #[test]
+    fn pandoc_keeps_its_notes() {
+        let options = Options {
+            output_format: OutputFormat::PandocJson,
+            ..Options::default()
+        };
+        let document = convert(
+            "See\u{1d}endnote:there\u{1d}.\r\n",
+            &options,
+            &[PathBuf::from("test.ws")],
+        )
+        .unwrap();
+        let json = render(&document, Path::new("test.ws"), &options).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.contains(
+            "{\"t\":\"Note\",\"c\":[{\"t\":\"Para\",\"c\":[{\"t\":\"Str\",\"c\":\"there\"}]}]}"
+        ));
+        assert!(!json.contains("Notes"));
+    }
+
+    #[test]
+    fn included_files_share_the_index() {
+        let directory = std::env::temp_dir().join(format!("ws2markdown-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let directory = fs::canonicalize(directory).unwrap();
+        let main = directory.join("main.ws");
+        fs::write(
+            directory.join("part.ws"),
+            ".h1 Part\r\nTwo\u{1d}endnote:b\u{1d}\r\n.ix second\r\n",
+        )
+        .unwrap();
+        let options = Options {
+            inline_includes: true,
+            ..Options::default()
+        };
+        let document = convert(
+            ".h1 Main\r\nOne\u{1d}endnote:a\u{1d}\r\n.ix first\r\n.fi part.ws\r\n.h1 Part\r\n",
+            &options,
+            &[main],
+        )
+        .unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        // One index and one list of notes, numbered through.
+        assert_eq!(
+            markdown::render(&document, &options),
+            "# Main\nOne<sup><a id=\"endnote-ref-1\"></a>[1](#endnote-1)</sup>\n\n\
+             # Part\nTwo<sup><a id=\"endnote-ref-2\"></a>[2](#endnote-2)</sup>\n# Part\n\n\
+             ## Index\n\n- first: [Main](#main)\n- second: [Part](#part)\n\n\
+             ## Notes\n\n\
+             1. <a id=\"endnote-1\"></a>a [↩](#endnote-ref-1)\n\
+             2. <a id=\"endnote-2\"></a>b [↩](#endnote-ref-2)\n"
+        );
+    }
+}
@@ -0,0 +1,597 @@
+/* ----- CDDL HEADER -----
+ *
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.1 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * https://spdx.org/licenses/CDDL-1.1.html
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ *
+ * ----- CDDL HEADER END -----
+ */
+
+// Markdown output, in the flavor the user wants. WordStar's lines stay
+// apart, and so do its blank lines, as far as Markdown lets them.
+
+use crate::document::{endnotes, plain_text, Block, Document, Inline};
+use crate::escape::{escape_block_marker, escape_inline};
+use crate::{html, Flavor, HeadingStyle, Options, UnderlineMode};
+
+/// How underlined text starts and ends. Markdown has no underline, and __
+/// is bold almost everywhere.
+fn underline_markers(options: &Options) -> (&'static str, &'static str) {
+    match (options.underline, options.flavor) {
+        (Some(UnderlineMode::InsTag), _) | (None, Some(Flavor::Gfm)) => ("<ins>", "</ins>"),
+        (Some(UnderlineMode::Emphasis), _) => ("_", "_"),
+        (Some(UnderlineMode::Drop), _) => ("", ""),
+        (None, Some(Flavor::Pandoc)) => ("[", "]{.underline}"),
+        _ => ("<u>", "</u>"),
+    }
+}
+
+/// Whether the Markdown has footnotes (`[^1]`), which endnotes become.
+fn has_footnotes(options: &Options) -> bool {
+    matches!(
+        options.flavor,
+        Some(Flavor::Gfm | Flavor::MultiMarkdown | Flavor::Pandoc)
+    )
+}
+
+/// Whether a table cell is a number, which is aligned to the right.
+fn is_number(cell: &str) -> bool {
+    !cell.is_empty()
+        && cell
+            .chars()
+            .all(|c| c.is_ascii_digit() || ".,-+%$".contains(c))
+        && cell.chars().any(|c| c.is_ascii_digit())
+}
+
+/// Indents all but the first line of `text`, empty lines excepted.
+fn indent(text: &str, width: usize) -> String {
+    text.split('\n')
+        .enumerate()
+        .map(|(index, line)| match index {
+            _ if index == 0 || line.is_empty() => line.to_string(),
+            _ => format!("{}{}", " ".repeat(width), line),
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+struct Writer<'a> {
+    options: &'a Options,
+    /// The footnotes so far, written at the end
+    footnotes: Vec<String>,
+    /// Whether the text written next starts a line, and could start a block
+    line_start: bool,
+}
+
+/// Writes `document` as Markdown.
+pub fn render(document: &Document, options: &Options) -> String {
+    let mut blocks = document.blocks.clone();
+    if !has_footnotes(options) {
+        endnotes(&mut blocks);
+    }
+    let mut writer = Writer {
+        options,
+        footnotes: Vec::new(),
+        line_start: true,
+    };
+
+    let mut markdown = String::new();
+    if !document.metadata.is_empty() {
+        markdown.push_str("---\n");
+        for (key, value) in &document.metadata {
+            markdown.push_str(&format!("{}: {}\n", key, value));
+        }
+        markdown.push_str("---\n\n");
+    }
+    let mut text = writer.blocks(&blocks, false);
+    for (index, footnote) in writer.footnotes.iter().enumerate() {
+        if !text.is_empty() {
+            text.push_str("\n\n");
+        }
+        text.push_str(&format!("[^{}]: {}", index + 1, indent(footnote, 4)));
+    }
+    if !text.is_empty() {
+        markdown.push_str(&text);
+        markdown.push('\n');
+    }
+    markdown
+}
+
+impl Writer<'_> {
+    // The level a heading is written with.
+    fn heading_level(&self, level: usize) -> usize {
+        (level as isize + self.options.shift_headings).clamp(1, 6) as usize
+    }
+
+    // Whether a block is a heading underlined with = or -.
+    fn is_setext(&self, block: &Block) -> bool {
+        match block {
+            Block::Heading { level, content } => {
+                self.options.heading_style == HeadingStyle::Setext
+                    && self.heading_level(*level) <= 2
+                    && !plain_text(content).trim().is_empty()
+            }
+            _ => false,
+        }
+    }
+
+    // How many blank lines have to be between two blocks, at least. Tight
+    // list items have none between their text and the lists in them.
+    fn required_blank_lines(&self, previous: &Block, next: &Block, tight: bool) -> usize {
+        match (previous, next) {
+            // A closing </div> runs on to the next blank line.
+            (Block::Div { .. }, _) => 1,
+            (Block::Comment(_) | Block::Template(_), _)
+            | (_, Block::Comment(_) | Block::Template(_)) => 0,
+            // The line before would be part of the heading otherwise.
+            (_, Block::Heading { .. }) if self.is_setext(next) => 1,
+            (_, Block::Heading { .. }) | (Block::Heading { .. }, _) => 0,
+            (Block::Paragraph(_), Block::List { .. }) if tight => 0,
+            _ => 1,
+        }
+    }
+
+    fn blocks(&mut self, blocks: &[Block], tight: bool) -> String {
+        let max_blank_lines = self.options.max_blank_lines.unwrap_or(1);
+        let mut markdown = String::new();
+        let mut previous: Option<&Block> = None;
+        let mut space = 0;
+        for block in blocks {
+            if let Block::Space(lines) = block {
+                space += lines;
+                continue;
+            }
+            if let Some(previous) = previous {
+                let required = self.required_blank_lines(previous, block, tight);
+                markdown.push('\n');
+                markdown.push_str(&"\n".repeat(space.min(max_blank_lines).max(required)));
+            }
+            markdown.push_str(&self.block(block));
+            previous = Some(block);
+            space = 0;
+        }
+        markdown
+    }
+
+    fn block(&mut self, block: &Block) -> String {
+        match block {
+            Block::Heading { level, content } => {
+                let level = self.heading_level(*level);
+                if self.is_setext(block) {
+                    self.line_start = true;
+                    let text = self.inlines(content);
+                    let underline = if level == 1 { "=" } else { "-" };
+                    let width = text.chars().count().max(3);
+                    format!("{}\n{}", text, underline.repeat(width))
+                } else {
+                    self.line_start = false;
+                    format!("{} {}", "#".repeat(level), self.inlines(content))
+                }
+            }
+            Block::Paragraph(content) => {
+                self.line_start = true;
+                self.inlines(content)
+            }
+            Block::Code { language, text } => format!("```{}\n{}\n```", language, text),
+            Block::Quote(content) => self
+                .blocks(content, false)
+                .split('\n')
+                .map(|line| match line {
+                    "" => String::from(">"),
+                    line => format!("> {}", line),
+                })
+                .collect::<Vec<String>>()
+                .join("\n"),
+            Block::List {
+                ordered,
+                start,
+                items,
+            } => {
+                // Items with more than a line of text need blank lines.
+                let tight = items.iter().all(|item| {
+                    item.iter()
+                        .filter(|block| !matches!(block, Block::List { .. } | Block::Space(_)))
+                        .count()
+                        <= 1
+                });
+                let rendered: Vec<String> = items
+                    .iter()
+                    .enumerate()
+                    .map(|(index, item)| {
+                        let marker = match ordered {
+                            true => format!("{}. ", start + index),
+                            false => String::from("- "),
+                        };
+                        let text = self.blocks(item, tight);
+                        format!("{}{}", marker, indent(&text, marker.len()))
+                    })
+                    .collect();
+                rendered.join(if tight { "\n" } else { "\n\n" })
+            }
+            Block::Table { header, rows } => self.table(header, rows),
+            Block::Rule => self
+                .options
+                .page_separator
+                .clone()
+                .unwrap_or(String::from("----")),
+            Block::Div {
+                attributes,
+                content,
+            } => {
+                let content = self.blocks(content, false);
+                if attributes.starts_with('{') {
+                    format!("::: {}\n{}\n:::", attributes, content)
+                } else {
+                    format!("<div {}>\n\n{}\n\n</div>", attributes, content)
+                }
+            }
+            // "--" would end the comment early.
+            Block::Comment(comment) => format!("<!-- {} -->", comment.replace("--", "- -")),
+            Block::Template(template) => template.clone(),
+            Block::Space(_) => String::new(),
+        }
+    }
+
+    // A pipe table, or an HTML one for Markdown without tables. Columns of
+    // numbers are aligned to the right.
+    fn table(&mut self, header: &[Vec<Inline>], rows: &[Vec<Vec<Inline>>]) -> String {
+        let numbers: Vec<bool> = (0..header.len())
+            .map(|column| {
+                rows.iter()
+                    .map(|row| plain_text(&row[column]))
+                    .filter(|cell| !cell.is_empty())
+                    .all(|cell| is_number(&cell))
+            })
+            .collect();
+
+        if self.options.flavor == Some(Flavor::CommonMark) {
+            let row = |cells: &[Vec<Inline>], tag: &str| {
+                let cells: String = cells
+                    .iter()
+                    .zip(&numbers)
+                    .map(|(cell, &number)| {
+                        let alignment = if number { " align=\"right\"" } else { "" };
+                        format!("<{0}{1}>{2}</{0}>", tag, alignment, html::inlines(cell))
+                    })
+                    .collect();
+                format!("<tr>{}</tr>\n", cells)
+            };
+            let body: String = rows.iter().map(|cells| row(cells, "td")).collect();
+            return format!(
+                "<table>\n<thead>\n{}</thead>\n<tbody>\n{}</tbody>\n</table>",
+                row(header, "th"),
+                body
+            );
+        }
+
+        let mut row = |cells: &[Vec<Inline>]| {
+            let cells: Vec<String> = cells
+                .iter()
+                .map(|cell| {
+                    self.line_start = false;
+                    self.inlines(cell).replace('|', "\\|")
+                })
+                .collect();
+            format!("| {} |", cells.join(" | "))
+        };
+        let mut lines = vec![row(header)];
+        let separators: Vec<&str> = numbers
+            .iter()
+            .map(|&number| if number { "---:" } else { "---" })
+            .collect();
+        lines.push(format!("| {} |", separators.join(" | ")));
+        for cells in rows {
+            lines.push(row(cells));
+        }
+        lines.join("\n")
+    }
+
+    fn inlines(&mut self, inlines: &[Inline]) -> String {
+        let mut markdown = String::new();
+        for (index, inline) in inlines.iter().enumerate() {
+            match inline {
+                // Paragraphs end anyway.
+                Inline::LineBreak if index + 1 == inlines.len() => {}
+                Inline::LineBreak => {
+                    // Lines which ended with a hard return stay apart.
+                    let marker = match self.options.flavor {
+                        None => "",
+                        Some(Flavor::MultiMarkdown) => "  ",
+                        Some(_) => "\\",
+                    };
+                    if !marker.is_empty() {
+                        // Spaces at the end might be a break of their own.
+                        let trimmed_length = markdown.trim_end_matches(' ').len();
+                        markdown.truncate(trimmed_length);
+                        markdown.push_str(marker);
+                    }
+                    markdown.push('\n');
+                    self.line_start = true;
+                }
+                _ => self.inline(&mut markdown, inline),
+            }
+        }
+        markdown
+    }
+
+    // Text between markers, with the spaces around it outside of them. The
+    // HTML `tag` stands in where the markers would run into others.
+    fn delimited(
+        &mut self,
+        markdown: &mut String,
+        content: &[Inline],
+        (open, close): (&str, &str),
+        tag: Option<&str>,
+    ) {
+        if !open.is_empty() {
+            self.line_start = false;
+        }
+        let text = self.inlines(content);
+        let trimmed = text.trim_matches(' ');
+        if trimmed.is_empty() || open.is_empty() {
+            markdown.push_str(&text);
+            return;
+        }
+        let leading_spaces = text.len() - text.trim_start_matches(' ').len();
+        markdown.push_str(&text[..leading_spaces]);
+        let runs_into_others = markdown.ends_with(open.chars().next().unwrap())
+            || trimmed.starts_with(open)
+            || trimmed.ends_with(close);
+        match tag.filter(|_| runs_into_others) {
+            Some(tag) => markdown.push_str(&format!("<{0}>{1}</{0}>", tag, trimmed)),
+            None => markdown.push_str(&format!("{}{}{}", open, trimmed, close)),
+        }
+        markdown.push_str(&text[leading_spaces + trimmed.len()..]);
+    }
+
+    fn inline(&mut self, markdown: &mut String, inline: &Inline) {
+        let options = self.options;
+        match inline {
+            Inline::Text(text) => {
+                let mut text = match options.no_escape {
+                    true => text.clone(),
+                    false => escape_inline(text),
+                };
+                if self.line_start && !options.no_escape {
+                    text = escape_block_marker(&text);
+                }
+                if !options.unicode_nbsp {
+                    text = text.replace('\u{a0}', "&nbsp;");
+                } else if self.line_start {
+                    // Margins aren't binding spaces, they stay entities.
+                    let margin = text.len() - text.trim_start_matches('\u{a0}').len();
+                    text.replace_range(..margin, &"&nbsp;".repeat(margin / 2));
+                }
+                if text.contains(|c: char| !c.is_whitespace()) {
+                    self.line_start = false;
+                }
+                markdown.push_str(&text);
+            }
+            Inline::Bold(content) => {
+                self.delimited(markdown, content, ("**", "**"), Some("strong"))
+            }
+            Inline::Italic(content) => self.delimited(markdown, content, ("*", "*"), Some("em")),
+            Inline::Underline(content) => {
+                let markers = underline_markers(options);
+                self.delimited(markdown, content, markers, None)
+            }
+            Inline::Strikeout(content) if options.html_strikeout => {
+                self.delimited(markdown, content, ("<del>", "</del>"), None)
+            }
+            Inline::Strikeout(content) => {
+                self.delimited(markdown, content, ("~~", "~~"), Some("del"))
+            }
+            Inline::Superscript(content) if options.pandoc_scripts => {
+                self.delimited(markdown, content, ("^", "^"), Some("sup"))
+            }
+            Inline::Superscript(content) => {
+                self.delimited(markdown, content, ("<sup>", "</sup>"), None)
+            }
+            Inline::Subscript(content) if options.pandoc_scripts => {
+                self.delimited(markdown, content, ("~", "~"), Some("sub"))
+            }
+            Inline::Subscript(content) => {
+                self.delimited(markdown, content, ("<sub>", "</sub>"), None)
+            }
+            Inline::Code(code) if code.is_empty() => {}
+            Inline::Code(code) if code.contains('`') => {
+                self.line_start = false;
+                markdown.push_str(&format!("`` {} ``", code));
+            }
+            Inline::Code(code) => {
+                self.line_start = false;
+                markdown.push_str(&format!("`{}`", code));
+            }
+            Inline::Link { content, target } => {
+                self.line_start = false;
+                let content = self.inlines(content);
+                markdown.push_str(&format!("[{}]({})", content, target));
+            }
+            Inline::Image {
+                description,
+                source,
+            } => {
+                self.line_start = false;
+                markdown.push_str(&format!(
+                    "![{}]({})",
+                    description,
+                    source.replace(' ', "%20")
+                ));
+            }
+            Inline::Anchor(name) => {
+                self.line_start = false;
+                markdown.push_str(&format!("<a id=\"{}\"></a>", name));
+            }
+            Inline::Span {
+                attributes,
+                content,
+            } => {
+                self.line_start = false;
+                let content = self.inlines(content);
+                markdown.push_str(&format!("<span {}>{}</span>", attributes, content));
+            }
+            Inline::LineBreak | Inline::SoftBreak => {
+                markdown.push('\n');
+                self.line_start = true;
+            }
+            Inline::Note(content) => {
+                let note = self.inlines(content);
+                self.footnotes.push(note);
+                self.line_start = false;
+                markdown.push_str(&format!("[^{}]", self.footnotes.len()));
+            }
+            Inline::Html(html) | Inline::Template(html) => {
+                self.line_start = false;
+                markdown.push_str(html);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Inline {
+        Inline::Text(text.to_string())
+    }
+
+    fn document(blocks: Vec<Block>) -> Document {
+        Document {
+            metadata: Vec::new(),
+            blocks,
+            right_margin: None,
+        }
+    }
+
+    #[test]
+    fn blank_lines_are_kept_up_to_the_maximum() {
+        let blocks = vec![
+            Block::Paragraph(vec![text("one"), Inline::LineBreak, text("two")]),
+            Block::Space(3),
+            Block::Paragraph(vec![text("three")]),
+        ];
+        let options = Options::default();
+        assert_eq!(
+            render(&document(blocks.clone()), &options),
+            "one\ntwo\n\nthree\n"
+        );
+        let options = Options {
+            max_blank_lines: Some(2),
+            flavor: Some(Flavor::Gfm),
+            ..Default::default()
+        };
+        assert_eq!(
+            render(&document(blocks), &options),
+            "one\\\ntwo\n\n\nthree\n"
+        );
+    }
+
+    #[test]
+    fn markers_keep_out_of_the_spaces_and_each_other() {
+        let blocks = vec![Block::Paragraph(vec![
+            Inline::Bold(vec![text("bold ")]),
+            Inline::Bold(vec![Inline::Italic(vec![text("both")])]),
+            Inline::Italic(vec![text("italic")]),
+        ])];
+        assert_eq!(
+            render(&document(blocks), &Options::default()),
+            "**bold** ***both***<em>italic</em>\n"
+        );
+    }
+
+    #[test]
+    fn text_is_escaped() {
+        let blocks = vec![Block::Paragraph(vec![
+            text("- not a *list*"),
+            Inline::LineBreak,
+            text("\u{a0}1. either"),
+        ])];
+        assert_eq!(
+            render(&document(blocks), &Options::default()),
+            "\\- not a \\*list\\*\n&nbsp;1\\. either\n"
+        );
+    }
+
+    #[test]
+    fn lists_and_notes() {
+        let blocks = vec![
+            Block::List {
+                ordered: true,
+                start: 9,
+                items: vec![
+                    vec![Block::Paragraph(vec![text("nine")])],
+                    vec![
+                        Block::Paragraph(vec![text("ten"), Inline::Note(vec![text("A note")])]),
+                        Block::List {
+                            ordered: false,
+                            start: 1,
+                            items: vec![vec![Block::Paragraph(vec![text("nested")])]],
+                        },
+                    ],
+                ],
+            },
+            Block::Paragraph(vec![text("after")]),
+        ];
+        let options = Options {
+            flavor: Some(Flavor::Pandoc),
+            ..Default::default()
+        };
+        assert_eq!(
+            render(&document(blocks), &options),
+            "9. nine\n10. ten[^1]\n    - nested\n\nafter\n\n[^1]: A note\n"
+        );
+    }
+
+    #[test]
+    fn headings_and_tables() {
+        let blocks = vec![
+            Block::Paragraph(vec![text("Text")]),
+            Block::Heading {
+                level: 1,
+                content: vec![text("Title")],
+            },
+            Block::Table {
+                header: vec![vec![text("Name")], vec![text("Price")]],
+                rows: vec![vec![vec![text("a|b")], vec![text("1.20")]]],
+            },
+        ];
+        assert_eq!(
+            render(&document(blocks.clone()), &Options::default()),
+            "Text\n# Title\n| Name | Price |\n| --- | ---: |\n| a\\|b | 1.20 |\n"
+        );
+        let options = Options {
+            heading_style: HeadingStyle::Setext,
+            flavor: Some(Flavor::CommonMark),
+            ..Default::default()
+        };
+        assert_eq!(
+            render(&document(blocks), &options),
+            "Text\n\nTitle\n=====\n<table>\n<thead>\n<tr><th>Name</th><th align=\"right\">Price</th></tr>\n</thead>\n<tbody>\n<tr><td>a|b</td><td align=\"right\">1.20</td></tr>\n</tbody>\n</table>\n"
+        );
+    }
+
+    #[test]
+    fn paragraphs_may_start_with_formatting() {
+        let options = crate::Options::default();
+        let document = crate::convert(
+            "\u{13}Chapter one\u{13} begins here.\r\nSecond para & more.\r\n",
+            &options,
+            &[std::path::PathBuf::from("test.ws")],
+        )
+        .unwrap();
+        assert_eq!(
+            render(&document, &options),
+            "<u>Chapter</u> <u>one</u> begins here.\nSecond para & more.\n"
+        );
+    }
+}
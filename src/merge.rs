@@ -0,0 +1,722 @@
+/* ----- CDDL HEADER -----
+ *
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.1 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * https://spdx.org/licenses/CDDL-1.1.html
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ *
+ * ----- CDDL HEADER END -----
+ */
+
+// WordStar's mail merge: form letters contain fields like "&NAME&" (or
+// "&NAME/O&", which leaves out empty lines) which MailMerge fills in from a
+// data file. We turn them into placeholders of a template engine.
+//
+// The fields are declared by .rv (read variables from the data file, which
+// is named by .df) and .av (ask for a variable). Conditions (.if, .el, .ei)
+// compare fields with each other or with "text". .ma sets a variable to the
+// result of a calculation ("TOTAL &PRICE& * &COUNT&"), .sv sets it to a
+// value ("CITY, Berlin").
+
+/// The template engines we can write placeholders for.
+pub enum TemplateEngine {
+    Handlebars,
+    Tera,
+    Jinja2,
+}
+
+impl TemplateEngine {
+    /// Returns the placeholder for a merge field.
+    fn placeholder(&self, field: &str) -> String {
+        match self {
+            TemplateEngine::Handlebars => format!("{{{{{}}}}}", field),
+            TemplateEngine::Tera | TemplateEngine::Jinja2 => format!("{{{{ {} }}}}", field),
+        }
+    }
+}
+
+use crate::document::{Block, Inline};
+use anyhow::{Context, Result};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+/// The comparisons of .if, with their template and Handlebars helper names.
+const COMPARISONS: [(&str, &str, &str); 6] = [
+    ("<>", "!=", "ne"),
+    ("<=", "<=", "lte"),
+    (">=", ">=", "gte"),
+    ("=", "==", "eq"),
+    ("<", "<", "lt"),
+    (">", ">", "gt"),
+];
+
+/// Splits a condition into its operands and comparison, if it has one.
+fn split_condition(condition: &str) -> Option<(&str, usize, &str)> {
+    let mut in_quotes = false;
+    for (position, character) in condition.char_indices() {
+        match character {
+            '"' => in_quotes = !in_quotes,
+            _ if in_quotes => {}
+            _ => {
+                if let Some(index) = COMPARISONS
+                    .iter()
+                    .position(|(comparison, ..)| condition[position..].starts_with(comparison))
+                {
+                    let right = &condition[position + COMPARISONS[index].0.len()..];
+                    return Some((condition[..position].trim(), index, right.trim()));
+                }
+            }
+        }
+    }
+    None
+}
+
+/// The beginning of a conditional block. Without an engine, it's a comment.
+pub fn if_block(condition: &str, engine: Option<&TemplateEngine>) -> Block {
+    let condition = condition.trim();
+    let Some(engine) = engine else {
+        return Block::Comment(format!("if {}", condition));
+    };
+
+    // Fields become variables.
+    let variables = map_fields(condition, |name| name.to_lowercase());
+    Block::Template(match (engine, split_condition(&variables)) {
+        (TemplateEngine::Handlebars, Some((left, index, right))) => {
+            // Handlebars needs helpers for comparisons.
+            format!("{{{{#if ({} {} {})}}}}", COMPARISONS[index].2, left, right)
+        }
+        (TemplateEngine::Handlebars, None) => format!("{{{{#if {}}}}}", variables),
+        (_, Some((left, index, right))) => {
+            format!("{{% if {} {} {} %}}", left, COMPARISONS[index].1, right)
+        }
+        (_, None) => format!("{{% if {} %}}", variables),
+    })
+}
+
+/// Adds a variable to the declared ones, unless it's there already or not a
+/// variable at all.
+pub fn declare(variables: &mut Vec<String>, variable: &str) {
+    if is_variable_name(variable)
+        && !variables
+            .iter()
+            .any(|declared| declared.eq_ignore_ascii_case(variable))
+    {
+        variables.push(variable.to_string());
+    }
+}
+
+/// Returns the variable and the prompt (if there is one) of .av:
+/// ".av NAME" or ".av "prompt", NAME, length".
+pub fn ask_variable(text: &str) -> Option<(String, Option<String>)> {
+    let mut arguments = arguments(text).into_iter();
+    let prompt = text
+        .trim_start()
+        .starts_with('"')
+        .then(|| arguments.next())
+        .flatten();
+    let variable = arguments
+        .next()
+        .filter(|variable| is_variable_name(variable))?;
+    Some((variable, prompt))
+}
+
+/// Returns the variable and the value of .sv: ".sv NAME, value".
+pub fn set_variable(text: &str) -> Option<(String, String)> {
+    let mut arguments = arguments(text).into_iter();
+    let variable = arguments
+        .next()
+        .filter(|variable| is_variable_name(variable))?;
+    Some((variable, arguments.next().unwrap_or_default()))
+}
+
+/// Splits the arguments of .ma into the variable and the calculation.
+pub fn split_math(text: &str) -> (&str, &str) {
+    let text = text.trim();
+    let (variable, calculation) = text.split_once([' ', ',']).unwrap_or((text, ""));
+    (variable.trim(), calculation.trim())
+}
+
+/// A calculation (.ma) as a comment of the template engine.
+pub fn math_comment(text: &str, engine: Option<&TemplateEngine>) -> Block {
+    let (variable, calculation) = split_math(text);
+    match engine {
+        None => Block::Comment(format!("{} = {}", variable, calculation)),
+        Some(TemplateEngine::Handlebars) => {
+            Block::Template(format!("{{{{!-- {} = {} --}}}}", variable, calculation))
+        }
+        Some(_) => Block::Template(format!("{{# {} = {} #}}", variable, calculation)),
+    }
+}
+
+/// The "else" of a conditional block.
+pub fn else_block(engine: Option<&TemplateEngine>) -> Block {
+    match engine {
+        None => Block::Comment(String::from("else")),
+        Some(TemplateEngine::Handlebars) => Block::Template(String::from("{{else}}")),
+        Some(_) => Block::Template(String::from("{% else %}")),
+    }
+}
+
+/// The end of a conditional block.
+pub fn end_if_block(engine: Option<&TemplateEngine>) -> Block {
+    match engine {
+        None => Block::Comment(String::from("endif")),
+        Some(TemplateEngine::Handlebars) => Block::Template(String::from("{{/if}}")),
+        Some(_) => Block::Template(String::from("{% endif %}")),
+    }
+}
+
+/// Returns the name of the merge field at the start of `text` and the
+/// length of the field, if there is one.
+fn merge_field(text: &str) -> Option<(&str, usize)> {
+    let field = text.strip_prefix('&')?;
+    let name_length = field
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(field.len());
+    let name = &field[..name_length];
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+
+    let rest = &field[name_length..];
+    let options_length = if rest.len() >= 2 && rest[..2].eq_ignore_ascii_case("/o") {
+        2
+    } else {
+        0
+    };
+    rest[options_length..]
+        .starts_with('&')
+        .then_some((name, 1 + name_length + options_length + 1))
+}
+
+/// A piece of text with merge fields in it.
+enum Piece<'a> {
+    Text(String),
+    /// A merge field, by its name
+    Field(&'a str),
+}
+
+/// Splits `text` into the merge fields and the text between them.
+fn pieces(text: &str) -> Vec<Piece<'_>> {
+    let mut pieces = Vec::new();
+    let mut outside_text = String::new();
+    let mut rest = text;
+    while let Some(position) = rest.find('&') {
+        outside_text.push_str(&rest[..position]);
+        rest = &rest[position..];
+        match merge_field(rest) {
+            Some((name, length)) => {
+                if !outside_text.is_empty() {
+                    pieces.push(Piece::Text(std::mem::take(&mut outside_text)));
+                }
+                pieces.push(Piece::Field(name));
+                rest = &rest[length..];
+            }
+            None => {
+                outside_text.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    outside_text.push_str(rest);
+    if !outside_text.is_empty() {
+        pieces.push(Piece::Text(outside_text));
+    }
+    pieces
+}
+
+/// Replaces the merge fields in `text` by what `replace` returns for their
+/// names.
+fn map_fields(text: &str, mut replace: impl FnMut(&str) -> String) -> String {
+    pieces(text)
+        .into_iter()
+        .map(|piece| match piece {
+            Piece::Text(text) => text,
+            Piece::Field(name) => replace(name),
+        })
+        .collect()
+}
+
+/// Splits `text` into its text and placeholders for its merge fields.
+pub fn split_fields(text: &str, engine: &TemplateEngine) -> Vec<Inline> {
+    pieces(text)
+        .into_iter()
+        .map(|piece| match piece {
+            Piece::Text(text) => Inline::Text(text),
+            Piece::Field(name) => Inline::Template(engine.placeholder(&name.to_lowercase())),
+        })
+        .collect()
+}
+
+/// Returns the names of the merge fields in `text`.
+pub fn field_names(text: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    map_fields(text, |name| {
+        names.push(name.to_string());
+        String::new()
+    });
+    names
+}
+
+/// Splits the arguments of a merge dot command at the commas which are not
+/// in quotes, and removes the quotes.
+pub fn arguments(text: &str) -> Vec<String> {
+    let mut arguments = vec![String::new()];
+    let mut in_quotes = false;
+    for character in text.chars() {
+        match character {
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => arguments.push(String::new()),
+            _ => arguments.last_mut().unwrap().push(character),
+        }
+    }
+    arguments
+        .iter()
+        .map(|argument| argument.trim().to_string())
+        .collect()
+}
+
+/// Whether the text is a valid variable name.
+pub fn is_variable_name(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_alphabetic())
+        && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Returns the arguments of `line` if it's the dot command `name`, in any
+/// case: this runs on the text as it is in the file.
+fn dot_command<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    line.get(..name.len())
+        .filter(|command| command.eq_ignore_ascii_case(name))
+        .map(|_| &line[name.len()..])
+}
+
+/// Reads the records of a comma-separated data file. The fields are named by
+/// the .rv commands in `text`, or by the first line of the file if there are
+/// none. Names are in upper case.
+pub fn read_data_file(path: &Path, text: &str) -> Result<Vec<HashMap<String, String>>> {
+    let data = fs::read(path)
+        .with_context(|| format!("could not read the data file {}", path.display()))?;
+    let data = String::from_utf8_lossy(&data);
+    let mut lines = data.lines().filter(|line| !line.trim().is_empty());
+
+    let mut names: Vec<String> = text
+        .lines()
+        .filter_map(|line| dot_command(line, ".rv"))
+        .flat_map(arguments)
+        .filter(|name| is_variable_name(name))
+        .collect();
+    if names.is_empty() {
+        names = arguments(lines.next().unwrap_or_default());
+    }
+    let names: Vec<String> = names.iter().map(|name| name.to_uppercase()).collect();
+
+    Ok(lines
+        .map(|line| names.iter().cloned().zip(arguments(line)).collect())
+        .collect())
+}
+
+/// Returns the value of an operand of a condition: a field, a "text" or
+/// just text.
+fn operand_value(operand: &str, record: &HashMap<String, String>) -> String {
+    match merge_field(operand) {
+        Some((name, length)) if length == operand.len() => record
+            .get(&name.to_uppercase())
+            .cloned()
+            .unwrap_or_default(),
+        _ => operand.trim_matches('"').to_string(),
+    }
+}
+
+/// Evaluates the condition of an .if for a record. Numbers are compared as
+/// numbers, everything else as text. A field on its own is true if it's not
+/// empty.
+fn evaluate(condition: &str, record: &HashMap<String, String>) -> bool {
+    let Some((left, index, right)) = split_condition(condition.trim()) else {
+        return !operand_value(condition.trim(), record).is_empty();
+    };
+    let (left, right) = (operand_value(left, record), operand_value(right, record));
+    let ordering = match (left.trim().parse::<f64>(), right.trim().parse::<f64>()) {
+        (Ok(left), Ok(right)) => left.partial_cmp(&right),
+        _ => Some(left.cmp(&right)),
+    };
+    let Some(ordering) = ordering else {
+        return false;
+    };
+    match COMPARISONS[index].0 {
+        "<>" => ordering.is_ne(),
+        "<=" => ordering.is_le(),
+        ">=" => ordering.is_ge(),
+        "=" => ordering.is_eq(),
+        "<" => ordering.is_lt(),
+        _ => ordering.is_gt(),
+    }
+}
+
+/// Asks for the value of a variable on stdin.
+fn ask(variable: &str, prompt: Option<&str>) -> String {
+    match prompt {
+        Some(prompt) => eprint!("{} ", prompt),
+        None => eprint!("{}: ", variable),
+    }
+    io::stderr().flush().ok();
+    let mut value = String::new();
+    io::stdin().read_line(&mut value).ok();
+    value.trim_end_matches(['\r', '\n']).to_string()
+}
+
+/// Performs the mail merge for one record: fills in the fields and keeps
+/// only the lines whose conditions are true. Variables which are asked for
+/// (.av) or set (.sv) are taken from `variables` if they're in there.
+pub fn merge_record(
+    text: &str,
+    record: &HashMap<String, String>,
+    variables: &HashMap<String, String>,
+) -> String {
+    // Calculations and the like add variables.
+    let mut record = record.clone();
+    let mut merged_text = String::with_capacity(text.len());
+    // Whether the branch of every open .if is taken
+    let mut branches: Vec<bool> = Vec::new();
+    for line in text.split_inclusive('\n') {
+        if let Some(condition) = dot_command(line, ".if") {
+            branches.push(evaluate(condition, &record));
+        } else if dot_command(line, ".el").is_some() {
+            if let Some(branch) = branches.last_mut() {
+                *branch = !*branch;
+            }
+        } else if dot_command(line, ".ei").is_some() {
+            branches.pop();
+        } else if !branches.iter().all(|&branch| branch) {
+            // Not taken.
+        } else if let Some(math) = dot_command(line, ".ma") {
+            let (variable, calculation) = split_math(math);
+            let result = calculate(calculation, &record).map_or(String::new(), format_number);
+            record.insert(variable.to_uppercase(), result);
+        } else if let Some((variable, prompt)) = dot_command(line, ".av").and_then(ask_variable) {
+            let variable = variable.to_uppercase();
+            let value = match variables.get(&variable) {
+                Some(value) => value.clone(),
+                None => ask(&variable, prompt.as_deref()),
+            };
+            record.insert(variable, value);
+        } else if let Some((variable, value)) = dot_command(line, ".sv").and_then(set_variable) {
+            let variable = variable.to_uppercase();
+            let value = variables.get(&variable).cloned().unwrap_or(value);
+            record.insert(variable, value);
+        } else if dot_command(line, ".rv").is_some() || dot_command(line, ".df").is_some() {
+            // They have done their job.
+        } else {
+            let merged_line = map_fields(line, |name| {
+                record
+                    .get(&name.to_uppercase())
+                    .cloned()
+                    .unwrap_or_default()
+            });
+            // "&NAME/O&" leaves the line out if that's all there is in it.
+            if !(merged_line.trim().is_empty() && has_empty_omitted_field(line, &record)) {
+                merged_text.push_str(&merged_line);
+            }
+        }
+    }
+    merged_text
+}
+
+/// Whether `line` has a field with /O which is empty in `record`.
+fn has_empty_omitted_field(line: &str, record: &HashMap<String, String>) -> bool {
+    let mut rest = line;
+    while let Some(position) = rest.find('&') {
+        rest = &rest[position..];
+        match merge_field(rest) {
+            Some((name, length)) => {
+                let omitted = rest[..length].to_ascii_uppercase().ends_with("/O&");
+                let empty = record
+                    .get(&name.to_uppercase())
+                    .is_none_or(String::is_empty);
+                if omitted && empty {
+                    return true;
+                }
+                rest = &rest[length..];
+            }
+            None => rest = &rest[1..],
+        }
+    }
+    false
+}
+
+/// A simple calculator for .ma: numbers, + - * / and parentheses.
+struct Calculation {
+    characters: Vec<char>,
+    position: usize,
+}
+
+impl Calculation {
+    /// Returns the next character which is not a space.
+    fn peek(&mut self) -> Option<char> {
+        while self.characters.get(self.position) == Some(&' ') {
+            self.position += 1;
+        }
+        self.characters.get(self.position).copied()
+    }
+
+    fn sum(&mut self) -> Option<f64> {
+        let mut value = self.product()?;
+        loop {
+            match self.peek() {
+                Some('+') => {
+                    self.position += 1;
+                    value += self.product()?;
+                }
+                Some('-') => {
+                    self.position += 1;
+                    value -= self.product()?;
+                }
+                _ => return Some(value),
+            }
+        }
+    }
+
+    fn product(&mut self) -> Option<f64> {
+        let mut value = self.factor()?;
+        loop {
+            match self.peek() {
+                Some('*') => {
+                    self.position += 1;
+                    value *= self.factor()?;
+                }
+                Some('/') => {
+                    self.position += 1;
+                    value /= self.factor()?;
+                }
+                _ => return Some(value),
+            }
+        }
+    }
+
+    fn factor(&mut self) -> Option<f64> {
+        match self.peek()? {
+            '-' => {
+                self.position += 1;
+                Some(-self.factor()?)
+            }
+            '(' => {
+                self.position += 1;
+                let value = self.sum()?;
+                (self.peek() == Some(')')).then(|| self.position += 1)?;
+                Some(value)
+            }
+            _ => {
+                let start = self.position;
+                while self
+                    .characters
+                    .get(self.position)
+                    .is_some_and(|c| c.is_ascii_digit() || *c == '.')
+                {
+                    self.position += 1;
+                }
+                let number: String = self.characters[start..self.position].iter().collect();
+                number.parse().ok()
+            }
+        }
+    }
+}
+
+/// Calculates the result of a .ma calculation for a record.
+fn calculate(calculation: &str, record: &HashMap<String, String>) -> Option<f64> {
+    let calculation = map_fields(calculation, |name| {
+        record
+            .get(&name.to_uppercase())
+            .map_or(String::new(), |value| value.trim().to_string())
+    });
+    let mut parser = Calculation {
+        characters: calculation.chars().collect(),
+        position: 0,
+    };
+    let value = parser.sum()?;
+    (parser.peek().is_none() && value.is_finite()).then_some(value)
+}
+
+/// Formats a result without needless decimals.
+fn format_number(value: f64) -> String {
+    let number = format!("{:.6}", value);
+    number
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(fields: &[(&str, &str)]) -> HashMap<String, String> {
+        fields
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn conditions() {
+        let record = record(&[("CITY", "Berlin"), ("COUNT", "12"), ("EMPTY", "")]);
+        assert!(evaluate(" &CITY& = \"Berlin\"", &record));
+        assert!(!evaluate(" &CITY& <> \"Berlin\"", &record));
+        assert!(evaluate(" &COUNT& > 9", &record));
+        assert!(evaluate(" &COUNT& >= 12", &record));
+        assert!(!evaluate(" &EMPTY&", &record));
+        assert!(evaluate(" &CITY&", &record));
+    }
+
+    #[test]
+    fn calculations() {
+        let record = record(&[("PRICE", "2.5"), ("COUNT", "4")]);
+        assert_eq!(calculate("&PRICE& * (&COUNT& + 1)", &record), Some(12.5));
+        assert_eq!(calculate("1 / 0", &record), None);
+        assert_eq!(format_number(12.5), "12.5");
+        assert_eq!(format_number(3.0), "3");
+    }
+
+    #[test]
+    fn merge_commands_in_any_case() {
+        let text =
+            ".RV NAME,CITY\nDear &NAME&,\n.IF &CITY& = \"Berlin\"\nHallo!\n.EL\nHello!\n.EI\n";
+        let merged = merge_record(
+            text,
+            &record(&[("NAME", "Anna"), ("CITY", "Berlin")]),
+            &HashMap::new(),
+        );
+        assert_eq!(merged, "Dear Anna,\nHallo!\n");
+    }
+
+    #[test]
+    fn empty_omitted_fields_leave_out_their_line() {
+        let text = "&NAME&\n&COMPANY/o&\n&STREET/O&\n&CITY&\n";
+        let merged = merge_record(
+            text,
+            &record(&[("NAME", "Anna"), ("STREET", "Main St"), ("CITY", "")]),
+            &HashMap::new(),
+        );
+        assert_eq!(merged, "Anna\nMain St\n\n");
+    }
+
+    #[test]
+    fn ma_and_sv() {
+        let text = ".Ma TOTAL &PRICE& * 2\n.SV GREETING, Hi\n&GREETING&: &TOTAL&\n";
+        let merged = merge_record(text, &record(&[("PRICE", "3")]), &HashMap::new());
+        assert_eq!(merged, "Hi: 6\n");
+    }
+
+    #[test]
+    fn fields_become_placeholders() {
+        let text = "Dear &NAME&, &CITY/O& R&D & Co";
+        assert_eq!(
+            split_fields(text, &TemplateEngine::Handlebars),
+            [
+                Inline::Text(String::from("Dear ")),
+                Inline::Template(String::from("{{name}}")),
+                Inline::Text(String::from(", ")),
+                Inline::Template(String::from("{{city}}")),
+                Inline::Text(String::from(" R&D & Co")),
+            ]
+        );
+        assert_eq!(
+            split_fields("&NAME&", &TemplateEngine::Tera),
+            [Inline::Template(String::from("{{ name }}"))]
+        );
+    }
+
+    #[test]
+    fn variables() {
+        assert_eq!(field_names("&NAME& lives in &CITY/O&."), ["NAME", "CITY"]);
+        assert_eq!(
+            arguments(" \"Hello, you\", NAME , 20"),
+            ["Hello, you", "NAME", "20"]
+        );
+        assert!(is_variable_name("STREET_2"));
+        assert!(!is_variable_name("2ND"));
+        assert!(!is_variable_name("FIRST NAME"));
+    }
+
+    #[test]
+    fn conditional_blocks() {
+        let condition = " &CITY& = \"Berlin\"";
+        let template = |text: &str| Block::Template(String::from(text));
+        assert_eq!(
+            if_block(condition, None),
+            Block::Comment(String::from("if &CITY& = \"Berlin\""))
+        );
+        assert_eq!(
+            if_block(condition, Some(&TemplateEngine::Handlebars)),
+            template("{{#if (eq city \"Berlin\")}}")
+        );
+        assert_eq!(
+            if_block(condition, Some(&TemplateEngine::Jinja2)),
+            template("{% if city == \"Berlin\" %}")
+        );
+        assert_eq!(
+            if_block(" &CITY&", Some(&TemplateEngine::Tera)),
+            template("{% if city %}")
+        );
+        assert_eq!(
+            else_block(Some(&TemplateEngine::Handlebars)),
+            template("{{else}}")
+        );
+        assert_eq!(end_if_block(None), Block::Comment(String::from("endif")));
+    }
+
+    #[test]
+    fn merging_a_record() {
+        let text = ".rv NAME,COUNT\nDear &NAME&,\n.if &COUNT& > 1\nyour &COUNT& books\n.el\nyour book\n.ei\nhave arrived.\n";
+        let record = HashMap::from([
+            (String::from("NAME"), String::from("Anna")),
+            (String::from("COUNT"), String::from("3")),
+        ]);
+        assert_eq!(
+            merge_record(text, &record, &HashMap::new()),
+            "Dear Anna,\nyour 3 books\nhave arrived.\n"
+        );
+    }
+
+    #[test]
+    fn calculations_as_comments() {
+        assert_eq!(split_math(" TOTAL &PRICE& * 2"), ("TOTAL", "&PRICE& * 2"));
+        assert_eq!(
+            math_comment(" TOTAL &PRICE& * 2", None),
+            Block::Comment(String::from("TOTAL = &PRICE& * 2"))
+        );
+        assert_eq!(
+            math_comment(" TOTAL &PRICE& * 2", Some(&TemplateEngine::Handlebars)),
+            Block::Template(String::from("{{!-- TOTAL = &PRICE& * 2 --}}"))
+        );
+    }
+
+    #[test]
+    fn asked_and_set_variables() {
+        assert_eq!(
+            ask_variable(" \"Your name?\", NAME, 20"),
+            Some((String::from("NAME"), Some(String::from("Your name?"))))
+        );
+        assert_eq!(ask_variable(" NAME"), Some((String::from("NAME"), None)));
+        assert_eq!(
+            set_variable(" CITY, Berlin"),
+            Some((String::from("CITY"), String::from("Berlin")))
+        );
+
+        let mut variables = Vec::new();
+        for variable in ["NAME", "name", "2ND"] {
+            declare(&mut variables, variable);
+        }
+        assert_eq!(variables, ["NAME"]);
+    }
+}
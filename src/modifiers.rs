@@ -0,0 +1,346 @@
+/* ----- CDDL HEADER -----
+ *
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.1 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * https://spdx.org/licenses/CDDL-1.1.html
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ *
+ * ----- CDDL HEADER END -----
+ */
+
+// WordStar's modifiers are toggles: ^B switches bold on, the next ^B switches
+// it off again, whenever that is. The inlines nest though, so we keep track of
+// what is on and open it right before the text it applies to, like spans.
+// What's switched on later is closed first, and opened again after.
+
+use crate::document::{plain_text, Inline};
+
+/// What a modifier makes of its text.
+#[derive(Clone, PartialEq)]
+pub enum Format {
+    Bold,
+    Italic,
+    Underline,
+    Strikeout,
+    Superscript,
+    Subscript,
+    Code,
+    /// Tags around the text, e.g. from the user print codes.
+    Html(String, String),
+    /// Nothing, the text stays as it is.
+    Plain,
+    /// A <span> with this style.
+    Span(String),
+}
+
+/// A modifier whose inline is open, with what's in it so far.
+struct Frame {
+    name: &'static str,
+    format: Format,
+    content: Vec<Inline>,
+}
+
+/// The modifiers which are on, in the order they were switched on, and the
+/// inlines of the text so far.
+#[derive(Default)]
+pub struct Modifiers {
+    on: Vec<(&'static str, Format)>,
+    content: Vec<Inline>,
+    frames: Vec<Frame>,
+}
+
+// Adds an inline, text to the text before it.
+fn push_inline(content: &mut Vec<Inline>, inline: Inline) {
+    if let (Some(Inline::Text(last)), Inline::Text(text)) = (content.last_mut(), &inline) {
+        last.push_str(text);
+        return;
+    }
+    content.push(inline);
+}
+
+// The inlines a modifier's text becomes.
+fn format(format: Format, content: Vec<Inline>) -> Vec<Inline> {
+    match format {
+        Format::Bold => vec![Inline::Bold(content)],
+        Format::Italic => vec![Inline::Italic(content)],
+        Format::Underline => vec![Inline::Underline(content)],
+        Format::Strikeout => vec![Inline::Strikeout(content)],
+        Format::Superscript => vec![Inline::Superscript(content)],
+        Format::Subscript => vec![Inline::Subscript(content)],
+        Format::Code => vec![Inline::Code(plain_text(&content))],
+        Format::Html(open, close) => [Inline::Html(open)]
+            .into_iter()
+            .chain(content)
+            .chain([Inline::Html(close)])
+            .collect(),
+        Format::Plain => content,
+        Format::Span(style) => vec![Inline::Span {
+            attributes: format!("style=\"{}\"", style),
+            content,
+        }],
+    }
+}
+
+// The last character of the text in `content`.
+fn last_character(content: &[Inline]) -> Option<char> {
+    for inline in content.iter().rev() {
+        let character = match inline {
+            Inline::Text(text) | Inline::Code(text) | Inline::Template(text) => {
+                text.chars().next_back()
+            }
+            Inline::Bold(content)
+            | Inline::Italic(content)
+            | Inline::Underline(content)
+            | Inline::Strikeout(content)
+            | Inline::Superscript(content)
+            | Inline::Subscript(content)
+            | Inline::Link { content, .. }
+            | Inline::Span { content, .. } => last_character(content),
+            Inline::Image { description, .. } => description.chars().next_back(),
+            Inline::LineBreak => Some('\n'),
+            Inline::SoftBreak => Some(' '),
+            Inline::Anchor(_) | Inline::Note(_) | Inline::Html(_) => None,
+        };
+        if character.is_some() {
+            return character;
+        }
+    }
+    None
+}
+
+impl Modifiers {
+    /// Whether the modifier is on.
+    pub fn is_on(&self, name: &str) -> bool {
+        self.on.iter().any(|(on, _)| *on == name)
+    }
+
+    /// Switches a modifier on (formatting its text as `format`) or off again.
+    pub fn toggle(&mut self, name: &'static str, format: Format) {
+        self.switch(name, !self.is_on(name), format);
+    }
+
+    /// Switches a modifier on or off, if it isn't already. Modifiers which
+    /// never applied to any text leave nothing behind.
+    pub fn switch(&mut self, name: &'static str, on: bool, format: Format) {
+        match self.on.iter().position(|(on, _)| *on == name) {
+            Some(position) if !on => {
+                self.on.remove(position);
+            }
+            None if on => self.on.push((name, format)),
+            _ => {}
+        }
+    }
+
+    // Where inlines go now.
+    fn target(&mut self) -> &mut Vec<Inline> {
+        match self.frames.last_mut() {
+            Some(frame) => &mut frame.content,
+            None => &mut self.content,
+        }
+    }
+
+    // Closes the innermost open inline.
+    fn close(&mut self) {
+        if let Some(frame) = self.frames.pop() {
+            if frame.content.is_empty() {
+                return;
+            }
+            for inline in format(frame.format, frame.content) {
+                push_inline(self.target(), inline);
+            }
+        }
+    }
+
+    /// Adds an inline, in the modifiers which are on. The ones switched off
+    /// since are closed, the ones switched on are opened.
+    pub fn write(&mut self, inline: Inline) {
+        let open = self
+            .frames
+            .iter()
+            .zip(&self.on)
+            .take_while(|(frame, (name, format))| frame.name == *name && frame.format == *format)
+            .count();
+        while self.frames.len() > open {
+            self.close();
+        }
+        for (name, format) in self.on[open..].iter().cloned() {
+            self.frames.push(Frame {
+                name,
+                format,
+                content: Vec::new(),
+            });
+        }
+        push_inline(self.target(), inline);
+    }
+
+    /// Adds an inline to what's open, without opening anything. This is for
+    /// what goes in front of the text, like margins.
+    pub fn push(&mut self, inline: Inline) {
+        push_inline(self.target(), inline);
+    }
+
+    /// The last character of the text so far, past the modifiers.
+    pub fn last_character(&self) -> Option<char> {
+        for frame in self.frames.iter().rev() {
+            if let Some(character) = last_character(&frame.content) {
+                return Some(character);
+            }
+        }
+        last_character(&self.content)
+    }
+
+    /// The text at the end, for joining lines.
+    pub fn text_end(&mut self) -> &mut String {
+        let target = self.target();
+        if !matches!(target.last(), Some(Inline::Text(_))) {
+            target.push(Inline::Text(String::new()));
+        }
+        match target.last_mut() {
+            Some(Inline::Text(text)) => text,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Closes the open inlines at the end of a line. The modifiers stay on
+    /// and are opened again before the text of the next line.
+    pub fn suspend(&mut self) {
+        while !self.frames.is_empty() {
+            self.close();
+        }
+    }
+
+    /// Switches off everything which is still on. Returns the names of the
+    /// modifiers, innermost first.
+    pub fn close_all(&mut self) -> Vec<&'static str> {
+        self.suspend();
+        self.on.drain(..).rev().map(|(name, _)| name).collect()
+    }
+
+    /// Returns the inlines so far (closing the open ones, which stay on).
+    pub fn take(&mut self) -> Vec<Inline> {
+        self.suspend();
+        let mut content = std::mem::take(&mut self.content);
+        content.retain(|inline| !matches!(inline, Inline::Text(text) if text.is_empty()));
+        content
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Inline {
+        Inline::Text(text.to_string())
+    }
+
+    #[test]
+    fn modifiers_are_opened_before_the_text() {
+        let mut modifiers = Modifiers::default();
+        modifiers.write(text("Some "));
+        modifiers.toggle("bold", Format::Bold);
+        modifiers.write(text("bold"));
+        modifiers.toggle("bold", Format::Bold);
+        modifiers.write(text(" text"));
+        assert_eq!(
+            modifiers.take(),
+            [
+                text("Some "),
+                Inline::Bold(vec![text("bold")]),
+                text(" text")
+            ]
+        );
+    }
+
+    #[test]
+    fn overlapping_modifiers_close_innermost_first() {
+        // The second modifier is opened again after the first is closed.
+        let mut modifiers = Modifiers::default();
+        modifiers.toggle("bold", Format::Bold);
+        modifiers.write(text("one"));
+        modifiers.toggle("italics", Format::Italic);
+        modifiers.write(text("both"));
+        modifiers.toggle("bold", Format::Bold);
+        modifiers.write(text("two"));
+        modifiers.toggle("italics", Format::Italic);
+        assert_eq!(
+            modifiers.take(),
+            [
+                Inline::Bold(vec![text("one"), Inline::Italic(vec![text("both")])]),
+                Inline::Italic(vec![text("two")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn print_codes_are_tags_around_the_text() {
+        let mut modifiers = Modifiers::default();
+        let kbd = Format::Html(String::from("<kbd>"), String::from("</kbd>"));
+        modifiers.toggle("^PQ", kbd.clone());
+        modifiers.write(text("F1"));
+        modifiers.toggle("^PQ", kbd);
+        modifiers.toggle("alternate pitch", Format::Code);
+        modifiers.write(text("a*b"));
+        assert_eq!(
+            modifiers.take(),
+            [
+                Inline::Html(String::from("<kbd>")),
+                text("F1"),
+                Inline::Html(String::from("</kbd>")),
+                Inline::Code(String::from("a*b")),
+            ]
+        );
+    }
+
+    #[test]
+    fn unused_modifiers_are_not_written() {
+        let mut modifiers = Modifiers::default();
+        modifiers.write(text("text"));
+        modifiers.toggle("bold", Format::Bold);
+        modifiers.toggle("bold", Format::Bold);
+        assert_eq!(modifiers.take(), [text("text")]);
+    }
+
+    #[test]
+    fn suspended_modifiers_open_again() {
+        let mut modifiers = Modifiers::default();
+        modifiers.toggle("italics", Format::Italic);
+        modifiers.write(text("one"));
+        assert_eq!(modifiers.take(), [Inline::Italic(vec![text("one")])]);
+        modifiers.write(text("two"));
+        assert_eq!(modifiers.close_all(), ["italics"]);
+        assert_eq!(modifiers.take(), [Inline::Italic(vec![text("two")])]);
+    }
+
+    #[test]
+    fn modifiers_still_on_are_closed_at_the_end() {
+        let mut modifiers = Modifiers::default();
+        modifiers.toggle("bold", Format::Bold);
+        modifiers.toggle("italics", Format::Italic);
+        modifiers.write(text("text"));
+        assert_eq!(modifiers.close_all(), ["italics", "bold"]);
+        assert_eq!(
+            modifiers.take(),
+            [Inline::Bold(vec![Inline::Italic(vec![text("text")])])]
+        );
+    }
+
+    #[test]
+    fn the_last_character_is_past_the_modifiers() {
+        let mut modifiers = Modifiers::default();
+        modifiers.write(text("a "));
+        modifiers.toggle("span", Format::Span(String::from("color:red")));
+        modifiers.write(Inline::Html(String::from("<!-- note -->")));
+        assert_eq!(modifiers.last_character(), Some(' '));
+        modifiers.toggle("underline", Format::Underline);
+        modifiers.write(text("x"));
+        assert_eq!(modifiers.last_character(), Some('x'));
+    }
+}
@@ -0,0 +1,109 @@
+/* ----- CDDL HEADER -----
+ *
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.1 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * https://spdx.org/licenses/CDDL-1.1.html
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ *
+ * ----- CDDL HEADER END -----
+ */
+
+// WordStar's non-document mode (N at the opening menu) writes plain text: no
+// header, no flagged word endings, no control codes. Programs and batch files
+// were written that way, so there's nothing to convert, but some characters
+// mean something in Markdown.
+
+use crate::codepage::Decoder;
+use crate::document::{Block, Builder, Inline};
+use crate::header;
+
+/// Whether `content` looks like a non-document: text only, and no dot
+/// commands, because a document can be plain text too.
+pub fn is_nondocument(content: &[u8]) -> bool {
+    !header::has_header(content)
+        && content
+            .iter()
+            .all(|&byte| matches!(byte, b'\t' | b'\n' | b'\r' | 0x0c | 0x1a | 0x20..=0x7e))
+        && !content.split(|&byte| byte == b'\n').any(is_dot_command)
+}
+
+/// Whether `line` is a dot command (or a `..` comment).
+fn is_dot_command(line: &[u8]) -> bool {
+    match line {
+        [b'.', b'.', ..] => true,
+        [b'.', first, second, ..] => first.is_ascii_alphabetic() && second.is_ascii_alphabetic(),
+        _ => false,
+    }
+}
+
+/// Returns the text of a non-document, decoded by `decoder`.
+pub fn read_text(content: &[u8], decoder: &Decoder) -> String {
+    // Everything after ^Z is padding.
+    let end = content
+        .iter()
+        .position(|&byte| byte == 0x1a)
+        .unwrap_or(content.len());
+    decoder.decode_all(&content[..end])
+}
+
+/// Returns the lines of a non-document as paragraphs, which are separated by
+/// blank lines like in the file.
+pub fn blocks(text: &str) -> Vec<Block> {
+    let mut builder = Builder::default();
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            builder.space(1);
+        } else {
+            builder.paragraph_line(vec![Inline::Text(line.to_string())]);
+        }
+    }
+    builder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_text_is_a_nondocument() {
+        assert!(is_nondocument(b"@echo off\r\ncopy *.txt a:\r\n\x1a"));
+    }
+
+    #[test]
+    fn dot_commands_make_a_document() {
+        assert!(!is_nondocument(b".he My Header\r\nText\r\n.pa\r\n"));
+        assert!(!is_nondocument(b"Text\r\n.PA\r\n"));
+        assert!(!is_nondocument(b"..comment\r\nText\r\n"));
+    }
+
+    #[test]
+    fn leading_dots_in_prose_are_not_dot_commands() {
+        assert!(is_nondocument(b". and so on\r\n.5 percent\r\n"));
+    }
+
+    #[test]
+    fn control_codes_make_a_document() {
+        assert!(!is_nondocument(b"\x02bold\x02\r\n"));
+    }
+
+    #[test]
+    fn lines_stay_apart() {
+        let text = |text: &str| Inline::Text(text.to_string());
+        assert_eq!(
+            blocks("# one\ntwo\n\nthree\n"),
+            [
+                Block::Paragraph(vec![text("# one"), Inline::LineBreak, text("two")]),
+                Block::Space(1),
+                Block::Paragraph(vec![text("three")]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,276 @@
+/* ----- CDDL HEADER -----
+ *
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.1 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * https://spdx.org/licenses/CDDL-1.1.html
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ *
+ * ----- CDDL HEADER END -----
+ */
+
+// Org mode output. The front matter becomes the file's property drawer (and
+// its title a #+TITLE), the rest maps to Org's markup one by one.
+
+use crate::document::{unquote, Block, Document, Inline};
+
+/// Writes `document` in Org's syntax.
+pub fn render(document: &Document) -> String {
+    let mut org = String::new();
+    if !document.metadata.is_empty() {
+        org.push_str(":PROPERTIES:\n");
+        for (key, value) in &document.metadata {
+            org.push_str(&format!(":{}: {}\n", key, unquote(value)));
+        }
+        org.push_str(":END:\n");
+    }
+    if let Some(title) = document.metadata("title") {
+        org.push_str(&format!("#+TITLE: {}\n", title));
+    }
+    if !org.is_empty() {
+        org.push('\n');
+    }
+    org.push_str(&blocks(&document.blocks));
+    org.push('\n');
+    org
+}
+
+// Blank lines are all the same in Org.
+fn blocks(blocks: &[Block]) -> String {
+    let rendered: Vec<String> = blocks
+        .iter()
+        .filter(|block| !matches!(block, Block::Space(_)))
+        .map(block)
+        .collect();
+    rendered.join("\n\n")
+}
+
+// Indents all but the first line of `text`.
+fn indent(text: &str, width: usize) -> String {
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| match index {
+            _ if index == 0 || line.is_empty() => line.to_string(),
+            _ => format!("{}{}", " ".repeat(width), line),
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn block(block: &Block) -> String {
+    match block {
+        Block::Heading { level, content } => {
+            format!("{} {}", "*".repeat(*level), inlines(content))
+        }
+        Block::Paragraph(content) => inlines(content),
+        Block::Code { language, text } if language.is_empty() => {
+            format!("#+begin_example\n{}\n#+end_example", text)
+        }
+        Block::Code { language, text } => {
+            format!("#+begin_src {}\n{}\n#+end_src", language, text)
+        }
+        Block::Quote(content) => format!("#+begin_quote\n{}\n#+end_quote", blocks(content)),
+        Block::List {
+            ordered,
+            start,
+            items,
+        } => items
+            .iter()
+            .enumerate()
+            .map(|(index, item)| {
+                let marker = match (ordered, index) {
+                    // Org counts from 1 unless the first item says otherwise.
+                    (true, 0) if *start != 1 => format!("{0}. [@{0}] ", start),
+                    (true, _) => format!("{}. ", start + index),
+                    (false, _) => "- ".to_string(),
+                };
+                format!("{}{}", marker, indent(&blocks(item), marker.len()))
+            })
+            .collect::<Vec<String>>()
+            .join("\n"),
+        Block::Table { header, rows } => {
+            let row = |cells: &Vec<Vec<Inline>>| {
+                let cells: Vec<String> = cells.iter().map(|cell| inlines(cell)).collect();
+                format!("| {} |", cells.join(" | "))
+            };
+            let mut table = vec![row(header), "|-".to_string()];
+            table.extend(rows.iter().map(row));
+            table.join("\n")
+        }
+        Block::Rule => "-----".to_string(),
+        Block::Div {
+            attributes,
+            content,
+        } if attributes.contains("center") => {
+            format!("#+begin_center\n{}\n#+end_center", blocks(content))
+        }
+        Block::Div { content, .. } => blocks(content),
+        Block::Comment(comment) => comment
+            .lines()
+            .map(|line| format!("# {}", line).trim_end().to_string())
+            .collect::<Vec<String>>()
+            .join("\n"),
+        Block::Template(template) => template.clone(),
+        Block::Space(_) => String::new(),
+    }
+}
+
+fn inlines(inlines: &[Inline]) -> String {
+    let mut org = String::new();
+    for inline in inlines {
+        let line_start = org.is_empty() || org.ends_with('\n');
+        match inline {
+            Inline::Text(text) => org.push_str(&escape(text, line_start)),
+            _ => org.push_str(&self::inline(inline)),
+        }
+    }
+    org
+}
+
+// Whether a line starting with `rest` would be a list item, a table, a
+// keyword, a comment or a rule.
+fn starts_block(rest: &[char]) -> bool {
+    let line: String = rest.iter().take_while(|&&c| c != '\n').collect();
+    let numbered = line.trim_start_matches(|c: char| c.is_ascii_digit());
+    line.starts_with(['#', '|'])
+        || line.starts_with("- ")
+        || line.starts_with(": ")
+        || matches!(line.trim_end(), "-" | ":")
+        || line.starts_with("-----")
+        || (numbered.len() < line.len()
+            && (numbered.starts_with(". ") || numbered.starts_with(") ")))
+}
+
+// Org has no backslash escapes. A zero width space in front keeps markup
+// characters at the edge of a word, and the starts of lines, from being read
+// as markup.
+fn escape(text: &str, line_start: bool) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut org = String::new();
+    let mut at_start = line_start;
+    for (index, &c) in chars.iter().enumerate() {
+        let before = index.checked_sub(1).and_then(|index| chars.get(index));
+        let after = chars.get(index + 1);
+        let opens = before.is_none_or(|c| c.is_whitespace() || "-({'\"".contains(*c))
+            && after.is_some_and(|c| !c.is_whitespace());
+        let closes = before.is_some_and(|c| !c.is_whitespace())
+            && after.is_none_or(|c| c.is_whitespace() || "-.,;:!?')}\"\\".contains(*c));
+        if (at_start && starts_block(&chars[index..]))
+            || ("*/_=~+".contains(c) && (opens || closes))
+            || (c == '[' && after == Some(&'['))
+        {
+            org.push('\u{200b}');
+        }
+        org.push(c);
+        at_start = c == '\n' || (at_start && c == ' ');
+    }
+    org
+}
+
+fn inline(inline: &Inline) -> String {
+    match inline {
+        Inline::Text(text) => escape(text, false),
+        Inline::Bold(content) => format!("*{}*", inlines(content)),
+        Inline::Italic(content) => format!("/{}/", inlines(content)),
+        Inline::Underline(content) => format!("_{}_", inlines(content)),
+        Inline::Strikeout(content) => format!("+{}+", inlines(content)),
+        Inline::Superscript(content) => format!("^{{{}}}", inlines(content)),
+        Inline::Subscript(content) => format!("_{{{}}}", inlines(content)),
+        Inline::Code(code) => format!("={}=", code),
+        Inline::Link { content, target } => format!("[[{}][{}]]", target, inlines(content)),
+        Inline::Image { source, .. } => format!("[[file:{}]]", source),
+        Inline::Anchor(name) => format!("<<{}>>", name),
+        Inline::Span { content, .. } => inlines(content),
+        Inline::LineBreak => "\\\\\n".to_string(),
+        Inline::SoftBreak => "\n".to_string(),
+        Inline::Note(content) => format!("[fn::{}]", inlines(content)),
+        Inline::Template(template) => template.clone(),
+        Inline::Html(html) => format!("@@html:{}@@", html),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Inline {
+        Inline::Text(text.to_string())
+    }
+
+    fn paragraph(content: Vec<Inline>) -> String {
+        render(&Document {
+            metadata: Vec::new(),
+            blocks: vec![Block::Paragraph(content)],
+            right_margin: None,
+        })
+    }
+
+    #[test]
+    fn formatting() {
+        assert_eq!(
+            paragraph(vec![
+                Inline::Bold(vec![text("bold")]),
+                text(" x"),
+                Inline::Superscript(vec![text("2")]),
+                text(" "),
+                Inline::Code(String::from("code")),
+            ]),
+            "*bold* x^{2} =code=\n"
+        );
+    }
+
+    #[test]
+    fn markup_characters_are_escaped() {
+        assert_eq!(
+            paragraph(vec![text("a *star* and snake_case, 1+1")]),
+            "a \u{200b}*star\u{200b}* and snake_case, 1+1\n"
+        );
+        assert_eq!(
+            paragraph(vec![
+                text("#+TITLE: no"),
+                Inline::LineBreak,
+                text("- no item"),
+                Inline::SoftBreak,
+                text("2. no item"),
+            ]),
+            "\u{200b}#+TITLE: no\\\\\n\u{200b}- no item\n\u{200b}2. no item\n"
+        );
+    }
+
+    #[test]
+    fn ordered_lists_keep_their_start() {
+        let item = |content: &str| vec![Block::Paragraph(vec![text(content)])];
+        let document = Document {
+            metadata: Vec::new(),
+            blocks: vec![Block::List {
+                ordered: true,
+                start: 3,
+                items: vec![item("three"), item("four")],
+            }],
+            right_margin: None,
+        };
+        assert_eq!(render(&document), "3. [@3] three\n4. four\n");
+    }
+
+    #[test]
+    fn paragraphs_may_start_with_formatting() {
+        let options = crate::Options::default();
+        let document = crate::convert(
+            "\u{13}Chapter one\u{13} begins here.\r\nSecond para & more.\r\n",
+            &options,
+            &[std::path::PathBuf::from("test.ws")],
+        )
+        .unwrap();
+        assert_eq!(
+            render(&document),
+            "_Chapter_ _one_ begins here.\\\\\nSecond para & more.\n"
+        );
+    }
+}
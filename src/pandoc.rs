@@ -0,0 +1,380 @@
+/* ----- CDDL HEADER -----
+ *
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.1 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * https://spdx.org/licenses/CDDL-1.1.html
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ *
+ * ----- CDDL HEADER END -----
+ */
+
+// Pandoc's JSON AST (what `pandoc -t json` writes and `pandoc -f json`
+// reads), so that Pandoc can write everything else from our document.
+
+use crate::diagnostics::json_string;
+use crate::document::{Block, Document, Inline};
+
+// The version of pandoc-types this follows (Pandoc 3).
+const API_VERSION: &str = "[1,23,1]";
+
+fn node(kind: &str, content: &str) -> String {
+    format!("{{\"t\":\"{}\",\"c\":{}}}", kind, content)
+}
+
+fn empty_node(kind: &str) -> String {
+    format!("{{\"t\":\"{}\"}}", kind)
+}
+
+fn array(items: impl IntoIterator<Item = String>) -> String {
+    format!("[{}]", items.into_iter().collect::<Vec<String>>().join(","))
+}
+
+// Splits attributes at the spaces which aren't quoted.
+fn split_attributes(attributes: &str) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut quoted = false;
+    for character in attributes.chars() {
+        match character {
+            '"' => quoted = !quoted,
+            ' ' if !quoted => parts.push(String::new()),
+            _ => parts.last_mut().unwrap().push(character),
+        }
+    }
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+// Our attributes, HTML (align="center") or Pandoc's ({#id .center}), as
+// Pandoc's [identifier, classes, key-value pairs].
+fn attr(attributes: &str) -> String {
+    let attributes = attributes
+        .trim()
+        .trim_start_matches('{')
+        .trim_end_matches('}');
+    let mut identifier = String::new();
+    let mut classes = Vec::new();
+    let mut pairs = Vec::new();
+    for part in split_attributes(attributes) {
+        if let Some(name) = part.strip_prefix('#') {
+            identifier = name.to_string();
+        } else if let Some(class) = part.strip_prefix('.') {
+            classes.push(class.to_string());
+        } else if let Some((key, value)) = part.split_once('=') {
+            match key {
+                "id" => identifier = value.to_string(),
+                "class" => classes.extend(value.split_whitespace().map(str::to_string)),
+                _ => pairs.push(array([json_string(key), json_string(value)])),
+            }
+        } else {
+            // ::: center
+            classes.push(part);
+        }
+    }
+    array([
+        json_string(&identifier),
+        array(classes.iter().map(|class| json_string(class))),
+        array(pairs),
+    ])
+}
+
+fn no_attr() -> String {
+    attr("")
+}
+
+// Pandoc has no blank lines of its own.
+fn blocks(blocks: &[Block]) -> String {
+    array(
+        blocks
+            .iter()
+            .filter(|block| !matches!(block, Block::Space(_)))
+            .map(block),
+    )
+}
+
+// List items and table cells are plain, if they're only a paragraph.
+fn plain_blocks(blocks: &[Block]) -> String {
+    match blocks {
+        [Block::Paragraph(content)] => array([node("Plain", &inlines(content))]),
+        _ => self::blocks(blocks),
+    }
+}
+
+fn block(block: &Block) -> String {
+    match block {
+        Block::Heading { level, content } => node(
+            "Header",
+            &format!("[{},{},{}]", level, no_attr(), inlines(content)),
+        ),
+        Block::Paragraph(content) => node("Para", &inlines(content)),
+        Block::Code { language, text } if language.is_empty() => {
+            node("CodeBlock", &array([no_attr(), json_string(text)]))
+        }
+        Block::Code { language, text } => node(
+            "CodeBlock",
+            &array([attr(&format!(".{}", language)), json_string(text)]),
+        ),
+        Block::Quote(content) => node("BlockQuote", &blocks(content)),
+        Block::List {
+            ordered,
+            start,
+            items,
+        } => {
+            let items = array(items.iter().map(|item| plain_blocks(item)));
+            if *ordered {
+                let attributes = format!(
+                    "[{},{},{}]",
+                    start,
+                    empty_node("Decimal"),
+                    empty_node("Period")
+                );
+                node("OrderedList", &array([attributes, items]))
+            } else {
+                node("BulletList", &items)
+            }
+        }
+        Block::Table { header, rows } => {
+            let columns = rows.iter().map(Vec::len).chain([header.len()]).max();
+            let column_specifications = array(
+                (0..columns.unwrap_or(0))
+                    .map(|_| array([empty_node("AlignDefault"), empty_node("ColWidthDefault")])),
+            );
+            let row = |cells: &Vec<Vec<Inline>>| {
+                let cells = cells.iter().map(|cell| {
+                    array([
+                        no_attr(),
+                        empty_node("AlignDefault"),
+                        "1".to_string(),
+                        "1".to_string(),
+                        array([node("Plain", &inlines(cell))]),
+                    ])
+                });
+                array([no_attr(), array(cells)])
+            };
+            node(
+                "Table",
+                &array([
+                    no_attr(),
+                    "[null,[]]".to_string(),
+                    column_specifications,
+                    array([no_attr(), array([row(header)])]),
+                    array([array([
+                        no_attr(),
+                        "0".to_string(),
+                        "[]".to_string(),
+                        array(rows.iter().map(row)),
+                    ])]),
+                    array([no_attr(), "[]".to_string()]),
+                ]),
+            )
+        }
+        Block::Rule => empty_node("HorizontalRule"),
+        Block::Div {
+            attributes,
+            content,
+        } => node("Div", &array([attr(attributes), blocks(content)])),
+        Block::Comment(comment) => node(
+            "RawBlock",
+            &array([
+                json_string("html"),
+                json_string(&format!("<!-- {} -->", comment)),
+            ]),
+        ),
+        Block::Template(template) => node(
+            "RawBlock",
+            &array([json_string("html"), json_string(template)]),
+        ),
+        Block::Space(_) => node("Div", &array([no_attr(), "[]".to_string()])),
+    }
+}
+
+fn inlines(inlines: &[Inline]) -> String {
+    array(inlines.iter().flat_map(inline))
+}
+
+// Text is words and spaces to Pandoc.
+fn text(text: &str) -> Vec<String> {
+    let mut nodes = Vec::new();
+    for (index, line) in text.split('\n').enumerate() {
+        if index > 0 {
+            nodes.push(empty_node("SoftBreak"));
+        }
+        for (index, word) in line.split(' ').enumerate() {
+            if index > 0 && !matches!(nodes.last(), Some(last) if *last == empty_node("Space")) {
+                nodes.push(empty_node("Space"));
+            }
+            if !word.is_empty() {
+                nodes.push(node("Str", &json_string(word)));
+            }
+        }
+    }
+    nodes
+}
+
+fn inline(inline: &Inline) -> Vec<String> {
+    let node = |kind, content: &[Inline]| vec![node(kind, &inlines(content))];
+    match inline {
+        Inline::Text(content) => text(content),
+        Inline::Bold(content) => node("Strong", content),
+        Inline::Italic(content) => node("Emph", content),
+        Inline::Underline(content) => node("Underline", content),
+        Inline::Strikeout(content) => node("Strikeout", content),
+        Inline::Superscript(content) => node("Superscript", content),
+        Inline::Subscript(content) => node("Subscript", content),
+        Inline::Code(code) => vec![self::node("Code", &array([no_attr(), json_string(code)]))],
+        Inline::Link { content, target } => vec![self::node(
+            "Link",
+            &array([
+                no_attr(),
+                inlines(content),
+                array([json_string(target), json_string("")]),
+            ]),
+        )],
+        Inline::Image {
+            description,
+            source,
+        } => vec![self::node(
+            "Image",
+            &array([
+                no_attr(),
+                array(text(description)),
+                array([json_string(source), json_string("")]),
+            ]),
+        )],
+        Inline::Anchor(name) => vec![self::node(
+            "Span",
+            &array([attr(&format!("#{}", name)), "[]".to_string()]),
+        )],
+        Inline::Span {
+            attributes,
+            content,
+        } => vec![self::node(
+            "Span",
+            &array([attr(attributes), inlines(content)]),
+        )],
+        Inline::LineBreak => vec![empty_node("LineBreak")],
+        Inline::SoftBreak => vec![empty_node("SoftBreak")],
+        Inline::Note(content) => vec![self::node(
+            "Note",
+            &array([self::node("Para", &inlines(content))]),
+        )],
+        Inline::Template(html) | Inline::Html(html) => vec![self::node(
+            "RawInline",
+            &array([json_string("html"), json_string(html)]),
+        )],
+    }
+}
+
+// The front matter is all text, except for the flags.
+fn meta_value(value: &str) -> String {
+    match value {
+        "true" | "false" => node("MetaBool", value),
+        _ => node("MetaString", &json_string(value)),
+    }
+}
+
+/// Writes `document` as Pandoc's JSON.
+pub fn render(document: &Document) -> String {
+    let meta: Vec<String> = document
+        .metadata
+        .iter()
+        .map(|(key, _)| {
+            let value = document.metadata(key).unwrap_or_default();
+            format!("{}:{}", json_string(key), meta_value(&value))
+        })
+        .collect();
+    format!(
+        "{{\"pandoc-api-version\":{},\"meta\":{{{}}},\"blocks\":{}}}\n",
+        API_VERSION,
+        meta.join(","),
+        blocks(&document.blocks)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Inline {
+        Inline::Text(text.to_string())
+    }
+
+    fn item(content: &str) -> Vec<Block> {
+        vec![Block::Paragraph(vec![text(content)])]
+    }
+
+    #[test]
+    fn headers() {
+        assert_eq!(
+            block(&Block::Heading {
+                level: 2,
+                content: vec![text("Two words")],
+            }),
+            "{\"t\":\"Header\",\"c\":[2,[\"\",[],[]],[{\"t\":\"Str\",\"c\":\"Two\"},{\"t\":\"Space\"},{\"t\":\"Str\",\"c\":\"words\"}]]}"
+        );
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(
+            block(&Block::List {
+                ordered: false,
+                start: 1,
+                items: vec![item("one")],
+            }),
+            "{\"t\":\"BulletList\",\"c\":[[{\"t\":\"Plain\",\"c\":[{\"t\":\"Str\",\"c\":\"one\"}]}]]}"
+        );
+        assert_eq!(
+            block(&Block::List {
+                ordered: true,
+                start: 3,
+                items: vec![item("three")],
+            }),
+            "{\"t\":\"OrderedList\",\"c\":[[3,{\"t\":\"Decimal\"},{\"t\":\"Period\"}],[[{\"t\":\"Plain\",\"c\":[{\"t\":\"Str\",\"c\":\"three\"}]}]]]}"
+        );
+    }
+
+    #[test]
+    fn tables() {
+        let table = block(&Block::Table {
+            header: vec![vec![text("Name")], vec![text("Price")]],
+            rows: vec![vec![vec![text("Pear")], vec![text("0.80")]]],
+        });
+        assert!(table.starts_with(
+            "{\"t\":\"Table\",\"c\":[[\"\",[],[]],[null,[]],[[{\"t\":\"AlignDefault\"},{\"t\":\"ColWidthDefault\"}],[{\"t\":\"AlignDefault\"},{\"t\":\"ColWidthDefault\"}]],"
+        ));
+        assert_eq!(table.matches("{\"t\":\"Plain\"").count(), 4);
+        assert!(table.contains("[{\"t\":\"Str\",\"c\":\"0.80\"}]"));
+    }
+
+    #[test]
+    fn notes() {
+        assert_eq!(
+            inlines(&[text("See"), Inline::Note(vec![text("there")])]),
+            "[{\"t\":\"Str\",\"c\":\"See\"},{\"t\":\"Note\",\"c\":[{\"t\":\"Para\",\"c\":[{\"t\":\"Str\",\"c\":\"there\"}]}]}]"
+        );
+    }
+
+    #[test]
+    fn paragraphs_may_start_with_formatting() {
+        let options = crate::Options::default();
+        let document = crate::convert(
+            "\u{13}Chapter one\u{13} begins here.\r\nSecond para & more.\r\n",
+            &options,
+            &[std::path::PathBuf::from("test.ws")],
+        )
+        .unwrap();
+        let json = render(&document);
+        assert!(json.contains(
+            "\"blocks\":[{\"t\":\"Para\",\"c\":[{\"t\":\"Underline\",\"c\":[{\"t\":\"Str\",\"c\":\"Chapter\"}]}"
+        ));
+        assert!(json.contains("{\"t\":\"Str\",\"c\":\"&\"}"));
+    }
+}
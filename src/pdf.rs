@@ -0,0 +1,159 @@
+/* ----- CDDL HEADER -----
+ *
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.1 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * https://spdx.org/licenses/CDDL-1.1.html
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ *
+ * ----- CDDL HEADER END -----
+ */
+
+// PDF output: the HTML output, printed by a program which knows how to lay
+// out web pages. Chromium (or Chrome), wkhtmltopdf and WeasyPrint all do.
+
+use anyhow::{Context, Result};
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// The programs we look for, in this order.
+const ENGINES: [&str; 5] = [
+    "chromium",
+    "chromium-browser",
+    "google-chrome",
+    "wkhtmltopdf",
+    "weasyprint",
+];
+
+// The first engine which is in the PATH.
+fn find_engine() -> Option<PathBuf> {
+    let path = env::var_os("PATH")?;
+    ENGINES.iter().find_map(|engine| {
+        env::split_paths(&path)
+            .flat_map(|directory| {
+                [
+                    directory.join(engine),
+                    directory.join(format!("{}.exe", engine)),
+                ]
+            })
+            .find(|file| file.is_file())
+    })
+}
+
+// A new directory in the temp directory which only we can use, so nobody
+// can put (or link) files where the engine reads and writes.
+fn private_directory() -> Result<PathBuf> {
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    let mut attempt = 0;
+    loop {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.subsec_nanos());
+        let directory = env::temp_dir().join(format!(
+            "ws2markdown-{}-{}-{}",
+            process::id(),
+            time,
+            attempt
+        ));
+        match builder.create(&directory) {
+            Ok(()) => return Ok(directory),
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists && attempt < 100 => {
+                attempt += 1
+            }
+            Err(error) => return Err(error).context("could not create a directory for the PDF"),
+        }
+    }
+}
+
+/// Prints `html` to PDF with `engine`, or with the first one found.
+pub fn render(html: &str, engine: Option<&str>) -> Result<Vec<u8>> {
+    let engine = match engine {
+        Some(engine) => PathBuf::from(engine),
+        None => find_engine().context(
+            "--to=pdf needs chromium, google-chrome, wkhtmltopdf or weasyprint (or --pdf-engine)",
+        )?,
+    };
+    let directory = private_directory()?;
+    let pdf = print(html, &engine, &directory);
+    let _ = fs::remove_dir_all(&directory);
+    pdf
+}
+
+// Prints `html` to PDF with `engine`, in `directory`.
+fn print(html: &str, engine: &Path, directory: &Path) -> Result<Vec<u8>> {
+    let html_file = directory.join("document.html");
+    let pdf_file = directory.join("document.pdf");
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&html_file)
+        .and_then(|mut file| file.write_all(html.as_bytes()))
+        .context("could not write the HTML for the PDF")?;
+
+    // The engines don't agree on their arguments.
+    let name = engine
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+    let mut command = process::Command::new(engine);
+    if name.contains("wkhtmltopdf") {
+        command
+            .args(["--quiet", "--print-media-type"])
+            .arg(&html_file)
+            .arg(&pdf_file);
+    } else if name.contains("weasyprint") {
+        command.arg(&html_file).arg(&pdf_file);
+    } else {
+        command
+            .args(["--headless", "--disable-gpu", "--no-pdf-header-footer"])
+            .arg(format!("--print-to-pdf={}", pdf_file.display()))
+            .arg(&html_file);
+    }
+    let output = command
+        .output()
+        .with_context(|| format!("could not run {}", engine.display()))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "{} failed ({}) {}",
+            engine.display(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    fs::read(&pdf_file).with_context(|| format!("{} didn't write a PDF", engine.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_run_gets_its_own_directory() {
+        let first = private_directory().unwrap();
+        let second = private_directory().unwrap();
+        assert_ne!(first, second);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&first).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o700);
+        }
+        fs::remove_dir(first).unwrap();
+        fs::remove_dir(second).unwrap();
+    }
+}
@@ -0,0 +1,569 @@
+/* ----- CDDL HEADER -----
+ *
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.1 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * https://spdx.org/licenses/CDDL-1.1.html
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ *
+ * ----- CDDL HEADER END -----
+ */
+
+// Passes over the converted document, on the lines of its paragraphs (the
+// text between the line breaks).
+
+use crate::document::{plain_text, visit_inlines, Block, Inline};
+
+/// Splits a paragraph into its lines.
+fn lines(content: Vec<Inline>) -> Vec<Vec<Inline>> {
+    let mut lines = vec![Vec::new()];
+    for inline in content {
+        match inline {
+            Inline::LineBreak => lines.push(Vec::new()),
+            inline => lines.last_mut().unwrap().push(inline),
+        }
+    }
+    lines
+}
+
+/// Joins lines into a paragraph again.
+fn paragraph(lines: Vec<Vec<Inline>>) -> Block {
+    let mut content = Vec::new();
+    for (index, line) in lines.into_iter().enumerate() {
+        if index > 0 {
+            content.push(Inline::LineBreak);
+        }
+        content.extend(line);
+    }
+    Block::Paragraph(content)
+}
+
+/// Replaces the runs of lines (in all paragraphs) whose text is `selected`
+/// by the block `make` makes of them, if it makes one.
+fn replace_lines(
+    blocks: &mut Vec<Block>,
+    selected: &dyn Fn(&str) -> bool,
+    make: &dyn Fn(&[Vec<Inline>]) -> Option<Block>,
+) {
+    for block in std::mem::take(blocks) {
+        let content = match block {
+            Block::Paragraph(content) => content,
+            Block::Quote(mut content) => {
+                replace_lines(&mut content, selected, make);
+                blocks.push(Block::Quote(content));
+                continue;
+            }
+            Block::Div {
+                attributes,
+                mut content,
+            } => {
+                replace_lines(&mut content, selected, make);
+                blocks.push(Block::Div {
+                    attributes,
+                    content,
+                });
+                continue;
+            }
+            Block::List {
+                ordered,
+                start,
+                mut items,
+            } => {
+                for item in &mut items {
+                    replace_lines(item, selected, make);
+                }
+                blocks.push(Block::List {
+                    ordered,
+                    start,
+                    items,
+                });
+                continue;
+            }
+            block => {
+                blocks.push(block);
+                continue;
+            }
+        };
+
+        // Lines are collected until one isn't selected.
+        let mut other_lines = Vec::new();
+        let mut run: Vec<Vec<Inline>> = Vec::new();
+        let end_run = |run: &mut Vec<Vec<Inline>>,
+                       other_lines: &mut Vec<Vec<Inline>>,
+                       blocks: &mut Vec<Block>| {
+            if run.is_empty() {
+                return;
+            }
+            match make(run) {
+                Some(block) => {
+                    if !other_lines.is_empty() {
+                        blocks.push(paragraph(std::mem::take(other_lines)));
+                    }
+                    blocks.push(block);
+                    run.clear();
+                }
+                None => other_lines.append(run),
+            }
+        };
+        for line in lines(content) {
+            if selected(&plain_text(&line)) {
+                run.push(line);
+                continue;
+            }
+            end_run(&mut run, &mut other_lines, blocks);
+            other_lines.push(line);
+        }
+        end_run(&mut run, &mut other_lines, blocks);
+        if !other_lines.is_empty() {
+            blocks.push(paragraph(other_lines));
+        }
+    }
+}
+
+/// Calls `visit` for the inlines of every paragraph, heading and table
+/// cell in `blocks`.
+fn visit_contents(blocks: &mut [Block], visit: &mut dyn FnMut(&mut Vec<Inline>)) {
+    for block in blocks {
+        match block {
+            Block::Heading { content, .. } | Block::Paragraph(content) => visit(content),
+            Block::Quote(content) | Block::Div { content, .. } => visit_contents(content, visit),
+            Block::List { items, .. } => {
+                for item in items {
+                    visit_contents(item, visit);
+                }
+            }
+            Block::Table { header, rows } => {
+                for cell in header.iter_mut().chain(rows.iter_mut().flatten()) {
+                    visit(cell);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Calls `visit` for the text of every code block in `blocks`.
+fn visit_code(blocks: &mut [Block], visit: &mut dyn FnMut(&mut String)) {
+    for block in blocks {
+        match block {
+            Block::Code { text, .. } => visit(text),
+            Block::Quote(content) | Block::Div { content, .. } => visit_code(content, visit),
+            Block::List { items, .. } => {
+                for item in items {
+                    visit_code(item, visit);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Removes the spaces and tabs at the end of every line.
+pub fn trim_trailing_spaces(blocks: &mut [Block]) {
+    visit_contents(blocks, &mut |content| {
+        for index in 0..content.len() {
+            let line_end = content
+                .get(index + 1)
+                .is_none_or(|next| *next == Inline::LineBreak);
+            if let (Inline::Text(text), true) = (&mut content[index], line_end) {
+                text.truncate(text.trim_end_matches([' ', '\t']).len());
+            }
+        }
+    });
+    visit_code(blocks, &mut |text| {
+        *text = text
+            .split('\n')
+            .map(|line| line.trim_end_matches([' ', '\t']))
+            .collect::<Vec<&str>>()
+            .join("\n");
+    });
+}
+
+/// Collapses runs of spaces in `text` to one space, but the ones it starts
+/// with if it starts a line.
+fn collapse(text: &str, line_start: bool) -> String {
+    let indentation = match line_start {
+        true => text.len() - text.trim_start_matches(' ').len(),
+        false => 0,
+    };
+    let mut collapsed_text = text[..indentation].to_string();
+    let mut previous = None;
+    for character in text[indentation..].chars() {
+        if character != ' ' || previous != Some(' ') {
+            collapsed_text.push(character);
+        }
+        previous = Some(character);
+    }
+    collapsed_text
+}
+
+/// Collapses runs of spaces to one space. Indentation, code blocks and
+/// inline code are left alone.
+pub fn collapse_spaces(blocks: &mut [Block]) {
+    fn collapse_all(content: &mut [Inline], mut line_start: bool) {
+        for inline in content {
+            match inline {
+                Inline::Text(text) => *text = collapse(text, line_start),
+                Inline::Bold(content)
+                | Inline::Italic(content)
+                | Inline::Underline(content)
+                | Inline::Strikeout(content)
+                | Inline::Superscript(content)
+                | Inline::Subscript(content)
+                | Inline::Link { content, .. }
+                | Inline::Span { content, .. } => collapse_all(content, false),
+                _ => {}
+            }
+            line_start = *inline == Inline::LineBreak;
+        }
+    }
+    visit_contents(blocks, &mut |content| collapse_all(content, true));
+}
+
+/// Removes the form feeds which are left over from page breaks.
+pub fn strip_form_feeds(blocks: &mut [Block]) {
+    visit_inlines(blocks, &mut |inline| {
+        if let Inline::Text(text) = inline {
+            text.retain(|c| c != '\u{c}');
+        }
+    });
+    visit_code(blocks, &mut |text| text.retain(|c| c != '\u{c}'));
+}
+
+/// How many lines (including the header) a table needs at least.
+const MIN_TABLE_ROWS: usize = 3;
+
+/// How many spaces separate two columns at least.
+const MIN_COLUMN_GAP: usize = 2;
+
+/// Removes the layout in front of a line: margins of spaces and binding
+/// spaces.
+fn strip_margin(line: &str) -> &str {
+    line.trim_start_matches([' ', '\u{a0}'])
+}
+
+/// Whether a line might be a table row: text with a column gap in it.
+fn has_column_gap(line: &str) -> bool {
+    strip_margin(line)
+        .trim_end()
+        .contains(&" ".repeat(MIN_COLUMN_GAP))
+}
+
+/// Splits the lines of a block into cells, if all of them have their column
+/// gaps in the same places and there are at least two columns.
+fn split_columns(lines: &[String]) -> Option<Vec<Vec<String>>> {
+    let rows: Vec<Vec<char>> = lines
+        .iter()
+        .map(|line| strip_margin(line).trim_end().chars().collect())
+        .collect();
+    let width = rows.iter().map(Vec::len).max()?;
+
+    // A column is blank if every line has a space there (or has ended).
+    let blank = |column: usize| {
+        rows.iter()
+            .all(|row| row.get(column).is_none_or(|&c| c == ' '))
+    };
+
+    // Cells are between the gaps.
+    let mut cells: Vec<(usize, usize)> = Vec::new();
+    let mut cell_start = None;
+    let mut gap = 0;
+    for column in 0..width {
+        if blank(column) {
+            gap += 1;
+            continue;
+        }
+        match cell_start {
+            None => cell_start = Some(column),
+            Some(start) if gap >= MIN_COLUMN_GAP => {
+                cells.push((start, column - gap));
+                cell_start = Some(column);
+            }
+            Some(_) => {}
+        }
+        gap = 0;
+    }
+    cells.push((cell_start?, width));
+    if cells.len() < 2 {
+        return None;
+    }
+
+    let table: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| {
+            cells
+                .iter()
+                .map(|&(start, end)| {
+                    let cell: String = row[start.min(row.len())..end.min(row.len())]
+                        .iter()
+                        .collect();
+                    cell.trim().to_string()
+                })
+                .collect()
+        })
+        .collect();
+
+    // Every row needs at least two filled cells, or it's not a table row.
+    table
+        .iter()
+        .all(|row| row.iter().filter(|cell| !cell.is_empty()).count() >= 2)
+        .then_some(table)
+}
+
+/// The cells of a table row.
+fn row(cells: Vec<String>) -> Vec<Vec<Inline>> {
+    cells
+        .into_iter()
+        .map(|cell| match cell.is_empty() {
+            true => Vec::new(),
+            false => vec![Inline::Text(cell)],
+        })
+        .collect()
+}
+
+/// Turns runs of lines with their text aligned in columns into tables,
+/// the first line being the header.
+pub fn format_tables(blocks: &mut Vec<Block>) {
+    replace_lines(blocks, &has_column_gap, &|lines| {
+        if lines.len() < MIN_TABLE_ROWS {
+            return None;
+        }
+        let lines: Vec<String> = lines.iter().map(|line| plain_text(line)).collect();
+        let mut table = split_columns(&lines)?.into_iter();
+        Some(Block::Table {
+            header: row(table.next()?),
+            rows: table.map(row).collect(),
+        })
+    });
+}
+
+/// How much of a drawing's text has to be lines, at least.
+const MIN_DRAWING_SHARE: f64 = 0.5;
+
+/// Whether a character is part of a (box or ASCII) drawing.
+fn is_drawing_character(character: char) -> bool {
+    matches!(
+        character,
+        '\u{2500}'..='\u{259f}' | '+' | '-' | '|' | '=' | '_'
+    )
+}
+
+/// Puts runs of lines of box drawings and ASCII art into code blocks, so
+/// their alignment survives proportional fonts.
+pub fn fence_drawings(blocks: &mut Vec<Block>) {
+    replace_lines(
+        blocks,
+        &|line| line.chars().any(is_drawing_character),
+        &|lines| {
+            let lines: Vec<String> = lines.iter().map(|line| plain_text(line)).collect();
+            let characters = lines
+                .iter()
+                .flat_map(|line| line.chars())
+                .filter(|character| !character.is_whitespace());
+            let (drawing_characters, all) = characters.fold((0, 0), |(lines, all), character| {
+                (
+                    lines + usize::from(is_drawing_character(character)),
+                    all + 1,
+                )
+            });
+
+            // Single lines are rules, not drawings.
+            let drawing =
+                lines.len() > 1 && drawing_characters as f64 >= all as f64 * MIN_DRAWING_SHARE;
+            drawing.then(|| Block::Code {
+                language: String::new(),
+                // Margins are spaces again in code blocks.
+                text: lines.join("\n").replace('\u{a0}', " "),
+            })
+        },
+    );
+}
+
+/// How many periods make a dot leader, at least.
+const MIN_LEADER_DOTS: usize = 4;
+
+/// Returns where the dot leader (text ..... page) in a line starts and ends.
+fn find_leader(line: &str) -> Option<(usize, usize)> {
+    let mut span_start = None;
+    let mut dots = 0;
+    for (position, character) in line.char_indices() {
+        match character {
+            '.' | ' ' => {
+                span_start.get_or_insert(position);
+                dots += usize::from(character == '.');
+            }
+            _ => {
+                if let Some(start) = span_start.take() {
+                    if dots >= MIN_LEADER_DOTS && start > 0 {
+                        return Some((start, position));
+                    }
+                }
+                dots = 0;
+            }
+        }
+    }
+    None
+}
+
+/// Replaces dot leaders by `separator`.
+pub fn collapse_leaders(blocks: &mut [Block], separator: &str) {
+    visit_inlines(blocks, &mut |inline| {
+        if let Inline::Text(text) = inline {
+            if let Some((start, end)) = find_leader(text) {
+                text.replace_range(start..end, separator);
+            }
+        }
+    });
+}
+
+/// Turns runs of lines with dot leaders into the rows of a two-column
+/// table, which has no header.
+pub fn tabulate_leaders(blocks: &mut Vec<Block>) {
+    replace_lines(blocks, &|line| find_leader(line).is_some(), &|lines| {
+        let rows = lines
+            .iter()
+            .map(|line| {
+                let line = plain_text(line);
+                let (start, end) = find_leader(&line).unwrap();
+                row(vec![
+                    strip_margin(&line[..start]).to_string(),
+                    line[end..].trim_end().to_string(),
+                ])
+            })
+            .collect();
+        Some(Block::Table {
+            header: vec![Vec::new(), Vec::new()],
+            rows,
+        })
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Inline {
+        Inline::Text(text.to_string())
+    }
+
+    // A paragraph of lines of text.
+    fn lines(lines: &[&str]) -> Block {
+        paragraph(lines.iter().map(|line| vec![text(line)]).collect())
+    }
+
+    fn cells(cells: &[&str]) -> Vec<Vec<Inline>> {
+        row(cells.iter().map(|cell| cell.to_string()).collect())
+    }
+
+    #[test]
+    fn aligned_columns_become_a_table() {
+        let mut blocks = vec![lines(&[
+            "Before",
+            "Name    Price",
+            "Apple   1.20",
+            "Pear    0.80",
+            "After",
+        ])];
+        format_tables(&mut blocks);
+        assert_eq!(
+            blocks,
+            [
+                lines(&["Before"]),
+                Block::Table {
+                    header: cells(&["Name", "Price"]),
+                    rows: vec![cells(&["Apple", "1.20"]), cells(&["Pear", "0.80"])],
+                },
+                lines(&["After"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_cells() {
+        let mut blocks = vec![lines(&["A     B     C", "a|b         1", "x     y     2"])];
+        format_tables(&mut blocks);
+        assert_eq!(
+            blocks,
+            [Block::Table {
+                header: cells(&["A", "B", "C"]),
+                rows: vec![cells(&["a|b", "", "1"]), cells(&["x", "y", "2"])],
+            }]
+        );
+    }
+
+    #[test]
+    fn too_few_rows_are_no_table() {
+        let mut blocks = vec![lines(&["Name    Price", "Apple   1.20"])];
+        let unchanged = blocks.clone();
+        format_tables(&mut blocks);
+        assert_eq!(blocks, unchanged);
+    }
+
+    #[test]
+    fn misaligned_lines_are_no_table() {
+        let mut blocks = vec![lines(&["Name    Price", "An apple a day", "Pear    0.80"])];
+        let unchanged = blocks.clone();
+        format_tables(&mut blocks);
+        assert_eq!(blocks, unchanged);
+    }
+
+    #[test]
+    fn code_blocks_are_no_tables() {
+        let mut blocks = vec![Block::Code {
+            language: String::new(),
+            text: String::from("Name    Price\nApple   1.20\nPear    0.80"),
+        }];
+        let unchanged = blocks.clone();
+        format_tables(&mut blocks);
+        assert_eq!(blocks, unchanged);
+    }
+
+    #[test]
+    fn drawings_become_code() {
+        let mut blocks = vec![lines(&["Text", "\u{a0}+--+", "\u{a0}|  |", "\u{a0}+--+"])];
+        fence_drawings(&mut blocks);
+        assert_eq!(
+            blocks,
+            [
+                lines(&["Text"]),
+                Block::Code {
+                    language: String::new(),
+                    text: String::from(" +--+\n |  |\n +--+"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn dot_leaders() {
+        let mut blocks = vec![lines(&["Introduction ....... 1", "Index .......... 12"])];
+        tabulate_leaders(&mut blocks);
+        assert_eq!(
+            blocks,
+            [Block::Table {
+                header: vec![Vec::new(), Vec::new()],
+                rows: vec![cells(&["Introduction", "1"]), cells(&["Index", "12"])],
+            }]
+        );
+
+        let mut blocks = vec![lines(&["Introduction ....... 1"])];
+        collapse_leaders(&mut blocks, " — ");
+        assert_eq!(blocks, [lines(&["Introduction — 1"])]);
+    }
+
+    #[test]
+    fn whitespace() {
+        let mut blocks = vec![lines(&["  one   two  ", "\u{c}three"])];
+        collapse_spaces(&mut blocks);
+        trim_trailing_spaces(&mut blocks);
+        strip_form_feeds(&mut blocks);
+        assert_eq!(blocks, [lines(&["  one two", "three"])]);
+    }
+}
@@ -0,0 +1,111 @@
+/* ----- CDDL HEADER -----
+ *
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.1 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * https://spdx.org/licenses/CDDL-1.1.html
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ *
+ * ----- CDDL HEADER END -----
+ */
+
+// WordStar rulers (.rr) look like this:
+//
+//   .rr----L----!----!--------#-------R
+//
+// Every character is one column, starting right after ".rr". "L" is the left
+// margin, "P" is the paragraph margin, "!" and "#" (decimal tab) are tab stops
+// and "R" is the right margin.
+
+/// Tab width for text without tab stops.
+const DEFAULT_TAB_WIDTH: usize = 8;
+
+/// A WordStar ruler, as far as we need it.
+#[derive(Default)]
+pub struct Ruler {
+    /// The left margin, in columns.
+    pub left_margin: usize,
+    /// The paragraph margin, in columns, if there is one.
+    pub paragraph_margin: Option<usize>,
+    /// The right margin, in columns, if there is one.
+    pub right_margin: Option<usize>,
+    /// The columns of the tab stops, in ascending order.
+    pub tab_stops: Vec<usize>,
+}
+
+impl Ruler {
+    /// Reads a ruler from the text after ".rr".
+    pub fn parse(ruler: &str) -> Ruler {
+        let mut parsed_ruler = Ruler::default();
+        for (column, character) in ruler.chars().enumerate() {
+            match character.to_ascii_uppercase() {
+                'L' => parsed_ruler.left_margin = column,
+                'P' => parsed_ruler.paragraph_margin = Some(column),
+                'R' => parsed_ruler.right_margin = Some(column),
+                '!' | '#' => parsed_ruler.tab_stops.push(column),
+                _ => {}
+            }
+        }
+        parsed_ruler
+    }
+
+    /// Returns the column a tab in the given column jumps to.
+    pub fn next_tab_stop(&self, column: usize) -> usize {
+        self.tab_stops
+            .iter()
+            .copied()
+            .find(|&tab_stop| tab_stop > column)
+            .unwrap_or((column / DEFAULT_TAB_WIDTH + 1) * DEFAULT_TAB_WIDTH)
+    }
+
+    /// Returns how many tabs it takes to get (at least) to the given column.
+    pub fn tab_level(&self, column: usize) -> usize {
+        let mut level = 0;
+        let mut tab_column = 0;
+        while tab_column < column {
+            tab_column = self.next_tab_stop(tab_column);
+            level += 1;
+        }
+        level
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let ruler = Ruler::parse("----L-P--!----#---R");
+        assert_eq!(ruler.left_margin, 4);
+        assert_eq!(ruler.paragraph_margin, Some(6));
+        assert_eq!(ruler.right_margin, Some(18));
+        assert_eq!(ruler.tab_stops, [9, 14]);
+    }
+
+    #[test]
+    fn parse_lowercase() {
+        let ruler = Ruler::parse("l---!---r");
+        assert_eq!(ruler.left_margin, 0);
+        assert_eq!(ruler.right_margin, Some(8));
+        assert_eq!(ruler.tab_stops, [4]);
+    }
+
+    #[test]
+    fn tab_stops() {
+        let ruler = Ruler::parse("L---!-----!");
+        assert_eq!(ruler.next_tab_stop(0), 4);
+        assert_eq!(ruler.next_tab_stop(4), 10);
+        // Past the last tab stop, tabs are 8 columns wide.
+        assert_eq!(ruler.next_tab_stop(10), 16);
+        assert_eq!(ruler.tab_level(10), 2);
+        assert_eq!(ruler.tab_level(11), 3);
+    }
+}
@@ -0,0 +1,188 @@
+/* ----- CDDL HEADER -----
+ *
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.1 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * https://spdx.org/licenses/CDDL-1.1.html
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ *
+ * ----- CDDL HEADER END -----
+ */
+
+// WordStar 5 and later store everything that is more than a simple toggle in
+// "symmetric sequences" which can be read from both directions:
+//
+//   0x1D, length (16 bits), type, data, length (16 bits), 0x1D
+//
+// The length counts everything after the first length field. The data is
+// binary, so we can't leave these sequences to the parser. Instead, we
+// rewrite the ones we understand into textual tokens ("\x1D<name>:<value>\x1D")
+// and drop the rest.
+
+use std::collections::HashMap;
+
+/// Sequence types we know about.
+const ENDNOTE: u8 = 0x02;
+const ANNOTATION: u8 = 0x03;
+// Inset graphics (WordStar 5 and later): the NUL-terminated file name
+const GRAPHIC: u8 = 0x08;
+const FONT_TABLE: u8 = 0x0e;
+const FONT_CHANGE: u8 = 0x0f;
+const COLOR_CHANGE: u8 = 0x10;
+// WordStar 6 and 7: paragraph styles
+const STYLE_TABLE: u8 = 0x11;
+const STYLE_CHANGE: u8 = 0x12;
+
+/// The colors of WordStar 7, by number.
+const COLORS: [&str; 16] = [
+    "black", "navy", "green", "teal", "maroon", "purple", "olive", "silver", "gray", "blue",
+    "lime", "aqua", "red", "fuchsia", "yellow", "white",
+];
+
+/// Returns the type, the data and the position of the closing 0x1D of the
+/// symmetric sequence starting at `start`, if there is a valid one.
+pub fn parse_sequence(content: &[u8], start: usize) -> Option<(u8, &[u8], usize)> {
+    if content.get(start) != Some(&0x1d) {
+        return None;
+    }
+    let length_bytes = content.get(start + 1..start + 3)?;
+    let length = u16::from_le_bytes([length_bytes[0], length_bytes[1]]) as usize;
+
+    // type + trailing length + 0x1D at least
+    let end = start + 2 + length;
+    if length < 4 || content.get(end) != Some(&0x1d) || &content[end - 2..end] != length_bytes {
+        return None;
+    }
+    Some((content[start + 3], &content[start + 4..end - 2], end))
+}
+
+/// Reads a font (or paragraph style) table: a list of numbers, each followed
+/// by the NUL-terminated name of the font.
+fn read_font_table(data: &[u8], fonts: &mut HashMap<u8, String>) {
+    let mut entries = data.iter();
+    while let Some(&number) = entries.next() {
+        let name: String = entries
+            .by_ref()
+            .take_while(|&&byte| byte != 0)
+            .map(|&byte| char::from(byte))
+            .collect();
+        fonts.insert(number, name);
+    }
+}
+
+/// Reads the text of a note. Notes are plain text for us, so line breaks
+/// become spaces and everything else which is not text is dropped.
+fn read_note_text(data: &[u8]) -> String {
+    let mut text = String::new();
+    for &byte in data {
+        match byte & 0x7f {
+            b'\r' | b'\n' if !text.ends_with(' ') => text.push(' '),
+            0x20..=0x7e => text.push(char::from(byte & 0x7f)),
+            _ => {}
+        }
+    }
+    text.trim().to_string()
+}
+
+/// Writes a token for the parser.
+fn push_token(content: &mut Vec<u8>, name: &str, value: &str) {
+    content.push(0x1d);
+    content.extend_from_slice(name.as_bytes());
+    content.push(b':');
+    // The value must not end the token early.
+    content.extend(value.replace('\u{1d}', "").as_bytes());
+    content.push(0x1d);
+}
+
+/// A sequence which was dropped: where it starts and its type, if it could
+/// be read at all.
+pub type SkippedSequence = (usize, Option<u8>);
+
+/// Rewrites the symmetric sequences in `content` into tokens the grammar
+/// understands. Every 0x1D in the result belongs to such a token, the
+/// sequences we don't know become "unknown" tokens with their type (if any).
+/// Also returns those.
+pub fn rewrite_sequences(content: &[u8]) -> (Vec<u8>, Vec<SkippedSequence>) {
+    let mut fonts: HashMap<u8, String> = HashMap::new();
+    let mut styles: HashMap<u8, String> = HashMap::new();
+    let mut rewritten_content = Vec::with_capacity(content.len());
+    let mut skipped_sequences = Vec::new();
+    let mut position = 0;
+
+    while position < content.len() {
+        match parse_sequence(content, position) {
+            Some((sequence_type, data, end)) => {
+                match sequence_type {
+                    FONT_TABLE => read_font_table(data, &mut fonts),
+                    STYLE_TABLE => read_font_table(data, &mut styles),
+                    STYLE_CHANGE => {
+                        // An unknown style resets to the default style.
+                        let style = data
+                            .first()
+                            .and_then(|number| styles.get(number))
+                            .map(String::as_str)
+                            .unwrap_or_default();
+                        push_token(&mut rewritten_content, "style", style);
+                    }
+                    FONT_CHANGE => {
+                        // An unknown font resets to the default font.
+                        let font = data
+                            .first()
+                            .and_then(|number| fonts.get(number))
+                            .map(String::as_str)
+                            .unwrap_or_default();
+                        push_token(&mut rewritten_content, "font", font);
+                    }
+                    COLOR_CHANGE => {
+                        // An unknown color resets to the default color.
+                        let color = data
+                            .first()
+                            .and_then(|&number| COLORS.get(number as usize))
+                            .copied()
+                            .unwrap_or_default();
+                        push_token(&mut rewritten_content, "color", color);
+                    }
+                    ENDNOTE => push_token(&mut rewritten_content, "endnote", &read_note_text(data)),
+                    GRAPHIC => {
+                        let graphic: String = data
+                            .iter()
+                            .take_while(|&&byte| byte != 0)
+                            .map(|&byte| char::from(byte))
+                            .collect();
+                        push_token(&mut rewritten_content, "graphic", graphic.trim());
+                    }
+                    ANNOTATION => {
+                        push_token(&mut rewritten_content, "annotation", &read_note_text(data))
+                    }
+                    _ => {
+                        push_token(
+                            &mut rewritten_content,
+                            "unknown",
+                            &format!("{:02X}", sequence_type),
+                        );
+                        skipped_sequences.push((position, Some(sequence_type)))
+                    }
+                }
+                position = end + 1;
+            }
+            None => {
+                // A broken sequence. Skip its 0x1D, keep everything else.
+                if content[position] == 0x1d {
+                    push_token(&mut rewritten_content, "unknown", "");
+                    skipped_sequences.push((position, None));
+                } else {
+                    rewritten_content.push(content[position]);
+                }
+                position += 1;
+            }
+        }
+    }
+    (rewritten_content, skipped_sequences)
+}
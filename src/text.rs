@@ -0,0 +1,116 @@
+/* ----- CDDL HEADER -----
+ *
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.1 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * https://spdx.org/licenses/CDDL-1.1.html
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ *
+ * ----- CDDL HEADER END -----
+ */
+
+// Plain text output, e.g. for full-text search: no markup, no front matter,
+// no HTML, one line per paragraph unless it's wrapped.
+
+use crate::document::{plain_text, Block, Document};
+use crate::wrap::wrap_plain_text;
+
+/// Writes the text of `document`, paragraphs wrapped at `width` characters
+/// if there is one.
+pub fn render(document: &Document, width: Option<usize>) -> String {
+    let mut text = blocks(&document.blocks, width);
+    text.push('\n');
+    text
+}
+
+fn blocks(blocks: &[Block], width: Option<usize>) -> String {
+    let rendered: Vec<String> = blocks
+        .iter()
+        .map(|content| block(content, width))
+        .filter(|text| !text.is_empty())
+        .collect();
+    rendered.join("\n\n")
+}
+
+// Indents every line of `text` but the first one by `width`.
+fn hang(text: &str, width: usize) -> String {
+    text.replace('\n', &format!("\n{}", " ".repeat(width)))
+        .lines()
+        .map(str::trim_end)
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+fn block(block: &Block, width: Option<usize>) -> String {
+    match block {
+        Block::Heading { content, .. } | Block::Paragraph(content) => {
+            // Binding spaces are spaces here, but not where to break.
+            let text = plain_text(content);
+            let lines: Vec<String> = text
+                .lines()
+                .map(|line| match width {
+                    Some(width) => wrap_plain_text(line, width),
+                    None => line.to_string(),
+                })
+                .collect();
+            lines.join("\n").replace('\u{a0}', " ")
+        }
+        Block::Code { text, .. } => text.clone(),
+        Block::Quote(content) | Block::Div { content, .. } => blocks(content, width),
+        Block::List {
+            ordered,
+            start,
+            items,
+        } => items
+            .iter()
+            .enumerate()
+            .map(|(index, item)| {
+                let marker = if *ordered {
+                    format!("{}. ", start + index)
+                } else {
+                    "- ".to_string()
+                };
+                format!("{}{}", marker, hang(&blocks(item, width), marker.len()))
+            })
+            .collect::<Vec<String>>()
+            .join("\n"),
+        Block::Table { header, rows } => std::iter::once(header)
+            .chain(rows)
+            .map(|cells| {
+                let cells: Vec<String> = cells.iter().map(|cell| plain_text(cell)).collect();
+                cells.join("\t")
+            })
+            .collect::<Vec<String>>()
+            .join("\n"),
+        // Page breaks and whatever isn't text
+        Block::Template(template) => template.clone(),
+        Block::Rule | Block::Comment(_) | Block::Space(_) => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paragraphs_may_start_with_formatting() {
+        let options = crate::Options::default();
+        let document = crate::convert(
+            "\u{13}Chapter one\u{13} begins here.\r\nSecond para & more.\r\n",
+            &options,
+            &[std::path::PathBuf::from("test.ws")],
+        )
+        .unwrap();
+        assert_eq!(
+            render(&document, None),
+            "Chapter one begins here.\nSecond para & more.\n"
+        );
+    }
+}
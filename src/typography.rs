@@ -0,0 +1,72 @@
+/* ----- CDDL HEADER -----
+ *
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.1 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * https://spdx.org/licenses/CDDL-1.1.html
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ *
+ * ----- CDDL HEADER END -----
+ */
+
+// Typewriter punctuation, the way it looked in print.
+
+/// Whether a quote after `previous` opens something.
+fn opens_quote(previous: Option<char>) -> bool {
+    match previous {
+        None => true,
+        Some(character) => {
+            character.is_whitespace()
+                || "([{<-/\u{2013}\u{2014}\u{201c}\u{2018}".contains(character)
+        }
+    }
+}
+
+/// Replaces straight quotes by curly ones and `...` by an ellipsis.
+/// `previous` is the character before `text`, if there is one.
+pub fn smart_punctuation(text: &str, mut previous: Option<char>) -> String {
+    let mut smart_text = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(character) = rest.chars().next() {
+        let (replacement, length) = match character {
+            '.' if rest.starts_with("...") => ('\u{2026}', 3),
+            '"' if opens_quote(previous) => ('\u{201c}', 1),
+            '"' => ('\u{201d}', 1),
+            // Apostrophes are closing quotes.
+            '\'' if opens_quote(previous) => ('\u{2018}', 1),
+            '\'' => ('\u{2019}', 1),
+            _ => (character, character.len_utf8()),
+        };
+        smart_text.push(replacement);
+        previous = Some(replacement);
+        rest = &rest[length..];
+    }
+    smart_text
+}
+
+/// Replaces `--` by an en dash and `---` by an em dash. Longer runs of
+/// hyphens are lines, they stay.
+pub fn smart_dashes(text: &str) -> String {
+    let mut smart_text = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(position) = rest.find('-') {
+        smart_text.push_str(&rest[..position]);
+        rest = &rest[position..];
+        let hyphens = rest.len() - rest.trim_start_matches('-').len();
+        match hyphens {
+            2 => smart_text.push('\u{2013}'),
+            3 => smart_text.push('\u{2014}'),
+            _ => smart_text.push_str(&rest[..hyphens]),
+        }
+        rest = &rest[hyphens..];
+    }
+    smart_text.push_str(rest);
+    smart_text
+}
@@ -0,0 +1,238 @@
+/* ----- CDDL HEADER -----
+ *
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.1 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * https://spdx.org/licenses/CDDL-1.1.html
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ *
+ * ----- CDDL HEADER END -----
+ */
+
+// Wrapping of (reflowed) paragraphs at the right margin. Markdown doesn't
+// care much where lines break, but a few things must not be torn apart or
+// start a line. Plain text can break at every space.
+
+use crate::document::Inline;
+
+/// Whether a line starting with this word would be something else than
+/// paragraph text in Markdown.
+fn starts_block(word: &str) -> bool {
+    if word.starts_with(['#', '>', '-', '+', '*', '=', '|', '`', '~']) {
+        return true;
+    }
+    // Numbered lists: "1." or "1)"
+    let digits = word.len() - word.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    digits > 0 && word[digits..].starts_with(['.', ')'])
+}
+
+/// Wraps a paragraph (one line of text) so that no line is longer than
+/// `width` characters, as far as there are places to break it.
+pub fn wrap_paragraph(paragraph: &str, width: usize) -> String {
+    wrap(paragraph, width, true)
+}
+
+/// Wraps a line of plain text like `wrap_paragraph()`, without looking out
+/// for Markdown.
+pub fn wrap_plain_text(line: &str, width: usize) -> String {
+    wrap(line, width, false)
+}
+
+fn wrap(paragraph: &str, width: usize, markdown: bool) -> String {
+    let mut wrapped_paragraph = String::with_capacity(paragraph.len());
+    let mut line_length = 0;
+    // The position (in `wrapped_paragraph`) and the line length of the last
+    // space we could break at
+    let mut last_break: Option<(usize, usize)> = None;
+
+    for (position, character) in paragraph.char_indices() {
+        match character {
+            '\n' => {
+                wrapped_paragraph.push(character);
+                line_length = 0;
+                last_break = None;
+                continue;
+            }
+            ' ' if line_length > 0 => {
+                let next_word = paragraph[position..].trim_start_matches(' ');
+                let starts_block = markdown && starts_block(next_word);
+                if !next_word.is_empty() && !next_word.starts_with('\n') && !starts_block {
+                    last_break = Some((wrapped_paragraph.len(), line_length));
+                }
+            }
+            _ => {}
+        }
+        wrapped_paragraph.push(character);
+        line_length += 1;
+
+        if line_length > width {
+            if let Some((break_position, break_length)) = last_break.take() {
+                wrapped_paragraph.replace_range(break_position..break_position + 1, "\n");
+                line_length -= break_length + 1;
+            }
+        }
+    }
+    wrapped_paragraph
+}
+
+/// The text of `content` as it is wrapped: what can't be broken has binding
+/// spaces, and what isn't seen takes no room.
+fn flatten(content: &[Inline], text: &mut String) {
+    for inline in content {
+        match inline {
+            Inline::Text(inline_text) => text.push_str(inline_text),
+            Inline::Bold(content)
+            | Inline::Italic(content)
+            | Inline::Underline(content)
+            | Inline::Strikeout(content)
+            | Inline::Superscript(content)
+            | Inline::Subscript(content)
+            | Inline::Link { content, .. }
+            | Inline::Span { content, .. } => flatten(content, text),
+            Inline::Code(code) | Inline::Template(code) => {
+                text.push_str(&code.replace(' ', "\u{a0}"))
+            }
+            Inline::Image { description, .. } => text.push_str(&description.replace(' ', "\u{a0}")),
+            Inline::LineBreak | Inline::SoftBreak => text.push('\n'),
+            Inline::Anchor(_) | Inline::Note(_) | Inline::Html(_) => {}
+        }
+    }
+}
+
+/// Splits the text of `content` into lines at the (character) positions in
+/// `breaks`, which are spaces. `position` is where `content` starts.
+fn split(content: Vec<Inline>, breaks: &[usize], position: &mut usize) -> Vec<Inline> {
+    let mut split_content = Vec::with_capacity(content.len());
+    for inline in content {
+        let inline = match inline {
+            Inline::Text(text) => {
+                let mut line = String::new();
+                for character in text.chars() {
+                    if breaks.contains(position) {
+                        split_content.push(Inline::Text(std::mem::take(&mut line)));
+                        split_content.push(Inline::SoftBreak);
+                    } else {
+                        line.push(character);
+                    }
+                    *position += 1;
+                }
+                Inline::Text(line)
+            }
+            Inline::Bold(content) => Inline::Bold(split(content, breaks, position)),
+            Inline::Italic(content) => Inline::Italic(split(content, breaks, position)),
+            Inline::Underline(content) => Inline::Underline(split(content, breaks, position)),
+            Inline::Strikeout(content) => Inline::Strikeout(split(content, breaks, position)),
+            Inline::Superscript(content) => Inline::Superscript(split(content, breaks, position)),
+            Inline::Subscript(content) => Inline::Subscript(split(content, breaks, position)),
+            Inline::Link { content, target } => Inline::Link {
+                content: split(content, breaks, position),
+                target,
+            },
+            Inline::Span {
+                attributes,
+                content,
+            } => Inline::Span {
+                attributes,
+                content: split(content, breaks, position),
+            },
+            inline => {
+                let mut text = String::new();
+                flatten(std::slice::from_ref(&inline), &mut text);
+                *position += text.chars().count();
+                inline
+            }
+        };
+        split_content.push(inline);
+    }
+    split_content.retain(|inline| *inline != Inline::Text(String::new()));
+    split_content
+}
+
+/// Wraps the lines of a paragraph like `wrap_paragraph()`, with soft breaks
+/// between the wrapped lines.
+pub fn wrap_inlines(content: &mut Vec<Inline>, width: usize) {
+    let mut text = String::new();
+    flatten(content, &mut text);
+    let breaks: Vec<usize> = text
+        .chars()
+        .zip(wrap_paragraph(&text, width).chars())
+        .enumerate()
+        .filter(|&(_, (character, wrapped))| character != wrapped)
+        .map(|(position, _)| position)
+        .collect();
+    if !breaks.is_empty() {
+        *content = split(std::mem::take(content), &breaks, &mut 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_at_the_last_space() {
+        assert_eq!(
+            wrap_paragraph("one two three four", 9),
+            "one two\nthree\nfour"
+        );
+    }
+
+    #[test]
+    fn short_paragraphs_stay_as_they_are() {
+        assert_eq!(wrap_paragraph("one two", 65), "one two");
+    }
+
+    #[test]
+    fn long_words_are_not_split() {
+        assert_eq!(
+            wrap_paragraph("a supercalifragilistic word", 5),
+            "a\nsupercalifragilistic\nword"
+        );
+    }
+
+    #[test]
+    fn lines_dont_start_blocks() {
+        assert_eq!(
+            wrap_paragraph("one - two # three", 5),
+            "one -\ntwo #\nthree"
+        );
+        assert_eq!(wrap_paragraph("text 1. item", 5), "text 1.\nitem");
+    }
+
+    #[test]
+    fn plain_text_breaks_at_every_space() {
+        assert_eq!(wrap_plain_text("a < b - c # d", 3), "a <\nb -\nc #\nd");
+    }
+
+    #[test]
+    fn inlines_are_wrapped_with_soft_breaks() {
+        let text = |text: &str| Inline::Text(text.to_string());
+        let mut content = vec![
+            text("one "),
+            Inline::Bold(vec![text("two three")]),
+            text(" "),
+            Inline::Code(String::from("a b")),
+            Inline::LineBreak,
+            text("four"),
+        ];
+        wrap_inlines(&mut content, 8);
+        assert_eq!(
+            content,
+            [
+                text("one "),
+                Inline::Bold(vec![text("two"), Inline::SoftBreak, text("three")]),
+                Inline::SoftBreak,
+                Inline::Code(String::from("a b")),
+                Inline::LineBreak,
+                text("four"),
+            ]
+        );
+    }
+}
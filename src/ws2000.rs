@@ -0,0 +1,77 @@
+/* ----- CDDL HEADER -----
+ *
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.1 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * https://spdx.org/licenses/CDDL-1.1.html
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ *
+ * ----- CDDL HEADER END -----
+ */
+
+// WordStar 2000 is a different program with a different file format: there
+// is no 128-byte header, word endings are not flagged with bit 7, and the
+// formatting is kept in codes between the text. Those codes aren't documented
+// well enough for us to map them, so we keep what matters most - the text
+// and its line structure - and drop the codes. What's left goes through the
+// same parser as every other file, so dot commands still work.
+
+use crate::header;
+
+/// Whether `content` looks like a WordStar 2000 file rather than one of
+/// WordStar 3 to 7: it has neither the header of WordStar 5 and later, nor
+/// the flagged word endings of WordStar 3 and 4, but it does have DEL bytes,
+/// which WordStar 3 to 7 never write. A file with none of these is just as
+/// likely to be a header-less WordStar 3 or 4 file, and the WordStar parser
+/// loses nothing on those, so it's left to that.
+pub fn is_ws2000(content: &[u8]) -> bool {
+    !header::has_header(content)
+        && !content.iter().any(|&byte| byte >= 0x80)
+        && content.contains(&0x7f)
+}
+
+/// Prepares the content of a WordStar 2000 file for the parser: text, tabs
+/// and line breaks are kept, formatting codes are dropped.
+pub fn read_text(content: &[u8]) -> Vec<u8> {
+    content
+        .iter()
+        .copied()
+        // Everything after ^Z is padding.
+        .take_while(|&byte| byte != 0x1a)
+        .filter(|&byte| matches!(byte, b'\t' | b'\n' | b'\r' | 0x0c | 0x20..=0x7e))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_text_with_control_codes_is_not_ws2000() {
+        assert!(!is_ws2000(
+            b"This is \x02bold\x02 and \x13under\x13 text.\r\n"
+        ));
+    }
+
+    #[test]
+    fn flagged_word_endings_are_not_ws2000() {
+        assert!(!is_ws2000(b"Thi\xf3 is text.\x7f\r\n"));
+    }
+
+    #[test]
+    fn del_bytes_are_ws2000() {
+        assert!(is_ws2000(b"\x7fB\x7fText\r\n"));
+    }
+
+    #[test]
+    fn read_text_drops_codes_and_padding() {
+        assert_eq!(read_text(b"\x7fB\x02Text\r\n\x1a\x1a"), b"BText\r\n");
+    }
+}
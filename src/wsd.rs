@@ -0,0 +1,115 @@
+/* ----- CDDL HEADER -----
+ *
+ * The contents of this file are subject to the terms of the
+ * Common Development and Distribution License, Version 1.1 only
+ * (the "License").  You may not use this file except in compliance
+ * with the License.
+ *
+ * See the file LICENSE in this distribution for details.
+ * A copy of the CDDL is also available via the Internet at
+ * https://spdx.org/licenses/CDDL-1.1.html
+ *
+ * When distributing Covered Code, include this CDDL HEADER in each
+ * file and include the contents of the LICENSE file from this
+ * distribution.
+ *
+ * ----- CDDL HEADER END -----
+ */
+
+// WordStar for Windows (.wsd) is a binary format of its own, which has little
+// in common with the DOS versions. Its text is stored as plain runs of
+// Windows characters between the binary records, so we pick those runs out
+// (like strings(1) would) and leave the records alone. The names in the
+// records (fonts, printers, styles) are runs too, but they end with a zero
+// byte like C strings do. What's left goes through the same parser as every
+// other file.
+
+use crate::codepage::Decoder;
+use std::path::Path;
+
+/// How many characters a run needs at least to be text, not binary data.
+const MIN_TEXT_RUN: usize = 4;
+
+/// How long the names in the records are at most: 32 characters, with the
+/// zero byte (LF_FACESIZE and CCHDEVICENAME in Windows).
+const MAX_NAME_LENGTH: usize = 31;
+
+/// Whether `path` looks like a WordStar for Windows document.
+pub fn is_wsd(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("wsd"))
+}
+
+/// Whether a run of `length` characters which ends with `end` is the name
+/// of a font, a printer or a style rather than text.
+fn is_name(run: &[u8], length: usize, end: u8) -> bool {
+    end == 0 && length <= MAX_NAME_LENGTH && !run.contains(&b'\n') && !run.contains(&b'\r')
+}
+
+/// Adds a run of text. Runs are separated by formatting in the records,
+/// which was at least a space between words.
+fn push_run(text: &mut Vec<u8>, run: &[u8]) {
+    let separated = |byte: Option<&u8>| byte.is_none_or(u8::is_ascii_whitespace);
+    if !separated(text.last()) && !separated(run.first()) {
+        text.push(b' ');
+    }
+    text.extend_from_slice(run);
+}
+
+/// Prepares the content of a WordStar for Windows file for the parser: runs
+/// of text and line breaks are kept (decoded by `decoder` to UTF-8), binary
+/// data and the names in it are dropped.
+pub fn read_text(content: &[u8], decoder: &Decoder) -> Vec<u8> {
+    let mut text = Vec::with_capacity(content.len());
+    let mut run: Vec<u8> = Vec::new();
+    let mut run_length = 0;
+    for &byte in content {
+        match byte {
+            b'\t' | b'\n' | b'\r' | 0x20..=0x7e => {
+                run.push(byte);
+                run_length += 1;
+            }
+            // Bytes which aren't characters are binary data.
+            0x80..=0xff if decoder.knows(byte) => {
+                run.extend_from_slice(decoder.decode(byte).as_bytes());
+                run_length += 1;
+            }
+            _ => {
+                if run_length >= MIN_TEXT_RUN && !is_name(&run, run_length, byte) {
+                    push_run(&mut text, &run);
+                }
+                run.clear();
+                run_length = 0;
+            }
+        }
+    }
+    if run_length >= MIN_TEXT_RUN {
+        push_run(&mut text, &run);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codepage::{CharacterMap, CodePage, Undecodable};
+
+    // A font, a printer and a style record, then the text with bold
+    // switched on and off in the middle of the second line.
+    const DOCUMENT: &[u8] = b"\x02\x00\x01\x10\x00Times New Roman\x00\x02\x0e\x00HP LaserJet III\x00\
+        \x03\x07\x00Normal\x00\x04\x12\x00Dear Anna,\r\n\x05\x01this is\x05\x02bold\x05\x03 and \xe9lan.\r\n\x1a";
+
+    #[test]
+    fn text_runs_without_the_records() {
+        let charmap = CharacterMap::new();
+        let decoder = Decoder {
+            code_page: CodePage::Windows1252,
+            charmap: &charmap,
+            undecodable: Undecodable::default(),
+        };
+        assert_eq!(
+            String::from_utf8(read_text(DOCUMENT, &decoder)).unwrap(),
+            "Dear Anna,\r\nthis is bold and élan.\r\n"
+        );
+    }
+}